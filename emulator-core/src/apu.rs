@@ -8,6 +8,27 @@ use channel_2::Channel2;
 use channel_3::Channel3;
 use channel_4::Channel4;
 
+use std::error::Error;
+use std::fmt::Display;
+
+use crate::CLOCK_FREQUENCY;
+
+// A sample rate the APU can't produce, as it's above the rate it's clocked at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidSampleRate(pub u32);
+
+impl Display for InvalidSampleRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "sample rate {} is above the apu clock of {}",
+            self.0, CLOCK_FREQUENCY
+        )
+    }
+}
+
+impl Error for InvalidSampleRate {}
+
 #[derive(Clone, Debug)]
 enum WaveDuty {
     Eighth,
@@ -23,7 +44,56 @@ const HALF_WAVE_DUTY_WAVEFORM: [bool; 8] = [true, false, false, false, false, tr
 const THREE_QUARTERS_WAVE_DUTY_WAVEFORM: [bool; 8] =
     [false, true, true, true, true, true, true, false];
 
-#[derive(Clone, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApuChannel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+impl ApuChannel {
+    fn mask(self) -> u8 {
+        match self {
+            ApuChannel::Square1 => 1 << 0,
+            ApuChannel::Square2 => 1 << 1,
+            ApuChannel::Wave => 1 << 2,
+            ApuChannel::Noise => 1 << 3,
+        }
+    }
+}
+
+// One-pole high-pass filter modelling the capacitor on the Game Boy's audio
+// output, which removes the DC offset from the mixed signal.
+#[derive(Clone, Copy, Default)]
+struct HighPassFilter {
+    last_input: f32,
+    last_output: f32,
+}
+
+impl HighPassFilter {
+    const CHARGE_FACTOR: f32 = 0.996;
+
+    fn apply(&mut self, input: f32) -> f32 {
+        let output = input - self.last_input + Self::CHARGE_FACTOR * self.last_output;
+        self.last_input = input;
+        self.last_output = output;
+        output
+    }
+}
+
+// The high-pass filters for both sides of the output. Each consumer of samples
+// keeps its own, as the filters' state depends on when samples are taken.
+#[derive(Clone, Copy, Default)]
+pub struct OutputFilter([HighPassFilter; 2]);
+
+// Averages a [left, right] sample into one.
+pub fn downmix_mono([left, right]: [f32; 2]) -> f32 {
+    (left + right) / 2.0
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Apu {
     pub channel_1: Channel1,
     channel_2: Channel2,
@@ -32,6 +102,49 @@ pub struct Apu {
     channel_control: u8,
     output_terminal_selection: u8,
     powered: bool,
+    // Channels muted by the frontend, as a mask of `ApuChannel::mask` bits. Muted
+    // channels keep running and are only left out of the mix.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) muted_channels: u8,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_master_volume"))]
+    pub(crate) master_volume: f32,
+    // Filter state for `sample`, which also feeds the `take_samples` buffer.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    output_filter: OutputFilter,
+    // Output sample rate requested by the frontend, or 0 if the frontend takes
+    // samples itself. Samples are buffered until `take_samples` is called, up
+    // to a second's worth.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) sample_rate: u32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sample_accumulator: u32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    samples: Vec<[f32; 2]>,
+}
+
+#[cfg(feature = "serde")]
+fn default_master_volume() -> f32 {
+    1.0
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self {
+            channel_1: Default::default(),
+            channel_2: Default::default(),
+            channel_3: Default::default(),
+            channel_4: Default::default(),
+            channel_control: 0,
+            output_terminal_selection: 0,
+            powered: false,
+            muted_channels: 0,
+            master_volume: 1.0,
+            output_filter: Default::default(),
+            sample_rate: 0,
+            sample_accumulator: 0,
+            samples: Vec::new(),
+        }
+    }
 }
 
 impl Apu {
@@ -40,52 +153,204 @@ impl Apu {
         self.channel_2.step();
         self.channel_3.step();
         self.channel_4.step();
+
+        // The APU is stepped at CLOCK_FREQUENCY, so this takes exactly
+        // `sample_rate` samples per emulated second.
+        if self.sample_rate != 0 {
+            self.sample_accumulator += self.sample_rate;
+            if self.sample_accumulator >= CLOCK_FREQUENCY {
+                self.sample_accumulator -= CLOCK_FREQUENCY;
+                let sample = self.sample();
+                // A frontend that stops taking samples loses the newest ones
+                // rather than growing the buffer without limit.
+                if self.samples.len() < self.sample_rate as usize {
+                    self.samples.push(sample);
+                }
+            }
+        }
     }
 
-    pub fn sample(&mut self) -> [f32; 2] {
+    // Makes the APU buffer its own output at the given rate, for collection
+    // with `take_samples`. A rate of 0 turns this off.
+    pub fn set_sample_rate(&mut self, hz: u32) -> Result<(), InvalidSampleRate> {
+        if hz > CLOCK_FREQUENCY {
+            return Err(InvalidSampleRate(hz));
+        }
+        self.sample_rate = hz;
+        self.sample_accumulator = 0;
+        self.samples.clear();
+        Ok(())
+    }
+
+    pub fn take_samples(&mut self) -> Vec<[f32; 2]> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Returns the APU to its power-on state, powered off with every register
+    // cleared. Settings made by the frontend (muting, volume and sample rate)
+    // are kept.
+    pub fn reset(&mut self) {
+        let mut apu = Apu::default();
+        apu.restore_settings_from(self);
+        *self = apu;
+    }
+
+    pub(crate) fn restore_settings_from(&mut self, other: &Apu) {
+        self.muted_channels = other.muted_channels;
+        self.master_volume = other.master_volume;
+        self.sample_rate = other.sample_rate;
+    }
+
+    pub fn set_channel_enabled(&mut self, channel: ApuChannel, enabled: bool) {
+        if enabled {
+            self.muted_channels &= !channel.mask();
+        } else {
+            self.muted_channels |= channel.mask();
+        }
+    }
+
+    // Returns the muted channels, with bits 0 through 3 set for square 1,
+    // square 2, wave and noise respectively.
+    pub fn muted_channels(&self) -> u8 {
+        self.muted_channels
+    }
+
+    // Scales the output of `sample`, where 1.0 leaves it unchanged.
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume;
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master_volume
+    }
+
+    // Whether square 1, square 2, wave and noise are currently playing, as
+    // reported by bits 0 through 3 of NR52.
+    pub fn channel_status(&self) -> [bool; 4] {
+        let nr52 = self.read_nr52();
+        [
+            Self::SOUND_1_ON_OFF_FLAG,
+            Self::SOUND_2_ON_OFF_FLAG,
+            Self::SOUND_3_ON_OFF_FLAG,
+            Self::SOUND_4_ON_OFF_FLAG,
+        ]
+        .map(|flag| (nr52 & flag) != 0)
+    }
+
+    // The master enable, bit 7 of NR52.
+    pub fn power(&self) -> bool {
+        self.powered
+    }
+
+    fn channel_enabled(&self, channel: ApuChannel) -> bool {
+        (self.muted_channels & channel.mask()) == 0
+    }
+
+    // Returns the output of square 1, square 2, wave and noise, in that order,
+    // before panning, master volume or muting are applied. Channels that are
+    // switched off output 0.0.
+    pub fn sample_channels(&mut self) -> [f32; 4] {
+        let enabled = [
+            self.channel_1.get_enabled(),
+            self.channel_2.get_enabled(),
+            self.channel_3.get_enabled(),
+            self.channel_4.get_enabled(),
+        ];
+        let mut outputs = self.dac_outputs();
+        for (output, enabled) in outputs.iter_mut().zip(enabled) {
+            if !enabled {
+                *output = 0.0;
+            }
+        }
+        outputs
+    }
+
+    // The analog output of each channel as it's mixed. A channel that's
+    // switched off still outputs its DAC's idle level of -1.0.
+    fn dac_outputs(&self) -> [f32; 4] {
         fn digital_to_analog(value: u8) -> f32 {
             ((f32::from(value) / 15.0) * 2.0) - 1.0
         }
 
         if self.powered {
-            let channel_1_sample = self.channel_1.sample();
-            let channel_2_sample = self.channel_2.sample();
-            let channel_3_sample = self.channel_3.sample();
-            let channel_4_sample = self.channel_4.sample();
+            [
+                digital_to_analog(self.channel_1.sample()),
+                digital_to_analog(self.channel_2.sample()),
+                digital_to_analog(self.channel_3.sample()),
+                digital_to_analog(self.channel_4.sample()),
+            ]
+        } else {
+            [0.0; 4]
+        }
+    }
+
+    pub fn sample(&mut self) -> [f32; 2] {
+        let mut output_filter = self.output_filter;
+        let sample = self.sample_filtered(&mut output_filter);
+        self.output_filter = output_filter;
+        sample
+    }
+
+    // Like `sample`, but filtered through the caller's own `filter`, for
+    // consumers that take samples alongside the frontend's, such as recorders.
+    pub fn sample_filtered(&mut self, filter: &mut OutputFilter) -> [f32; 2] {
+        let [left_output, right_output] = self.mix();
+        let [left_filter, right_filter] = &mut filter.0;
+
+        [
+            left_filter.apply(left_output) * self.master_volume,
+            right_filter.apply(right_output) * self.master_volume,
+        ]
+    }
+
+    // Like `sample`, but with both sides mixed down to one, for mono output.
+    pub fn sample_mono(&mut self) -> f32 {
+        downmix_mono(self.sample())
+    }
+
+    fn mix(&mut self) -> [f32; 2] {
+        if self.powered {
+            let [channel_1_sample, channel_2_sample, channel_3_sample, channel_4_sample] =
+                self.dac_outputs();
+
+            let channel_1_enabled = self.channel_enabled(ApuChannel::Square1);
+            let channel_2_enabled = self.channel_enabled(ApuChannel::Square2);
+            let channel_3_enabled = self.channel_enabled(ApuChannel::Wave);
+            let channel_4_enabled = self.channel_enabled(ApuChannel::Noise);
 
             let mut left_output = 0.0;
             let mut right_output = 0.0;
 
-            if self.output_sound_1_left() {
-                left_output += digital_to_analog(channel_1_sample);
+            if channel_1_enabled && self.output_sound_1_left() {
+                left_output += channel_1_sample;
             }
 
-            if self.output_sound_2_left() {
-                left_output += digital_to_analog(channel_2_sample);
+            if channel_2_enabled && self.output_sound_2_left() {
+                left_output += channel_2_sample;
             }
 
-            if self.output_sound_3_left() {
-                left_output += digital_to_analog(channel_3_sample);
+            if channel_3_enabled && self.output_sound_3_left() {
+                left_output += channel_3_sample;
             }
 
-            if self.output_sound_4_left() {
-                left_output += digital_to_analog(channel_4_sample);
+            if channel_4_enabled && self.output_sound_4_left() {
+                left_output += channel_4_sample;
             }
 
-            if self.output_sound_1_right() {
-                right_output += digital_to_analog(channel_1_sample);
+            if channel_1_enabled && self.output_sound_1_right() {
+                right_output += channel_1_sample;
             }
 
-            if self.output_sound_2_right() {
-                right_output += digital_to_analog(channel_2_sample);
+            if channel_2_enabled && self.output_sound_2_right() {
+                right_output += channel_2_sample;
             }
 
-            if self.output_sound_3_right() {
-                right_output += digital_to_analog(channel_3_sample);
+            if channel_3_enabled && self.output_sound_3_right() {
+                right_output += channel_3_sample;
             }
 
-            if self.output_sound_4_right() {
-                right_output += digital_to_analog(channel_4_sample);
+            if channel_4_enabled && self.output_sound_4_right() {
+                right_output += channel_4_sample;
             }
 
             left_output *= f32::from(self.get_left_output_volume() + 1);
@@ -111,9 +376,11 @@ impl Apu {
         self.channel_1.read_sound_length_wave_duty() | 0x3F
     }
 
-    pub fn write_nr11(&mut self, value: u8) {
+    pub fn write_nr11(&mut self, value: u8, dmg: bool) {
         if self.powered {
             self.channel_1.write_sound_length_wave_duty(value);
+        } else if dmg {
+            self.channel_1.write_sound_length(value);
         }
     }
 
@@ -157,9 +424,11 @@ impl Apu {
         self.channel_2.read_sound_length_wave_duty() | 0x3F
     }
 
-    pub fn write_nr21(&mut self, value: u8) {
+    pub fn write_nr21(&mut self, value: u8, dmg: bool) {
         if self.powered {
             self.channel_2.write_sound_length_wave_duty(value);
+        } else if dmg {
+            self.channel_2.write_sound_length(value);
         }
     }
 
@@ -207,8 +476,8 @@ impl Apu {
         self.channel_3.read_sound_length() | 0xFF
     }
 
-    pub fn write_nr31(&mut self, value: u8) {
-        if self.powered {
+    pub fn write_nr31(&mut self, value: u8, dmg: bool) {
+        if self.powered || dmg {
             self.channel_3.write_sound_length(value);
         }
     }
@@ -243,13 +512,25 @@ impl Apu {
         }
     }
 
-    pub fn read_wave_pattern_ram(&self, offset: u16) -> u8 {
-        self.channel_3.read_wave_pattern_ram(offset)
+    // `dmg` selects the DMG's stricter access rules while channel 3 plays.
+    pub fn read_wave_pattern_ram(&self, offset: u16, dmg: bool) -> u8 {
+        self.channel_3.read_wave_pattern_ram(offset, dmg)
     }
 
     // Wave pattern ram can be written even APU is powered off.
-    pub fn write_wave_pattern_ram(&mut self, value: u8, offset: u16) {
-        self.channel_3.write_wave_pattern_ram(value, offset);
+    pub fn write_wave_pattern_ram(&mut self, value: u8, offset: u16, dmg: bool) {
+        self.channel_3.write_wave_pattern_ram(value, offset, dmg);
+    }
+
+    // The 32 4-bit samples of channel 3's waveform, two per byte with the
+    // first in the high nibble, as at 0xFF30-0xFF3F. Unlike CPU accesses these
+    // work whether or not the channel is playing, for debuggers and tools.
+    pub fn wave_ram(&self) -> [u8; 16] {
+        self.channel_3.wave_table()
+    }
+
+    pub fn set_wave_ram(&mut self, data: [u8; 16]) {
+        self.channel_3.set_wave_table(data);
     }
 
     pub fn read_nr40(&self) -> u8 {
@@ -262,8 +543,8 @@ impl Apu {
         self.channel_4.read_sound_length_register() | 0xFF
     }
 
-    pub fn write_nr41(&mut self, value: u8) {
-        if self.powered {
+    pub fn write_nr41(&mut self, value: u8, dmg: bool) {
+        if self.powered || dmg {
             self.channel_4.write_sound_length_register(value);
         }
     }
@@ -349,11 +630,20 @@ impl Apu {
         result | 0x70
     }
 
+    // Powering off clears every register but wave RAM, and writes are then
+    // ignored until power comes back, apart from length writes on the DMG.
+    // Writes that don't change the power state do nothing, as the channel
+    // flags are read only.
     pub fn write_nr52(&mut self, value: u8) {
         let powered = (value & Self::ALL_SOUND_ON_OFF_FLAG) == Self::ALL_SOUND_ON_OFF_FLAG;
+        if powered == self.powered {
+            return;
+        }
 
-        self.channel_control = 0;
-        self.output_terminal_selection = 0;
+        if !powered {
+            self.channel_control = 0;
+            self.output_terminal_selection = 0;
+        }
         self.powered = powered;
 
         self.channel_1.set_power(powered);
@@ -427,3 +717,252 @@ impl Apu {
             == OUTPUT_TERMINAL_SELECTION_OUTPUT_SOUND_4_RIGHT_MASK
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_status() {
+        let mut apu = Apu::default();
+        assert!(!apu.power());
+        apu.write_nr52(0x80);
+        assert!(apu.power());
+        assert_eq!(apu.channel_status(), [false; 4]);
+
+        // Trigger channel 1 with a length of 2 and the length counter enabled.
+        // Just after power on the frame sequencer's next step doesn't clock
+        // the length counter, so enabling it takes off an extra tick.
+        apu.write_nr11(0x3E, false);
+        apu.write_nr12(0xF0);
+        apu.write_nr14(0xC0);
+        assert_eq!(apu.channel_status(), [true, false, false, false]);
+        assert_eq!(apu.read_nr52() & 0x0F, 0x01);
+
+        // The length counter is clocked at 256Hz, but where in that period the
+        // channel was triggered depends on the frame sequencer.
+        let mut steps = 0;
+        while apu.channel_status()[0] {
+            apu.step();
+            steps += 1;
+            assert!(steps <= CLOCK_FREQUENCY / 128);
+        }
+        assert_eq!(apu.channel_status(), [false; 4]);
+        assert_eq!(apu.read_nr52() & 0x0F, 0x00);
+    }
+
+    #[test]
+    fn test_power_off() {
+        let mut apu = Apu::default();
+        apu.write_nr52(0x80);
+        apu.write_nr12(0xF3);
+        apu.write_nr50(0x77);
+        // Writing the power bit again changes nothing.
+        apu.write_nr52(0x80);
+        assert_eq!(apu.read_nr50(), 0x77);
+
+        apu.write_nr52(0x00);
+        assert_eq!(apu.read_nr12(), 0x00);
+        assert_eq!(apu.read_nr50(), 0x00);
+        apu.write_nr12(0xF3);
+        apu.write_nr50(0x77);
+        assert_eq!(apu.read_nr12(), 0x00);
+        assert_eq!(apu.read_nr50(), 0x00);
+
+        // Only the DMG keeps a length of 2 written while powered off, which
+        // then runs out within two length clocks, as in test_channel_status.
+        let plays_past_two_length_clocks = |dmg| {
+            let mut apu = Apu::default();
+            apu.write_nr11(0x3E, dmg);
+            apu.write_nr52(0x80);
+            apu.write_nr12(0xF0);
+            apu.write_nr14(0xC0);
+            for _ in 0..CLOCK_FREQUENCY / 128 {
+                apu.step();
+            }
+            apu.channel_status()[0]
+        };
+        assert!(!plays_past_two_length_clocks(true));
+        assert!(plays_past_two_length_clocks(false));
+
+        apu.reset();
+        assert!(!apu.power());
+    }
+
+    #[test]
+    fn test_sample_mono() {
+        // Channel 2 on the left only, so the two sides differ.
+        let mut apu = Apu::default();
+        apu.write_nr52(0x80);
+        apu.write_nr50(0x77);
+        apu.write_nr51(0x20);
+        apu.write_nr21(0x80, false);
+        apu.write_nr22(0xF0);
+        apu.write_nr24(0x87);
+
+        let mut mono_apu = apu.clone();
+        let mut differed = false;
+        for _ in 0..10_000 {
+            apu.step();
+            mono_apu.step();
+            let [left, right] = apu.sample();
+            differed |= left != right;
+            assert_eq!(mono_apu.sample_mono(), (left + right) / 2.0);
+        }
+        assert!(differed);
+    }
+
+    #[test]
+    fn test_wave_ram() {
+        let mut apu = Apu::default();
+        apu.write_nr52(0x80);
+        let ramp: [u8; 16] = std::array::from_fn(|i| (i as u8 * 2) << 4 | (i as u8 * 2 + 1));
+        apu.set_wave_ram(ramp);
+        assert_eq!(apu.wave_ram(), ramp);
+        for offset in 0..16 {
+            assert_eq!(
+                apu.read_wave_pattern_ram(offset, true),
+                ramp[usize::from(offset)]
+            );
+        }
+
+        // Start channel 3. CPU accesses now hit the byte being played on the
+        // CGB and are blocked on the DMG, but the accessors still work.
+        apu.write_nr30(0x80);
+        apu.write_nr34(0x80);
+        assert!(apu.channel_status()[2]);
+        assert_eq!(apu.read_wave_pattern_ram(5, true), 0xFF);
+        apu.write_wave_pattern_ram(0x00, 5, true);
+        assert_eq!(apu.wave_ram(), ramp);
+
+        let playing = apu.read_wave_pattern_ram(5, false);
+        assert!(ramp.contains(&playing));
+        assert_eq!(apu.read_wave_pattern_ram(9, false), playing);
+        apu.set_wave_ram([0x11; 16]);
+        assert_eq!(apu.read_wave_pattern_ram(0, false), 0x11);
+    }
+
+    #[test]
+    fn test_muted_channels_are_silent() {
+        let mut apu = Apu::default();
+        apu.write_nr52(0x80);
+        apu.write_nr50(0x77);
+        apu.write_nr51(0xFF);
+
+        // Start a full volume square wave on channel 2.
+        apu.write_nr21(0x80, false);
+        apu.write_nr22(0xF0);
+        apu.write_nr23(0x00);
+        apu.write_nr24(0x87);
+        for _ in 0..1000 {
+            apu.step();
+        }
+        assert_ne!(apu.sample(), [0.0; 2]);
+
+        for channel in [
+            ApuChannel::Square1,
+            ApuChannel::Square2,
+            ApuChannel::Wave,
+            ApuChannel::Noise,
+        ] {
+            apu.set_channel_enabled(channel, false);
+        }
+        assert_eq!(apu.muted_channels(), 0b1111);
+
+        // The output filter takes a while to settle after the input drops.
+        for _ in 0..2000 {
+            apu.sample();
+        }
+        let [left, right] = apu.sample();
+        assert!(left.abs() < 0.001 && right.abs() < 0.001);
+
+        apu.set_channel_enabled(ApuChannel::Square2, true);
+        assert_ne!(apu.sample(), [0.0; 2]);
+    }
+
+    #[test]
+    fn test_mix_includes_switched_off_channels() {
+        // With every channel panned to both sides at full volume but none
+        // playing, each still adds its DAC's idle level to the mix.
+        let mut apu = Apu::default();
+        apu.write_nr52(0x80);
+        apu.write_nr50(0x77);
+        apu.write_nr51(0xFF);
+        assert_eq!(apu.sample_channels(), [0.0; 4]);
+        assert_eq!(apu.mix(), [-1.0; 2]);
+    }
+
+    #[test]
+    fn test_sample_channels() {
+        let mut apu = Apu::default();
+        apu.write_nr52(0x80);
+
+        // Start a full volume square wave on channel 2.
+        apu.write_nr21(0x80, false);
+        apu.write_nr22(0xF0);
+        apu.write_nr23(0x00);
+        apu.write_nr24(0x87);
+
+        let mut saw_output = false;
+        for _ in 0..1000 {
+            apu.step();
+            let [square_1, square_2, wave, noise] = apu.sample_channels();
+            assert_eq!([square_1, wave, noise], [0.0; 3]);
+            saw_output |= square_2 != 0.0;
+        }
+        assert!(saw_output);
+    }
+
+    #[test]
+    fn test_high_pass_filter_removes_dc_offset() {
+        let mut filter = HighPassFilter::default();
+        assert_eq!(filter.apply(1.0), 1.0);
+
+        let mut last_output = 1.0;
+        for _ in 0..2000 {
+            let output = filter.apply(1.0);
+            assert!(output < last_output);
+            last_output = output;
+        }
+        assert!(last_output < 0.001);
+    }
+
+    #[test]
+    fn test_master_volume() {
+        let mut apu = Apu::default();
+        apu.set_master_volume(0.0);
+        apu.write_nr52(0x80);
+        apu.write_nr50(0x77);
+        apu.write_nr51(0xFF);
+        apu.write_nr21(0x80, false);
+        apu.write_nr22(0xF0);
+        apu.write_nr24(0x87);
+        for _ in 0..1000 {
+            apu.step();
+            assert_eq!(apu.sample(), [0.0; 2]);
+        }
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut apu = Apu::default();
+        assert_eq!(apu.set_sample_rate(48000), Ok(()));
+        for _ in 0..(CLOCK_FREQUENCY / 64) {
+            apu.step();
+        }
+        assert_eq!(apu.take_samples().len(), 48000 / 64);
+        assert!(apu.take_samples().is_empty());
+
+        // Untaken samples are capped at a second's worth.
+        for _ in 0..(CLOCK_FREQUENCY * 2) {
+            apu.step();
+        }
+        assert_eq!(apu.take_samples().len(), 48000);
+
+        assert_eq!(
+            apu.set_sample_rate(CLOCK_FREQUENCY + 1),
+            Err(InvalidSampleRate(CLOCK_FREQUENCY + 1))
+        );
+        assert_eq!(apu.sample_rate, 48000);
+    }
+}
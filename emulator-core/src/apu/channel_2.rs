@@ -15,6 +15,7 @@ const VOLUME_ENVELOPE_CLOCKS: [bool; 8] = [false, false, false, false, false, fa
 const MAXIMUM_AUDIBLE_CHANNEL_FREQUENCY: u16 = 0x7FA;
 
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Channel2 {
     envelope_ticks_left: u8,
     length_counter: u8,
@@ -113,6 +114,14 @@ impl Channel2 {
         self.length_counter = 64 - (value & SOUND_LENGTH_MASK);
     }
 
+    // Loads the length counter without touching the wave duty, as a DMG does
+    // for writes while the APU is powered off.
+    pub fn write_sound_length(&mut self, value: u8) {
+        const SOUND_LENGTH_MASK: u8 = 0b0011_1111;
+
+        self.length_counter = 64 - (value & SOUND_LENGTH_MASK);
+    }
+
     pub fn read_volume_envelope(&self) -> u8 {
         self.volume_envelope
     }
@@ -16,6 +16,7 @@ enum OutputLevel {
 }
 
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Channel3 {
     sound_on_off: u8,
     sound_length: u8,
@@ -149,12 +150,36 @@ impl Channel3 {
         }
     }
 
-    pub fn read_wave_pattern_ram(&self, offset: u16) -> u8 {
-        self.wave_table[usize::from(offset)]
+    // While the channel plays, CPU accesses go to whichever byte it's reading,
+    // whatever the offset. The DMG only lets them through on the exact cycle
+    // the channel reads, which isn't modelled, so there they're blocked.
+    pub fn read_wave_pattern_ram(&self, offset: u16, dmg: bool) -> u8 {
+        match self.wave_pattern_ram_index(offset, dmg) {
+            Some(index) => self.wave_table[index],
+            None => 0xFF,
+        }
+    }
+
+    pub fn write_wave_pattern_ram(&mut self, value: u8, offset: u16, dmg: bool) {
+        if let Some(index) = self.wave_pattern_ram_index(offset, dmg) {
+            self.wave_table[index] = value;
+        }
+    }
+
+    fn wave_pattern_ram_index(&self, offset: u16, dmg: bool) -> Option<usize> {
+        match (self.get_enabled(), dmg) {
+            (false, _) => Some(usize::from(offset)),
+            (true, false) => Some(self.wave_index / 2),
+            (true, true) => None,
+        }
+    }
+
+    pub fn wave_table(&self) -> [u8; 16] {
+        self.wave_table
     }
 
-    pub fn write_wave_pattern_ram(&mut self, value: u8, offset: u16) {
-        self.wave_table[usize::from(offset)] = value;
+    pub fn set_wave_table(&mut self, data: [u8; 16]) {
+        self.wave_table = data;
     }
 }
 
@@ -8,6 +8,7 @@ const LENGTH_COUNTER_CLOCKS: [bool; 8] = [true, false, true, false, true, false,
 const VOLUME_ENVELOPE_CLOCKS: [bool; 8] = [false, false, false, false, false, false, false, true];
 
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Channel4 {
     sound_length: u8,
     length_counter: u8,
@@ -1,15 +1,34 @@
 use crate::{
     apu::Apu,
     cartridge::Cartridge,
+    cheats::{Cheat, GameGenieCode, GameSharkCode},
     joypad::Joypad,
-    ppu::{Ppu, PpuMode, PpuRenderStatus},
-    serial::Serial,
-    timer::Timer,
+    ppu::{OamCorruption, Ppu, PpuMode, PpuRenderStatus},
+    serial::{NoPeer, Serial},
+    sgb::Sgb,
+    timer::{Timer, TimerRegisters},
 };
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+
 const BOOT_ROM: &[u8; 0x900] = include_bytes!("cgb_boot_rom.bin");
 
-#[derive(Clone, Copy, Debug)]
+const DMG_BOOT_ROM_SIZE: usize = 0x100;
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+// Read from unmapped addresses and unused register bits, where nothing drives
+// the data bus and it floats high.
+pub const OPEN_BUS: u8 = 0xFF;
+
+fn default_boot_rom() -> Cow<'static, [u8]> {
+    Cow::Borrowed(BOOT_ROM)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InterruptType {
     VBlank,
     LcdStat,
@@ -18,32 +37,112 @@ pub enum InterruptType {
     Joypad,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl InterruptType {
+    // Every interrupt, indexed by its bit in IE and IF. This is also priority
+    // order, highest first.
+    pub const ALL: [InterruptType; 5] = [
+        InterruptType::VBlank,
+        InterruptType::LcdStat,
+        InterruptType::Timer,
+        InterruptType::Serial,
+        InterruptType::Joypad,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SpeedMode {
     Normal,
     Double,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: WatchKind) -> bool {
+        self == WatchKind::ReadWrite || self == access
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub value: u8,
+    // The access that triggered the watchpoint, either `Read` or `Write`.
+    pub kind: WatchKind,
+    // Address of the instruction that was executing when the access happened.
+    pub pc: u16,
+}
+
+// An access to the I/O registers (0xFF00-0xFF7F), as recorded by the I/O log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoAccess {
+    pub address: u16,
+    pub value: u8,
+    pub is_write: bool,
+    // Address of the instruction that made the access, and the t-cycles
+    // elapsed before it started, as in `Cpu::emulated_time`.
+    pub pc: u16,
+    pub cycle: u64,
+}
+
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bus {
     pub interrupt_enable: u8,
     pub interrupt_flag: u8,
     pub interrupt_master_enable: bool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array_2d"))]
     wram_banks: Box<[[u8; 0x1000]; 8]>,
     wram_bank_index: u8,
-    high_ram: [u8; 0x7F],
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array"))]
+    high_ram: Box<[u8; 0x7F]>,
     pub boot_rom_enabled: bool,
+    // Mapped over 0x0000..=0x00FF (and 0x0200..=0x08FF for CGB boot ROMs) until
+    // the boot ROM is disabled through 0xFF50.
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_boot_rom"))]
+    pub(crate) boot_rom: Cow<'static, [u8]>,
+    // Replaces the mode the boot ROM selects through KEY0 (0xFF4C) from the
+    // cartridge's CGB flag.
+    ppu_mode_override: Option<PpuMode>,
     dma_source: u16,
     dma_destination: u16,
     prepare_speed_switch: bool,
     current_speed: SpeedMode,
     hblank_dma_blocks_left: u8,
     hblank_dma_ongoing: bool,
+    // M-cycles the CPU still has to sit out for VRAM DMA.
+    dma_stall_m_cycles: u16,
+    oam_dma_source: u16,
+    oam_dma_bytes_copied: u16,
+    oam_dma_ongoing: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) watchpoints: HashMap<u16, WatchKind>,
+    // Reads only borrow the bus immutably, so hits are recorded through a RefCell.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    watch_hits: RefCell<Vec<WatchHit>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) watch_pc: u16,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) watch_cycle: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) io_log_enabled: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) io_log: RefCell<Vec<IoAccess>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) cheats: Vec<Cheat>,
     pub cartridge: Cartridge,
     pub timer: Timer,
     pub serial: Serial,
     pub ppu: Ppu,
     pub joypad: Joypad,
+    // Only present for cartridges with the SGB flag set.
+    pub sgb: Option<Sgb>,
     pub apu: Apu,
 }
 
@@ -55,22 +154,111 @@ impl Bus {
             interrupt_master_enable: false,
             wram_banks: Box::new([[0; 0x1000]; 8]),
             wram_bank_index: 1,
-            high_ram: [0; 0x7F],
+            high_ram: Box::new([0; 0x7F]),
             boot_rom_enabled: true,
+            boot_rom: default_boot_rom(),
+            ppu_mode_override: None,
             dma_source: 0,
             dma_destination: 0,
             prepare_speed_switch: false,
             current_speed: SpeedMode::Normal,
             hblank_dma_blocks_left: 0,
             hblank_dma_ongoing: false,
+            dma_stall_m_cycles: 0,
+            oam_dma_source: 0,
+            oam_dma_bytes_copied: 0,
+            oam_dma_ongoing: false,
+            watchpoints: HashMap::new(),
+            watch_hits: RefCell::new(Vec::new()),
+            watch_pc: 0,
+            watch_cycle: 0,
+            io_log_enabled: false,
+            io_log: RefCell::new(Vec::new()),
+            cheats: Vec::new(),
             timer: Default::default(),
             serial: Default::default(),
             ppu: Default::default(),
             joypad: Default::default(),
+            sgb: cartridge.header().sgb_flag.then(Sgb::default),
             apu: Default::default(),
             cartridge,
         }
     }
+
+    // Creates a bus that runs the given DMG (0x100 byte) or CGB (0x900 byte)
+    // boot ROM in place of the built in one.
+    pub fn new_with_boot_rom(
+        cartridge: Cartridge,
+        boot_rom: &[u8],
+    ) -> Result<Self, Box<dyn Error>> {
+        if boot_rom.len() != DMG_BOOT_ROM_SIZE && boot_rom.len() != CGB_BOOT_ROM_SIZE {
+            return Err(format!(
+                "expected boot rom size of 0x{:04X} or 0x{:04X}, but got 0x{:04X}",
+                DMG_BOOT_ROM_SIZE,
+                CGB_BOOT_ROM_SIZE,
+                boot_rom.len()
+            )
+            .into());
+        }
+
+        Ok(Self {
+            boot_rom: Cow::Owned(boot_rom.to_vec()),
+            ..Self::new(cartridge)
+        })
+    }
+
+    // Whether the emulated console is a DMG, which is only the case when running
+    // a DMG boot ROM. A CGB running a DMG cartridge in compatibility mode still
+    // has CGB hardware quirks.
+    pub fn is_dmg_hardware(&self) -> bool {
+        self.boot_rom.len() == DMG_BOOT_ROM_SIZE
+    }
+
+    // Forces the PPU into `mode` once the boot ROM hands off to the cartridge,
+    // regardless of the mode the boot ROM picks. PGB mode isn't emulated, so
+    // it's rejected.
+    pub fn set_ppu_mode_override(&mut self, mode: PpuMode) -> Result<(), Box<dyn Error>> {
+        if mode == PpuMode::Pgb {
+            return Err("PGB mode is not supported".into());
+        }
+        self.ppu_mode_override = Some(mode);
+        Ok(())
+    }
+
+    // Returns every subsystem to its power-on state, with the boot ROM mapped
+    // back in. The cartridge is reset in place so its ROM is not reloaded.
+    pub fn reset(&mut self, keep_cartridge_ram: bool) {
+        self.interrupt_enable = 0;
+        self.interrupt_flag = 0;
+        self.interrupt_master_enable = false;
+        self.wram_banks.iter_mut().for_each(|bank| bank.fill(0));
+        self.wram_bank_index = 1;
+        self.high_ram.fill(0);
+        self.boot_rom_enabled = true;
+        self.dma_source = 0;
+        self.dma_destination = 0;
+        self.prepare_speed_switch = false;
+        self.current_speed = SpeedMode::Normal;
+        self.hblank_dma_blocks_left = 0;
+        self.hblank_dma_ongoing = false;
+        self.dma_stall_m_cycles = 0;
+        self.oam_dma_source = 0;
+        self.oam_dma_bytes_copied = 0;
+        self.oam_dma_ongoing = false;
+        self.timer = Default::default();
+        let link = std::mem::replace(&mut self.serial.link, Box::new(NoPeer));
+        let on_write = self.serial.on_write.take();
+        self.serial = Default::default();
+        self.serial.link = link;
+        self.serial.on_write = on_write;
+        self.ppu.reset();
+        self.joypad = Default::default();
+        if let Some(sgb) = &mut self.sgb {
+            *sgb = Sgb::default();
+        }
+        self.apu.reset();
+        self.cartridge.reset(keep_cartridge_ram);
+    }
 }
 
 impl Bus {
@@ -79,14 +267,20 @@ impl Bus {
             let double_speed_tick = matches!(self.current_speed, SpeedMode::Double) && i % 2 == 1;
 
             let old_ppu_mode = self.ppu.get_stat_mode();
+            let old_frame_count = self.ppu.frame_count();
 
             if !double_speed_tick {
                 self.apu.step();
                 self.ppu.step();
             }
 
+            if !self.cheats.is_empty() && self.ppu.frame_count() != old_frame_count {
+                self.apply_game_shark_codes();
+            }
+
             self.cartridge.step();
             self.timer.step();
+            self.serial.step();
 
             let new_ppu_mode = self.ppu.get_stat_mode();
 
@@ -95,13 +289,7 @@ impl Bus {
                 && self.hblank_dma_blocks_left > 0
                 && self.hblank_dma_ongoing
             {
-                for _ in 0..Self::DMA_BLOCK_SIZE {
-                    let data = self.read_byte_address(self.dma_source);
-                    self.dma_source += 1;
-
-                    self.write_byte_address(data, self.dma_destination);
-                    self.dma_destination += 1;
-                }
+                self.copy_dma_block();
                 self.hblank_dma_blocks_left -= 1;
 
                 if self.hblank_dma_blocks_left == 0 {
@@ -111,41 +299,221 @@ impl Bus {
 
             self.update_interrupt_flag();
         }
+
+        self.step_oam_dma();
     }
 
-    pub fn read_byte_address(&self, address: u16) -> u8 {
+    // OAM DMA copies one byte per m-cycle, starting on the m-cycle after the
+    // write to 0xFF46.
+    fn step_oam_dma(&mut self) {
+        if !self.oam_dma_ongoing {
+            return;
+        }
+
+        let offset = self.oam_dma_bytes_copied;
+        let data = self.peek_byte_address(self.oam_dma_source + offset);
+        self.ppu.write_object_attribute_memory(data, offset);
+
+        self.oam_dma_bytes_copied += 1;
+        if self.oam_dma_bytes_copied == Self::OAM_DMA_LENGTH {
+            self.oam_dma_ongoing = false;
+        }
+    }
+
+    pub fn timer_registers(&self) -> TimerRegisters {
+        self.timer.registers()
+    }
+
+    // Whether an OAM DMA transfer is in progress.
+    pub fn dma_active(&self) -> bool {
+        self.oam_dma_ongoing
+    }
+
+    // While OAM DMA is running it owns the external and video buses, so the CPU
+    // can only reach the I/O registers and high RAM. Anything else reads 0xFF.
+    fn blocked_by_oam_dma(&self, address: u16) -> bool {
+        self.oam_dma_ongoing && address < 0xFF00
+    }
+
+    // The PPU owns VRAM during pixel transfer, and OAM during both OAM search
+    // and pixel transfer, so CPU accesses then read 0xFF and drop writes. The
+    // DMG and CGB are treated the same here. The CGB's differences, such as
+    // when OAM becomes accessible around OAM search and how the mode edges
+    // line up with CPU accesses in double speed, aren't modelled.
+    fn blocked_by_ppu(&self, address: u16) -> bool {
+        if !self.ppu.get_lcd_ppu_enable() {
+            return false;
+        }
+
+        let mode = self.ppu.get_stat_mode();
         match address {
-            0x0000..=0x00FF => {
-                if self.boot_rom_enabled {
-                    BOOT_ROM[usize::from(address)]
-                } else {
-                    self.cartridge.read(address)
+            0x8000..=0x9FFF => mode == PpuRenderStatus::PixelTransfer,
+            0xFE00..=0xFE9F => matches!(
+                mode,
+                PpuRenderStatus::OAMSearch | PpuRenderStatus::PixelTransfer
+            ),
+            _ => false,
+        }
+    }
+
+    // On the DMG, the CPU reading, writing, or incrementing/decrementing a
+    // 16-bit register while it holds an address in 0xFE00-0xFEFF corrupts OAM
+    // if the PPU is searching it at the time.
+    pub(crate) fn trigger_oam_bug(&mut self, address: u16, kind: OamCorruption) {
+        if (0xFE00..=0xFEFF).contains(&address) && self.is_dmg_hardware() {
+            self.ppu.corrupt_oam(kind);
+        }
+    }
+
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.cheats.clear();
+    }
+
+    fn read_rom_with_cheats(&self, address: u16) -> u8 {
+        let value = self.cartridge.read(address);
+        self.cheats
+            .iter()
+            .find_map(|cheat| match *cheat {
+                Cheat::GameGenie(GameGenieCode {
+                    address: cheat_address,
+                    new_value,
+                    compare_value,
+                }) if cheat_address == address
+                    && (compare_value.is_none() || compare_value == Some(value)) =>
+                {
+                    Some(new_value)
                 }
-            }
-            0x0100..=0x01FF => self.cartridge.read(address),
-            0x0200..=0x08FF => {
-                if self.boot_rom_enabled {
-                    BOOT_ROM[usize::from(address)]
-                } else {
-                    self.cartridge.read(address)
+                _ => None,
+            })
+            .unwrap_or(value)
+    }
+
+    fn apply_game_shark_codes(&mut self) {
+        for i in 0..self.cheats.len() {
+            if let Cheat::GameShark(GameSharkCode {
+                bank,
+                address,
+                value,
+            }) = self.cheats[i]
+            {
+                match (bank, address) {
+                    (0x80..=0x87, 0xD000..=0xDFFF) => {
+                        let bank = usize::from(bank & 0x07).max(1);
+                        self.wram_banks[bank][usize::from(address - 0xD000)] = value;
+                    }
+                    _ => self.write_byte_address_unwatched(value, address),
                 }
             }
-            0x900..=0x7FFF => self.cartridge.read(address),
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, address: u16, kind: WatchKind) {
+        self.watchpoints.insert(address, kind);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) {
+        self.watchpoints.remove(&address);
+    }
+
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.watch_hits.take()
+    }
+
+    // Starts recording every CPU access to the I/O registers, for collection
+    // with `take_io_log`. Nothing is recorded until this is called.
+    pub fn enable_io_log(&mut self) {
+        self.io_log_enabled = true;
+    }
+
+    // Stops recording and drops anything not yet taken.
+    pub fn disable_io_log(&mut self) {
+        self.io_log_enabled = false;
+        self.io_log.take();
+    }
+
+    pub fn take_io_log(&mut self) -> Vec<IoAccess> {
+        self.io_log.take()
+    }
+
+    fn log_io_access(&self, address: u16, value: u8, is_write: bool) {
+        if self.io_log_enabled && (0xFF00..=0xFF7F).contains(&address) {
+            self.io_log.borrow_mut().push(IoAccess {
+                address,
+                value,
+                is_write,
+                pc: self.watch_pc,
+                cycle: self.watch_cycle,
+            });
+        }
+    }
+
+    fn check_watchpoint(&self, address: u16, value: u8, access: WatchKind) {
+        if let Some(kind) = self.watchpoints.get(&address) {
+            if kind.matches(access) {
+                self.watch_hits.borrow_mut().push(WatchHit {
+                    address,
+                    value,
+                    kind: access,
+                    pc: self.watch_pc,
+                });
+            }
+        }
+    }
+
+    pub fn read_byte_address(&self, address: u16) -> u8 {
+        let value = if self.blocked_by_oam_dma(address) || self.blocked_by_ppu(address) {
+            OPEN_BUS
+        } else {
+            self.peek_byte_address(address)
+        };
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoint(address, value, WatchKind::Read);
+        }
+        self.log_io_access(address, value, false);
+        value
+    }
+
+    pub fn write_byte_address(&mut self, value: u8, address: u16) {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoint(address, value, WatchKind::Write);
+        }
+        self.log_io_access(address, value, true);
+        if !self.blocked_by_ppu(address) {
+            self.write_byte_address_unwatched(value, address);
+        }
+    }
+
+    // Reads a byte without triggering any watchpoints, for debugger views.
+    pub fn peek_byte_address(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x00FF | 0x0200..=0x08FF
+                if self.boot_rom_enabled && usize::from(address) < self.boot_rom.len() =>
+            {
+                self.boot_rom[usize::from(address)]
+            }
+            0x0000..=0x7FFF if !self.cheats.is_empty() => self.read_rom_with_cheats(address),
+            0x0000..=0x7FFF => self.cartridge.read(address),
             0x8000..=0x9FFF => self.ppu.read_vram(address - 0x8000),
             0xA000..=0xBFFF => self.cartridge.read(address),
             0xC000..=0xCFFF => self.wram_banks[0][usize::from(address - 0xC000)],
             0xD000..=0xDFFF => {
                 self.wram_banks[usize::from(self.wram_bank_index)][usize::from(address - 0xD000)]
             }
-            0xE000..=0xFDFF => self.read_byte_address(address - 0x2000), // echo ram
+            0xE000..=0xFDFF => self.peek_byte_address(address - 0x2000), // echo ram
             0xFE00..=0xFE9F => self.ppu.read_object_attribute_memory(address - 0xFE00),
-            0xFEA0..=0xFEFF => 0x00, // unusable memory, read returns garbage
+            0xFEA0..=0xFEFF => OPEN_BUS, // unusable memory
             0xFF00 => self.joypad.read(),
+            0xFF01 => self.serial.read_byte(),
+            0xFF02 => self.serial.read_control(),
             0xFF04 => self.timer.get_divider_register(),
             0xFF05 => self.timer.get_timer_counter(),
             0xFF06 => self.timer.get_timer_modulo(),
             0xFF07 => self.timer.get_timer_control(),
-            0xFF0F => self.interrupt_flag,
+            0xFF0F => self.interrupt_flag | 0b1110_0000,
             0xFF10 => self.apu.read_nr10(),
             0xFF11 => self.apu.read_nr11(),
             0xFF12 => self.apu.read_nr12(),
@@ -169,8 +537,10 @@ impl Bus {
             0xFF24 => self.apu.read_nr50(),
             0xFF25 => self.apu.read_nr51(),
             0xFF26 => self.apu.read_nr52(),
-            0xFF27..=0xFF2F => 0xFF, // $FF27-$FF2F always read back as $FF
-            0xFF30..=0xFF3F => self.apu.read_wave_pattern_ram(address - 0xFF30),
+            0xFF27..=0xFF2F => OPEN_BUS, // $FF27-$FF2F always read back as $FF
+            0xFF30..=0xFF3F => self
+                .apu
+                .read_wave_pattern_ram(address - 0xFF30, self.is_dmg_hardware()),
             0xFF40 => self.ppu.read_lcd_control(),
             0xFF41 => self.ppu.read_stat(),
             0xFF42 => self.ppu.read_scroll_y(),
@@ -182,7 +552,7 @@ impl Bus {
             0xFF49 => self.ppu.read_obj_palette_1(),
             0xFF4A => self.ppu.read_window_y(),
             0xFF4B => self.ppu.read_window_x(),
-            0xFF4D => self.read_key_1(),
+            0xFF4D => self.read_key_1() | 0b0111_1110,
             0xFF4F => self.ppu.read_vram_bank(),
             0xFF51 => self.read_dma_source_high(),
             0xFF52 => self.read_dma_source_low(),
@@ -193,17 +563,14 @@ impl Bus {
             0xFF69 => self.ppu.read_background_palette_data(),
             0xFF6A => self.ppu.read_obj_palette_index(),
             0xFF6B => self.ppu.read_obj_palette_data(),
-            0xFF70 => self.wram_bank_index,
+            0xFF70 => self.wram_bank_index | 0b1111_1000,
             0xFF80..=0xFFFE => self.high_ram[usize::from(address - 0xFF80)],
             0xFFFF => self.interrupt_enable,
-            _ => {
-                println!("read from 0x{:02X}", address);
-                0
-            }
+            _ => OPEN_BUS,
         }
     }
 
-    pub fn write_byte_address(&mut self, value: u8, address: u16) {
+    pub(crate) fn write_byte_address_unwatched(&mut self, value: u8, address: u16) {
         match address {
             0x0000..=0x7FFF => {
                 self.cartridge.write(value, address);
@@ -215,14 +582,26 @@ impl Bus {
                 self.wram_banks[usize::from(self.wram_bank_index)][usize::from(address - 0xD000)] =
                     value
             }
-            0xE000..=0xFDFF => self.write_byte_address(value, address - 0x2000), // echo ram
+            0xE000..=0xFDFF => self.write_byte_address_unwatched(value, address - 0x2000), // echo ram
             0xFE00..=0xFE9F => self
                 .ppu
                 .write_object_attribute_memory(value, address - 0xFE00),
             0xFEA0..=0xFEFF => {} // unusable memory, write is no-op
-            0xFF00 => self.joypad.write(value),
+            0xFF00 => {
+                self.joypad.write(value);
+                // SGB commands only reach the SGB when running as a DMG.
+                if let Some(sgb) = self
+                    .sgb
+                    .as_mut()
+                    .filter(|_| self.ppu.get_ppu_mode() == PpuMode::Dmg)
+                {
+                    if let Some(palette) = sgb.write(value) {
+                        self.ppu.set_dmg_palette(palette);
+                    }
+                }
+            }
             0xFF01 => self.serial.write_byte(value),
-            0xFF02 => {}
+            0xFF02 => self.serial.write_control(value),
             0xFF04 => self.timer.set_divider_register(value),
             0xFF05 => self.timer.set_timer_counter(value),
             0xFF06 => self.timer.set_timer_modulo(value),
@@ -231,22 +610,22 @@ impl Bus {
                 self.interrupt_flag = value & 0b0001_1111;
             }
             0xFF10 => self.apu.write_nr10(value),
-            0xFF11 => self.apu.write_nr11(value),
+            0xFF11 => self.apu.write_nr11(value, self.is_dmg_hardware()),
             0xFF12 => self.apu.write_nr12(value),
             0xFF13 => self.apu.write_nr13(value),
             0xFF14 => self.apu.write_nr14(value),
             0xFF15 => self.apu.write_nr20(value),
-            0xFF16 => self.apu.write_nr21(value),
+            0xFF16 => self.apu.write_nr21(value, self.is_dmg_hardware()),
             0xFF17 => self.apu.write_nr22(value),
             0xFF18 => self.apu.write_nr23(value),
             0xFF19 => self.apu.write_nr24(value),
             0xFF1A => self.apu.write_nr30(value),
-            0xFF1B => self.apu.write_nr31(value),
+            0xFF1B => self.apu.write_nr31(value, self.is_dmg_hardware()),
             0xFF1C => self.apu.write_nr32(value),
             0xFF1D => self.apu.write_nr33(value),
             0xFF1E => self.apu.write_nr34(value),
             0xFF1F => self.apu.write_nr40(value),
-            0xFF20 => self.apu.write_nr41(value),
+            0xFF20 => self.apu.write_nr41(value, self.is_dmg_hardware()),
             0xFF21 => self.apu.write_nr42(value),
             0xFF22 => self.apu.write_nr43(value),
             0xFF23 => self.apu.write_nr44(value),
@@ -254,19 +633,21 @@ impl Bus {
             0xFF25 => self.apu.write_nr51(value),
             0xFF26 => self.apu.write_nr52(value),
             0xFF27..=0xFF2F => {} // $FF27-$FF2F always read back as $FF
-            0xFF30..=0xFF3F => self.apu.write_wave_pattern_ram(value, address - 0xFF30),
+            0xFF30..=0xFF3F => {
+                self.apu
+                    .write_wave_pattern_ram(value, address - 0xFF30, self.is_dmg_hardware())
+            }
             0xFF40 => self.ppu.write_lcd_control(value),
             0xFF41 => self.ppu.write_stat(value),
             0xFF42 => self.ppu.write_scroll_y(value),
             0xFF43 => self.ppu.write_scroll_x(value),
             0xFF45 => self.ppu.write_lcd_y_compare(value),
             0xFF46 => {
-                // DMA
-                let start_address = u16::from(value) * 0x100;
-                for offset in 0..0xA0 {
-                    let data = self.read_byte_address(start_address + offset);
-                    self.write_byte_address(data, 0xFE00 + offset);
-                }
+                // OAM DMA, carried out over the following m-cycles by step_oam_dma.
+                // Writing again mid-transfer restarts it from the new source.
+                self.oam_dma_source = u16::from(value) * 0x100;
+                self.oam_dma_bytes_copied = 0;
+                self.oam_dma_ongoing = true;
             }
             0xFF47 => self.ppu.write_bg_palette(value),
             0xFF48 => self.ppu.write_obj_palette_0(value),
@@ -274,7 +655,6 @@ impl Bus {
             0xFF4A => self.ppu.write_window_y(value),
             0xFF4B => self.ppu.write_window_x(value),
             0xFF4C => {
-                println!("write of 0x{:02X} to 0xFF4C", value);
                 const CGB_MODE_FLAG_MASK: u8 = 1 << 7;
                 const DMG_MODE_FLAG_MASK: u8 = 1 << 2;
                 const PGB_MODE_FLAG_MASK: u8 = CGB_MODE_FLAG_MASK | DMG_MODE_FLAG_MASK;
@@ -286,13 +666,20 @@ impl Bus {
                     _ => unreachable!("0b{:08b}", value),
                 };
 
-                self.ppu.set_ppu_mode(ppu_mode);
+                self.ppu
+                    .set_ppu_mode(self.ppu_mode_override.unwrap_or(ppu_mode));
             }
             0xFF4D => self.write_key_1(value),
             0xFF4F => self.ppu.write_vram_bank(value),
             0xFF50 => {
-                println!("boot rom disabled");
-                self.boot_rom_enabled = value == 0 // disable boot rom on non-zero write
+                // Any write unmaps the boot rom, and it stays unmapped until reset.
+                self.boot_rom_enabled = false;
+                // The boot rom loads its palettes after selecting the mode, so the
+                // mode is applied again here to let palette overrides replace them.
+                let ppu_mode = self
+                    .ppu_mode_override
+                    .unwrap_or_else(|| self.ppu.get_ppu_mode());
+                self.ppu.set_ppu_mode(ppu_mode);
             }
             0xFF51 => self.write_dma_source_high(value),
             0xFF52 => self.write_dma_source_low(value),
@@ -313,10 +700,22 @@ impl Bus {
                 self.high_ram[usize::from(address - 0xFF80)] = value;
             }
             0xFFFF => self.interrupt_enable = value & 0b0001_1111,
-            _ => eprintln!("write of 0x{:02X} to 0x{:02X}", value, address),
+            _ => {}
         }
     }
 
+    // Returns the 127 bytes of high RAM (0xFF80-0xFFFE). This does not include
+    // the I/O registers at 0xFF00-0xFF7F or the interrupt enable register at
+    // 0xFFFF. Accessing high RAM through this does not step the bus.
+    pub fn hram(&self) -> &[u8] {
+        &self.high_ram[..]
+    }
+
+    // Mutable counterpart of `hram`.
+    pub fn hram_mut(&mut self) -> &mut [u8] {
+        &mut self.high_ram[..]
+    }
+
     fn read_dma_source_high(&self) -> u8 {
         (self.dma_source >> 8) as u8
     }
@@ -362,6 +761,35 @@ impl Bus {
     }
 
     const DMA_BLOCK_SIZE: u16 = 0x10;
+    // Each block takes 8 m-cycles at normal speed, or 16 double speed ones.
+    const DMA_BLOCK_M_CYCLES: u16 = 8;
+
+    // Copies the next 16 bytes of a VRAM DMA, stalling the CPU while it runs.
+    // The destination always lies in VRAM, wrapping around within it.
+    fn copy_dma_block(&mut self) {
+        for _ in 0..Self::DMA_BLOCK_SIZE {
+            let data = self.peek_byte_address(self.dma_source);
+            self.ppu.write_vram(data, self.dma_destination & 0x1FFF);
+            self.dma_source = self.dma_source.wrapping_add(1);
+            self.dma_destination = 0x8000 | (self.dma_destination.wrapping_add(1) & 0x1FFF);
+        }
+
+        self.dma_stall_m_cycles += match self.current_speed {
+            SpeedMode::Normal => Self::DMA_BLOCK_M_CYCLES,
+            SpeedMode::Double => Self::DMA_BLOCK_M_CYCLES * 2,
+        };
+    }
+
+    // Takes one m-cycle of any pending VRAM DMA stall, returning whether the
+    // CPU has to sit it out.
+    pub(crate) fn take_dma_stall_m_cycle(&mut self) -> bool {
+        if self.dma_stall_m_cycles == 0 {
+            return false;
+        }
+        self.dma_stall_m_cycles -= 1;
+        true
+    }
+    const OAM_DMA_LENGTH: u16 = 0xA0;
     fn write_dma_start(&mut self, value: u8) {
         const HBLANK_DMA_MASK: u8 = 0b1000_0000;
         const DMA_LENGTH_MASK: u8 = 0b0111_1111;
@@ -371,17 +799,13 @@ impl Bus {
         if (value & HBLANK_DMA_MASK) == HBLANK_DMA_MASK {
             self.hblank_dma_blocks_left = transfer_blocks;
             self.hblank_dma_ongoing = true;
-        } else if self.hblank_dma_blocks_left > 0 {
+        } else if self.hblank_dma_ongoing {
+            // Clearing bit 7 during an HBlank DMA stops it, leaving the
+            // remaining length readable.
             self.hblank_dma_ongoing = false;
         } else {
             for _ in 0..transfer_blocks {
-                for _ in 0..Self::DMA_BLOCK_SIZE {
-                    let data = self.read_byte_address(self.dma_source);
-                    self.dma_source += 1;
-
-                    self.write_byte_address(data, self.dma_destination);
-                    self.dma_destination += 1;
-                }
+                self.copy_dma_block();
             }
 
             self.hblank_dma_blocks_left = 0;
@@ -442,26 +866,30 @@ impl Bus {
             return None;
         }
 
-        for bit_idx in 0..=4 {
+        for (bit_idx, interrupt) in InterruptType::ALL.into_iter().enumerate() {
             let mask = 1 << bit_idx;
             if ((self.interrupt_enable & mask) != 0) && ((self.interrupt_flag & mask) != 0) {
                 self.interrupt_flag &= !mask;
                 self.interrupt_master_enable = false;
-                let result = match bit_idx {
-                    0 => Some(InterruptType::VBlank),
-                    1 => Some(InterruptType::LcdStat),
-                    2 => Some(InterruptType::Timer),
-                    3 => Some(InterruptType::Serial),
-                    4 => Some(InterruptType::Joypad),
-                    _ => unreachable!(),
-                };
-                return result;
+                return Some(interrupt);
             }
         }
 
         None
     }
 
+    // Returns the interrupts that are both enabled and requested (IE & IF), in
+    // priority order. These are serviced once IME is set.
+    pub fn pending_interrupts(&self) -> Vec<InterruptType> {
+        let pending = self.interrupt_enable & self.interrupt_flag;
+        InterruptType::ALL
+            .into_iter()
+            .enumerate()
+            .filter(|(bit_idx, _)| (pending & (1 << bit_idx)) != 0)
+            .map(|(_, interrupt)| interrupt)
+            .collect()
+    }
+
     fn update_interrupt_flag(&mut self) {
         if self.timer.poll_interrupt() {
             self.interrupt_flag |= Self::TIMER_INTERRUPT_MASK;
@@ -475,6 +903,10 @@ impl Bus {
             self.interrupt_flag |= Self::LCD_STAT_INTERRUPT_MASK;
         }
 
+        if self.serial.poll_interrupt() {
+            self.interrupt_flag |= Self::SERIAL_INTERRUPT_MASK;
+        }
+
         if self.joypad.poll_interrupt() {
             self.interrupt_flag |= Self::JOYPAD_INTERRUPT_MASK;
         }
@@ -505,7 +937,6 @@ impl Bus {
                 SpeedMode::Normal => SpeedMode::Double,
                 SpeedMode::Double => SpeedMode::Normal,
             };
-            println!("switched speed to {:?}", self.current_speed);
             self.prepare_speed_switch = false;
 
             true
@@ -517,4 +948,9 @@ impl Bus {
     pub fn get_current_speed(&self) -> SpeedMode {
         self.current_speed
     }
+
+    // Whether KEY1 has been armed for a speed switch on the next STOP.
+    pub fn get_prepare_speed_switch(&self) -> bool {
+        self.prepare_speed_switch
+    }
 }
@@ -1,14 +1,114 @@
 use instant::Instant;
+use std::cell::Cell;
 use std::convert::TryFrom;
 use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    // Too short to hold the two fixed ROM banks every cartridge has.
+    TooShort,
+    // The cartridge type byte names a mapper that isn't emulated.
+    UnsupportedMbc(u8),
+    BadHeaderChecksum,
+    // The ROM isn't the size its header says, or the header names a ROM or
+    // RAM size that doesn't exist or that the mapper can't address.
+    SizeMismatch,
+    // The cartridge has no RAM to load save data into.
+    NoRam,
+    // Save data isn't the size of the cartridge's RAM.
+    SaveSizeMismatch,
+}
+
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartridgeError::TooShort => f.write_str("rom is too short to be a cartridge"),
+            CartridgeError::UnsupportedMbc(code) => {
+                write!(f, "cartridge type 0x{:02X} is not supported", code)
+            }
+            CartridgeError::BadHeaderChecksum => f.write_str("cartridge header checksum is wrong"),
+            CartridgeError::SizeMismatch => {
+                f.write_str("rom or ram size does not match the cartridge header")
+            }
+            CartridgeError::NoRam => f.write_str("cartridge has no ram"),
+            CartridgeError::SaveSizeMismatch => {
+                f.write_str("save data size does not match the cartridge ram")
+            }
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+// Splits ROM data into 16KiB banks. Cartridge::new has already checked the
+// length against the header, which only names whole numbers of banks.
+fn rom_banks(data: &[u8]) -> Vec<[u8; 0x4000]> {
+    data.chunks_exact(0x4000)
+        .map(|bank| <[u8; 0x4000]>::try_from(bank).unwrap())
+        .collect()
+}
+
+// Bank selects only use as many bits as it takes to address every bank, like
+// the address lines a real cartridge has wired up. Sizes that aren't a power of
+// two wrap around what's left.
+fn bank_index(bank: usize, banks: usize) -> usize {
+    (bank & (banks.next_power_of_two() - 1)) % banks
+}
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cartridge {
     cartridge_type: CartridgeType,
-    title: String,
+    header: CartridgeHeader,
+    // Set when cartridge RAM (or the MBC3 clock) changes, and cleared when the
+    // save data is read. Save data is read through a shared reference, hence the
+    // Cell.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ram_dirty: Cell<bool>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartridgeHeader {
+    pub title: String,
+    // The mapper named by the cartridge type byte.
+    pub mapper_type: MapperType,
+    pub rom_banks: usize,
+    pub ram_banks: usize,
+    pub cgb_flag: CgbFlag,
+    pub sgb_flag: bool,
+    // Whether the cartridge type byte names a battery, meaning cartridge RAM
+    // should be persisted.
+    pub has_battery: bool,
+    pub header_checksum_valid: bool,
+    // The checksum over the whole ROM stored at 0x14E. Nothing checks it, but
+    // it helps tell apart revisions of a game.
+    pub global_checksum: u16,
+    // CRC32 of the whole ROM image, to identify it exactly.
+    pub rom_crc32: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CgbFlag {
+    DmgOnly,
+    CgbEnhanced,
+    CgbOnly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MapperType {
+    NoMbc,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum CartridgeType {
     NoMbc(NoMbc),
     Mbc1(Mbc1),
@@ -29,6 +129,8 @@ impl Cartridge {
     }
 
     pub fn write(&mut self, value: u8, address: u16) {
+        let old_ram_value = matches!(address, 0xA000..=0xBFFF).then(|| self.read(address));
+
         match &mut self.cartridge_type {
             CartridgeType::NoMbc(no_mbc) => no_mbc.write(value, address),
             CartridgeType::Mbc1(mbc_1) => mbc_1.write(value, address),
@@ -36,6 +138,10 @@ impl Cartridge {
             CartridgeType::Mbc3(mbc_3) => mbc_3.write(value, address),
             CartridgeType::Mbc5(mbc_5) => mbc_5.write(value, address),
         }
+
+        if old_ram_value.is_some_and(|old_value| old_value != self.read(address)) {
+            self.ram_dirty.set(true);
+        }
     }
 
     pub fn step(&mut self) {
@@ -49,6 +155,11 @@ impl Cartridge {
     }
 
     pub fn read_save_data(&self) -> Vec<u8> {
+        self.ram_dirty.set(false);
+        self.save_data()
+    }
+
+    fn save_data(&self) -> Vec<u8> {
         match &self.cartridge_type {
             CartridgeType::NoMbc(no_mbc) => no_mbc.read_save_data(),
             CartridgeType::Mbc1(mbc_1) => mbc_1.read_save_data(),
@@ -59,38 +170,173 @@ impl Cartridge {
     }
 
     pub fn write_save_data(&mut self, data: &[u8]) -> bool {
-        match &mut self.cartridge_type {
+        let accepted = match &mut self.cartridge_type {
             CartridgeType::NoMbc(no_mbc) => no_mbc.write_save_data(data),
             CartridgeType::Mbc1(mbc_1) => mbc_1.write_save_data(data),
             CartridgeType::Mbc2(mbc_2) => mbc_2.write_save_data(data),
             CartridgeType::Mbc3(mbc_3) => mbc_3.write_save_data(data),
             CartridgeType::Mbc5(mbc_5) => mbc_5.write_save_data(data),
+        };
+        // Rejected data leaves RAM, and whether it needs saving, as it was.
+        if accepted {
+            self.ram_dirty.set(false);
+        }
+        accepted
+    }
+
+    // Returns exactly the cartridge RAM, `ram_size` bytes with no clock data
+    // appended, as other emulators store .sav files.
+    pub fn export_raw_sram(&self) -> Vec<u8> {
+        let mut data = self.save_data();
+        data.truncate(self.ram_size());
+        data
+    }
+
+    // Loads a plain RAM image, as exported by other emulators, which must be
+    // exactly `ram_size` bytes. The MBC3 clock is untouched. The imported RAM
+    // counts as unsaved, see `is_ram_dirty`.
+    pub fn import_raw_sram(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        if self.ram_size() == 0 {
+            return Err(CartridgeError::NoRam);
+        }
+        if data.len() != self.ram_size() || !self.write_save_data(data) {
+            return Err(CartridgeError::SaveSizeMismatch);
+        }
+        self.ram_dirty.set(true);
+        Ok(())
+    }
+
+    // Returns the bank controller to its power-on state. Cartridge RAM (and the
+    // MBC3 clock) is only cleared when `keep_ram` is false.
+    pub fn reset(&mut self, keep_ram: bool) {
+        match &mut self.cartridge_type {
+            CartridgeType::NoMbc(no_mbc) => no_mbc.reset(keep_ram),
+            CartridgeType::Mbc1(mbc_1) => mbc_1.reset(keep_ram),
+            CartridgeType::Mbc2(mbc_2) => mbc_2.reset(keep_ram),
+            CartridgeType::Mbc3(mbc_3) => mbc_3.reset(keep_ram),
+            CartridgeType::Mbc5(mbc_5) => mbc_5.reset(keep_ram),
         }
     }
 
     pub fn get_title(&self) -> &str {
-        &self.title
+        &self.header.title
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.has_battery
+    }
+
+    // ROM size in bytes, which Cartridge::new has checked against the header.
+    pub fn rom_size(&self) -> usize {
+        self.header.rom_banks * 0x4000
+    }
+
+    // Cartridge RAM size in bytes, including MBC2's built in RAM but not the
+    // MBC3 clock registers.
+    pub fn ram_size(&self) -> usize {
+        match &self.cartridge_type {
+            CartridgeType::Mbc2(_) => 0x200,
+            _ => self.header.ram_banks * 0x2000,
+        }
+    }
+
+    // The boot ROM refuses to start a cartridge with a bad header checksum,
+    // which frontends may want to warn about.
+    pub fn check_header_checksum(&self) -> Result<(), CartridgeError> {
+        if self.header.header_checksum_valid {
+            Ok(())
+        } else {
+            Err(CartridgeError::BadHeaderChecksum)
+        }
+    }
+
+    // Whether cartridge RAM has changed since the save data was last read or
+    // written, i.e. whether there is anything new to save.
+    pub fn is_ram_dirty(&self) -> bool {
+        self.ram_dirty.get()
+    }
+
+    // For a cartridge restored from a save state in place of `previous`. RAM
+    // that still needs saving stays dirty, as does RAM the state replaced.
+    #[cfg(feature = "serde")]
+    pub(crate) fn carry_ram_dirty_from(&self, previous: &Cartridge) {
+        self.ram_dirty
+            .set(previous.is_ram_dirty() || self.export_raw_sram() != previous.export_raw_sram());
+    }
+
+    // Moves the ROM data out of `other` into this cartridge, whose ROM is
+    // missing after being restored from a save state. Returns false, leaving
+    // both cartridges untouched, if `other` is not the same ROM image.
+    #[cfg(feature = "serde")]
+    pub(crate) fn restore_rom_from(&mut self, other: &mut Cartridge) -> bool {
+        if self.header.title != other.header.title
+            || self.header.global_checksum != other.header.global_checksum
+            || self.header.rom_crc32 != other.header.rom_crc32
+        {
+            return false;
+        }
+
+        match (&mut self.cartridge_type, &mut other.cartridge_type) {
+            (CartridgeType::NoMbc(this), CartridgeType::NoMbc(other)) => {
+                std::mem::swap(&mut this.rom, &mut other.rom)
+            }
+            (CartridgeType::Mbc1(this), CartridgeType::Mbc1(other)) => {
+                std::mem::swap(&mut this.rom, &mut other.rom)
+            }
+            (CartridgeType::Mbc2(this), CartridgeType::Mbc2(other)) => {
+                std::mem::swap(&mut this.rom, &mut other.rom)
+            }
+            (CartridgeType::Mbc3(this), CartridgeType::Mbc3(other)) => {
+                std::mem::swap(&mut this.rom, &mut other.rom)
+            }
+            (CartridgeType::Mbc5(this), CartridgeType::Mbc5(other)) => {
+                std::mem::swap(&mut this.rom, &mut other.rom)
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Returns the memory bank controller that was instantiated for this cartridge.
+    pub fn mapper_type(&self) -> MapperType {
+        self.cartridge_type.mapper_type()
+    }
+}
+
+impl CartridgeType {
+    fn mapper_type(&self) -> MapperType {
+        match self {
+            CartridgeType::NoMbc(_) => MapperType::NoMbc,
+            CartridgeType::Mbc1(_) => MapperType::Mbc1,
+            CartridgeType::Mbc2(_) => MapperType::Mbc2,
+            CartridgeType::Mbc3(_) => MapperType::Mbc3,
+            CartridgeType::Mbc5(_) => MapperType::Mbc5,
+        }
     }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct NoMbc {
+    #[cfg_attr(feature = "serde", serde(skip))]
     rom: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::vec_of_arrays"))]
     ram: Vec<[u8; 0x2000]>,
 }
 
 impl NoMbc {
-    fn new(data: &[u8], ram_size: usize) -> Result<Self, Box<dyn Error>> {
+    fn new(data: &[u8], ram_size: usize) -> Result<Self, CartridgeError> {
         let ram = if ram_size == 0x0000 {
             Vec::new()
         } else if ram_size == 0x2000 {
             vec![[0; 0x2000]]
         } else {
-            return Err(format!(
-                "expected ram size of 0x0000 or 0x2000, but got 0x{:04X}",
-                ram_size
-            )
-            .into());
+            return Err(CartridgeError::SizeMismatch);
         };
 
         Ok(Self {
@@ -102,7 +348,11 @@ impl NoMbc {
     fn read(&self, address: u16) -> u8 {
         match address {
             0x0000..=0x7FFF => self.rom[usize::from(address)],
-            0xA000..=0xBFFF => self.ram[0][usize::from(address - 0xA000)],
+            // Without RAM the bus floats high.
+            0xA000..=0xBFFF => self
+                .ram
+                .first()
+                .map_or(0xFF, |bank| bank[usize::from(address - 0xA000)]),
             _ => unreachable!(),
         }
     }
@@ -110,11 +360,21 @@ impl NoMbc {
     fn write(&mut self, value: u8, address: u16) {
         match address {
             0x0000..=0x7FFF => {} // writing to ROM does nothing with no MBC
-            0xA000..=0xBFFF => self.ram[0][usize::from(address - 0xA000)] = value,
+            0xA000..=0xBFFF => {
+                if let Some(bank) = self.ram.first_mut() {
+                    bank[usize::from(address - 0xA000)] = value;
+                }
+            }
             _ => unreachable!(),
         };
     }
 
+    fn reset(&mut self, keep_ram: bool) {
+        if !keep_ram {
+            self.ram.iter_mut().for_each(|bank| bank.fill(0));
+        }
+    }
+
     fn read_save_data(&self) -> Vec<u8> {
         self.ram.iter().flatten().copied().collect()
     }
@@ -134,10 +394,13 @@ impl NoMbc {
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Mbc1 {
+    #[cfg_attr(feature = "serde", serde(skip))]
     rom: Vec<[u8; 0x4000]>,
     rom_banks: usize,
     bank_1: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::vec_of_arrays"))]
     ram: Vec<[u8; 0x2000]>,
     ram_banks: usize,
     bank_2: usize,
@@ -146,19 +409,12 @@ struct Mbc1 {
 }
 
 impl Mbc1 {
-    fn new(data: &[u8], ram_size: usize) -> Result<Self, Box<dyn Error>> {
+    fn new(data: &[u8], ram_size: usize) -> Result<Self, CartridgeError> {
         if ram_size % 0x2000 != 0 {
-            return Err(format!(
-                "expected ram size to be divisible by 0x2000, but got 0x{:04X}",
-                ram_size
-            )
-            .into());
+            return Err(CartridgeError::SizeMismatch);
         }
 
-        let rom: Vec<[u8; 0x4000]> = data
-            .chunks(0x4000)
-            .map(<[u8; 0x4000]>::try_from)
-            .collect::<Result<_, _>>()?;
+        let rom = rom_banks(data);
 
         let ram_banks = (ram_size / 0x2000).max(1);
         let ram: Vec<[u8; 0x2000]> = vec![[0; 0x2000]; ram_banks];
@@ -183,18 +439,19 @@ impl Mbc1 {
                 } else {
                     self.bank_2 << 5
                 };
-                self.rom[bank_number % self.rom_banks][usize::from(address)]
+                self.rom[bank_index(bank_number, self.rom_banks)][usize::from(address)]
             }
             0x4000..=0x7FFF => {
                 let bank_number = self.bank_1 | (self.bank_2 << 5);
-                self.rom[bank_number % self.rom_banks][usize::from(address - 0x4000)]
+                self.rom[bank_index(bank_number, self.rom_banks)][usize::from(address - 0x4000)]
             }
             0xA000..=0xBFFF => {
                 if self.ram_enabled {
                     if self.simple_rom_banking {
                         self.ram[0][usize::from(address - 0xA000)]
                     } else {
-                        self.ram[self.bank_2 % self.ram_banks][usize::from(address - 0xA000)]
+                        self.ram[bank_index(self.bank_2, self.ram_banks)]
+                            [usize::from(address - 0xA000)]
                     }
                 } else {
                     0xFF
@@ -225,8 +482,8 @@ impl Mbc1 {
                     if self.simple_rom_banking {
                         self.ram[0][usize::from(address - 0xA000)] = value;
                     } else {
-                        self.ram[self.bank_2 % self.ram_banks][usize::from(address - 0xA000)] =
-                            value;
+                        self.ram[bank_index(self.bank_2, self.ram_banks)]
+                            [usize::from(address - 0xA000)] = value;
                     }
                 }
             }
@@ -234,6 +491,16 @@ impl Mbc1 {
         }
     }
 
+    fn reset(&mut self, keep_ram: bool) {
+        self.bank_1 = 1;
+        self.bank_2 = 0;
+        self.ram_enabled = false;
+        self.simple_rom_banking = true;
+        if !keep_ram {
+            self.ram.iter_mut().for_each(|bank| bank.fill(0));
+        }
+    }
+
     fn read_save_data(&self) -> Vec<u8> {
         self.ram.iter().flatten().copied().collect()
     }
@@ -253,20 +520,20 @@ impl Mbc1 {
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Mbc2 {
+    #[cfg_attr(feature = "serde", serde(skip))]
     rom: Vec<[u8; 0x4000]>,
     rom_banks: usize,
     rom_bank: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array"))]
     ram: Box<[u8; 0x200]>,
     ram_enabled: bool,
 }
 
 impl Mbc2 {
-    fn new(data: &[u8]) -> Result<Self, Box<dyn Error>> {
-        let rom: Vec<[u8; 0x4000]> = data
-            .chunks(0x4000)
-            .map(<[u8; 0x4000]>::try_from)
-            .collect::<Result<_, _>>()?;
+    fn new(data: &[u8]) -> Result<Self, CartridgeError> {
+        let rom = rom_banks(data);
 
         let ram = Box::new([0; 0x200]);
 
@@ -283,7 +550,7 @@ impl Mbc2 {
         match address {
             0x0000..=0x3FFF => self.rom[0][usize::from(address)],
             0x4000..=0x7FFF => {
-                self.rom[self.rom_bank % self.rom_banks][usize::from(address - 0x4000)]
+                self.rom[bank_index(self.rom_bank, self.rom_banks)][usize::from(address - 0x4000)]
             }
             0xA000..=0xBFFF => self.ram[usize::from(address & 0x1FF)],
             _ => unreachable!(),
@@ -305,7 +572,15 @@ impl Mbc2 {
                 }
             }
             0xA000..=0xBFFF => self.ram[usize::from(address & 0x1FF)] = value,
-            _ => println!("write of 0x{:02X} to 0x{:04X}", value, address),
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self, keep_ram: bool) {
+        self.rom_bank = 1;
+        self.ram_enabled = false;
+        if !keep_ram {
+            self.ram.fill(0);
         }
     }
 
@@ -324,9 +599,12 @@ impl Mbc2 {
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Mbc3 {
+    #[cfg_attr(feature = "serde", serde(skip))]
     rom: Vec<[u8; 0x4000]>,
     rom_bank: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::vec_of_arrays"))]
     ram: Vec<[u8; 0x2000]>,
     ram_bank: usize,
     ram_enabled: bool,
@@ -336,11 +614,13 @@ struct Mbc3 {
     rtc_dl: u8,
     rtc_dh: u8,
     latch_state: RtcLatchState,
+    #[cfg_attr(feature = "serde", serde(skip, default = "Instant::now"))]
     last_step_time: Instant,
     background_secs: f64,
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum RtcLatchState {
     Unlatched,
     PartialLatch,
@@ -348,17 +628,12 @@ enum RtcLatchState {
 }
 
 impl Mbc3 {
-    fn new(data: &[u8], ram_size: usize) -> Result<Self, Box<dyn Error>> {
-        let rom = data
-            .chunks(0x4000)
-            .map(<[u8; 0x4000]>::try_from)
-            .collect::<Result<_, _>>()?;
+    fn new(data: &[u8], ram_size: usize) -> Result<Self, CartridgeError> {
+        let rom = rom_banks(data);
 
         let ram_banks = (ram_size / 0x2000).max(1);
         let ram: Vec<[u8; 0x2000]> = vec![[0; 0x2000]; ram_banks];
 
-        println!("{}", ram.len());
-
         Ok(Self {
             rom,
             rom_bank: 1,
@@ -379,17 +654,23 @@ impl Mbc3 {
     fn read(&self, address: u16) -> u8 {
         match address {
             0x0000..=0x3FFF => self.rom[0][usize::from(address)],
-            0x4000..=0x7FFF => self.rom[self.rom_bank][usize::from(address - 0x4000)],
+            0x4000..=0x7FFF => {
+                self.rom[bank_index(self.rom_bank, self.rom.len())][usize::from(address - 0x4000)]
+            }
             0xA000..=0xBFFF => {
                 if self.ram_enabled {
                     match self.ram_bank {
-                        0x00..=0x03 => self.ram[self.ram_bank][usize::from(address - 0xA000)],
+                        0x00..=0x03 => {
+                            self.ram[bank_index(self.ram_bank, self.ram.len())]
+                                [usize::from(address - 0xA000)]
+                        }
                         0x08 => self.rtc_secs,
                         0x09 => self.rtc_mins,
                         0x0A => self.rtc_hours,
                         0x0B => self.rtc_dl,
                         0x0C => self.rtc_dh,
-                        _ => unreachable!(),
+                        // Nothing is mapped to the other selects.
+                        _ => 0xFF,
                     }
                 } else {
                     0xFF
@@ -402,14 +683,19 @@ impl Mbc3 {
     fn write(&mut self, value: u8, address: u16) {
         match address {
             0x0000..=0x1FFF => self.ram_enabled = value != 0,
-            0x2000..=0x3FFF => self.rom_bank = if value == 0 { 1 } else { usize::from(value) },
+            // 7 bits select one of up to 128 banks, for 2MiB of ROM.
+            0x2000..=0x3FFF => {
+                let bank = usize::from(value & 0x7F);
+                self.rom_bank = if bank == 0 { 1 } else { bank };
+            }
             0x4000..=0x5FFF => self.ram_bank = usize::from(value),
             0x6000..=0x7FFF => self.write_latch(value),
             0xA000..=0xBFFF => {
                 if self.ram_enabled {
                     match self.ram_bank {
                         0x00..=0x03 => {
-                            self.ram[self.ram_bank][usize::from(address - 0xA000)] = value
+                            let ram_bank = bank_index(self.ram_bank, self.ram.len());
+                            self.ram[ram_bank][usize::from(address - 0xA000)] = value
                         }
                         0x08..=0x0C => {
                             match self.ram_bank {
@@ -423,7 +709,7 @@ impl Mbc3 {
 
                             self.background_secs %= 1.0;
                         }
-                        _ => unreachable!(),
+                        _ => {}
                     }
                 }
             }
@@ -522,30 +808,96 @@ impl Mbc3 {
         }
     }
 
+    fn reset(&mut self, keep_ram: bool) {
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.ram_enabled = false;
+        self.latch_state = RtcLatchState::Unlatched;
+        if !keep_ram {
+            self.ram.iter_mut().for_each(|bank| bank.fill(0));
+            self.rtc_secs = 0;
+            self.rtc_mins = 0;
+            self.rtc_hours = 0;
+            self.rtc_dl = 0;
+            self.rtc_dh = 0;
+            self.last_step_time = Instant::now();
+            self.background_secs = 0.0;
+        }
+    }
+
+    // The clock is saved after the RAM in the layout other emulators use: the
+    // current and latched registers as 32-bit little endian values, followed by
+    // a 64-bit unix timestamp of when the save was written.
+    const RTC_SAVE_SIZE: usize = 48;
+
+    fn unix_timestamp() -> u64 {
+        (instant::now() / 1000.0) as u64
+    }
+
     fn read_save_data(&self) -> Vec<u8> {
-        self.ram.iter().flatten().copied().collect()
+        let mut data: Vec<u8> = self.ram.iter().flatten().copied().collect();
+
+        // Latching doesn't keep a separate copy of the registers, so the current
+        // values are saved in place of the latched ones as well.
+        let registers = [
+            self.rtc_secs,
+            self.rtc_mins,
+            self.rtc_hours,
+            self.rtc_dl,
+            self.rtc_dh,
+        ];
+        for register in registers.iter().chain(registers.iter()) {
+            data.extend_from_slice(&u32::from(*register).to_le_bytes());
+        }
+        data.extend_from_slice(&Self::unix_timestamp().to_le_bytes());
+
+        data
     }
 
     fn write_save_data(&mut self, data: &[u8]) -> bool {
-        if data.len() != self.ram.iter().flatten().count() {
-            false
-        } else {
-            self.ram
-                .iter_mut()
-                .flatten()
-                .zip(data)
-                .for_each(|(ram_data, input_data)| *ram_data = *input_data);
-            true
+        let ram_size = self.ram.iter().flatten().count();
+        // Saves without clock data are still accepted, leaving the clock as is.
+        if data.len() != ram_size && data.len() != ram_size + Self::RTC_SAVE_SIZE {
+            return false;
+        }
+
+        let (ram_data, rtc_data) = data.split_at(ram_size);
+        self.ram
+            .iter_mut()
+            .flatten()
+            .zip(ram_data)
+            .for_each(|(ram_data, input_data)| *ram_data = *input_data);
+
+        if !rtc_data.is_empty() {
+            let register = |index: usize| rtc_data[index * 4];
+            self.rtc_secs = register(0) & 0x3F;
+            self.rtc_mins = register(1) & 0x3F;
+            self.rtc_hours = register(2) & 0x1F;
+            self.rtc_dl = register(3);
+            self.rtc_dh = register(4) & 0xC1;
+
+            // Catch the clock up on the time that passed while the game was off.
+            let saved_timestamp = u64::from_le_bytes(rtc_data[40..48].try_into().unwrap());
+            self.background_secs = if self.read_halt() {
+                0.0
+            } else {
+                Self::unix_timestamp().saturating_sub(saved_timestamp) as f64
+            };
         }
+
+        true
     }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Mbc5 {
+    #[cfg_attr(feature = "serde", serde(skip))]
     rom: Vec<[u8; 0x4000]>,
     rom_banks: usize,
     rom_bank_low: usize,
     rom_bank_high: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::vec_of_arrays"))]
     ram: Vec<[u8; 0x2000]>,
     ram_banks: usize,
     ram_bank: usize,
@@ -553,19 +905,12 @@ struct Mbc5 {
 }
 
 impl Mbc5 {
-    fn new(data: &[u8], ram_size: usize) -> Result<Self, Box<dyn Error>> {
+    fn new(data: &[u8], ram_size: usize) -> Result<Self, CartridgeError> {
         if ram_size % 0x2000 != 0 {
-            return Err(format!(
-                "expected ram size to be divisible by 0x2000, but got 0x{:04X}",
-                ram_size
-            )
-            .into());
+            return Err(CartridgeError::SizeMismatch);
         }
 
-        let rom: Vec<[u8; 0x4000]> = data
-            .chunks(0x4000)
-            .map(<[u8; 0x4000]>::try_from)
-            .collect::<Result<_, _>>()?;
+        let rom = rom_banks(data);
 
         let ram_banks = (ram_size / 0x2000).max(1);
         let ram: Vec<[u8; 0x2000]> = vec![[0; 0x2000]; ram_banks];
@@ -587,11 +932,12 @@ impl Mbc5 {
             0x0000..=0x3FFF => self.rom[0][usize::from(address)],
             0x4000..=0x7FFF => {
                 let bank_number = self.rom_bank_low | (self.rom_bank_high << 8);
-                self.rom[bank_number % self.rom_banks][usize::from(address - 0x4000)]
+                self.rom[bank_index(bank_number, self.rom_banks)][usize::from(address - 0x4000)]
             }
             0xA000..=0xBFFF => {
                 if self.ram_enabled {
-                    self.ram[self.ram_bank % self.ram_banks][usize::from(address - 0xA000)]
+                    self.ram[bank_index(self.ram_bank, self.ram_banks)]
+                        [usize::from(address - 0xA000)]
                 } else {
                     0xFF
                 }
@@ -606,16 +952,27 @@ impl Mbc5 {
             0x2000..=0x2FFF => self.rom_bank_low = usize::from(value),
             0x3000..=0x3FFF => self.rom_bank_high = usize::from(value & 0b1),
             0x4000..=0x5FFF => self.ram_bank = usize::from(value & 0x0F),
-            0x6000 => {}
+            0x6000..=0x7FFF => {}
             0xA000..=0xBFFF => {
                 if self.ram_enabled {
-                    self.ram[self.ram_bank % self.ram_banks][usize::from(address - 0xA000)] = value;
+                    self.ram[bank_index(self.ram_bank, self.ram_banks)]
+                        [usize::from(address - 0xA000)] = value;
                 }
             }
             _ => unreachable!("unknown cartridge write: 0x{:04X}", address),
         }
     }
 
+    fn reset(&mut self, keep_ram: bool) {
+        self.rom_bank_low = 1;
+        self.rom_bank_high = 0;
+        self.ram_bank = 0;
+        self.ram_enabled = false;
+        if !keep_ram {
+            self.ram.iter_mut().for_each(|bank| bank.fill(0));
+        }
+    }
+
     fn read_save_data(&self) -> Vec<u8> {
         self.ram.iter().flatten().copied().collect()
     }
@@ -635,8 +992,12 @@ impl Mbc5 {
 }
 
 impl Cartridge {
-    pub fn new(data: &[u8]) -> Result<Self, Box<dyn Error>> {
-        assert!(data.len() >= 0x8000);
+    // Header checksums aren't checked here, since plenty of homebrew and test
+    // ROMs get them wrong. See `check_header_checksum`.
+    pub fn new(data: &[u8]) -> Result<Self, CartridgeError> {
+        if data.len() < 0x8000 {
+            return Err(CartridgeError::TooShort);
+        }
         let expected_rom_size = match data[0x148] {
             0x00 => 0x008000,
             0x01 => 0x010000,
@@ -650,16 +1011,11 @@ impl Cartridge {
             0x52 => 0x120000,
             0x53 => 0x140000,
             0x54 => 0x180000,
-            _ => unimplemented!("ROM size value of 0x{:02X}", data[0x148]),
+            _ => return Err(CartridgeError::SizeMismatch),
         };
 
         if data.len() != expected_rom_size {
-            return Err(format!(
-                "expected rom size of 0x{:06X}, but got 0x{:06X}",
-                expected_rom_size,
-                data.len()
-            )
-            .into());
+            return Err(CartridgeError::SizeMismatch);
         }
 
         let ram_size = match data[0x149] {
@@ -668,35 +1024,314 @@ impl Cartridge {
             0x03 => 0x08000,
             0x04 => 0x20000,
             0x05 => 0x10000,
-            _ => unreachable!(),
+            _ => return Err(CartridgeError::SizeMismatch),
         };
 
-        let title: String = data[0x134..=0x143]
+        let cgb_flag = match data[0x143] {
+            0xC0 => CgbFlag::CgbOnly,
+            0x80 => CgbFlag::CgbEnhanced,
+            _ => CgbFlag::DmgOnly,
+        };
+
+        // Newer cartridges use the last byte of the title for the CGB flag.
+        let title_end = if cgb_flag == CgbFlag::DmgOnly {
+            0x143
+        } else {
+            0x142
+        };
+        let title: String = data[0x134..=title_end]
             .iter()
             .copied()
             .take_while(|val| *val != 0)
             .map(char::from)
             .collect();
 
-        println!("you are playing: {}", title);
+        let header_checksum = data[0x134..=0x14C].iter().fold(0u8, |checksum, val| {
+            checksum.wrapping_sub(*val).wrapping_sub(1)
+        });
 
         let cartridge_type_code = data[0x147];
-        println!("cartridge type code: ${:02X}", cartridge_type_code);
-
         let cartridge_impl = match cartridge_type_code {
-            0x00 => CartridgeType::NoMbc(NoMbc::new(data, ram_size)?),
+            0x00 | 0x08 | 0x09 => CartridgeType::NoMbc(NoMbc::new(data, ram_size)?),
             0x01 | 0x02 | 0x03 => CartridgeType::Mbc1(Mbc1::new(data, ram_size)?),
             0x05 | 0x06 => CartridgeType::Mbc2(Mbc2::new(data)?),
             0x0F | 0x10 | 0x11 | 0x12 | 0x13 => CartridgeType::Mbc3(Mbc3::new(data, ram_size)?),
             0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => {
                 CartridgeType::Mbc5(Mbc5::new(data, ram_size)?)
             }
-            _ => todo!(),
+            _ => return Err(CartridgeError::UnsupportedMbc(cartridge_type_code)),
+        };
+
+        let header = CartridgeHeader {
+            title,
+            mapper_type: cartridge_impl.mapper_type(),
+            rom_banks: expected_rom_size / 0x4000,
+            ram_banks: ram_size / 0x2000,
+            cgb_flag,
+            sgb_flag: data[0x146] == 0x03,
+            has_battery: matches!(
+                data[0x147],
+                0x03 | 0x06 | 0x09 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E
+            ),
+            header_checksum_valid: header_checksum == data[0x14D],
+            global_checksum: u16::from_be_bytes([data[0x14E], data[0x14F]]),
+            rom_crc32: crc32fast::hash(data),
         };
 
         Ok(Cartridge {
             cartridge_type: cartridge_impl,
-            title,
+            header,
+            ram_dirty: Cell::new(false),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds an otherwise empty MBC3+TIMER+RAM+BATTERY cartridge with 32KiB of RAM.
+    fn mbc3_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x03;
+        rom
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Cartridge::new(&[0; 10]).err(),
+            Some(CartridgeError::TooShort)
+        );
+        assert_eq!(
+            Cartridge::new(&[0; 0x8001]).err(),
+            Some(CartridgeError::SizeMismatch)
+        );
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x20;
+        assert_eq!(
+            Cartridge::new(&rom).err(),
+            Some(CartridgeError::UnsupportedMbc(0x20))
+        );
+
+        rom[0x147] = 0x00;
+        assert_eq!(
+            Cartridge::new(&rom).unwrap().check_header_checksum(),
+            Err(CartridgeError::BadHeaderChecksum)
+        );
+    }
+
+    #[test]
+    fn test_bank_selects_wrap() {
+        // MBC1 with 4 ROM banks, each starting with its own number.
+        let mut rom = vec![0; 0x10000];
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        for bank in 0..4 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.rom_size(), 0x10000);
+        assert_eq!(cartridge.ram_size(), 0);
+
+        // Only the low two bits of the bank number are wired up.
+        for (select, bank) in [(0x02, 2), (0x05, 1), (0x1F, 3), (0x04, 0)] {
+            cartridge.write(select, 0x2000);
+            assert_eq!(cartridge.read(0x4000), bank);
+        }
+        cartridge.write(0x03, 0x4000);
+        cartridge.write(0x01, 0x6000);
+        assert_eq!(cartridge.read(0x0000), 0);
+
+        // MBC3 RAM bank selects with nothing mapped read open bus.
+        let mut cartridge = Cartridge::new(&mbc3_rom()).unwrap();
+        assert_eq!(cartridge.ram_size(), 0x8000);
+        cartridge.write(0x0A, 0x0000);
+        cartridge.write(0x05, 0x4000);
+        cartridge.write(0x42, 0xA000);
+        assert_eq!(cartridge.read(0xA000), 0xFF);
+
+        // MBC5 ignores the whole 0x6000-0x7FFF range.
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x19;
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.write(0x01, 0x7000);
+        cartridge.write(0x07, 0x2000);
+        assert_eq!(cartridge.read(0x4000), 0);
+    }
+
+    #[test]
+    fn test_header() {
+        let cartridge = Cartridge::new(include_bytes!("../tests/cgb_acid2.gb")).unwrap();
+        let header = cartridge.header();
+
+        assert_eq!(header.title, "CGB-ACID2");
+        assert_eq!(header.mapper_type, MapperType::NoMbc);
+        assert_eq!(header.rom_banks, 2);
+        assert_eq!(header.ram_banks, 0);
+        assert_eq!(header.cgb_flag, CgbFlag::CgbOnly);
+        assert!(!header.sgb_flag);
+        assert!(header.header_checksum_valid);
+        assert_eq!(cartridge.check_header_checksum(), Ok(()));
+    }
+
+    #[test]
+    fn test_mapper_types() {
+        for (code, mapper_type) in [
+            (0x00, MapperType::NoMbc),
+            (0x08, MapperType::NoMbc),
+            (0x09, MapperType::NoMbc),
+            (0x01, MapperType::Mbc1),
+            (0x02, MapperType::Mbc1),
+            (0x03, MapperType::Mbc1),
+            (0x05, MapperType::Mbc2),
+            (0x06, MapperType::Mbc2),
+            (0x0F, MapperType::Mbc3),
+            (0x10, MapperType::Mbc3),
+            (0x11, MapperType::Mbc3),
+            (0x12, MapperType::Mbc3),
+            (0x13, MapperType::Mbc3),
+            (0x19, MapperType::Mbc5),
+            (0x1A, MapperType::Mbc5),
+            (0x1B, MapperType::Mbc5),
+            (0x1C, MapperType::Mbc5),
+            (0x1D, MapperType::Mbc5),
+            (0x1E, MapperType::Mbc5),
+        ] {
+            let mut rom = vec![0; 0x8000];
+            rom[0x147] = code;
+            if matches!(code, 0x08 | 0x09) {
+                rom[0x149] = 0x02;
+            }
+            let cartridge = Cartridge::new(&rom).unwrap();
+            assert_eq!(cartridge.mapper_type(), mapper_type, "code {:02x}", code);
+            assert_eq!(cartridge.header().mapper_type, mapper_type);
+        }
+
+        // ROM+RAM cartridges map their RAM straight in.
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x09;
+        rom[0x149] = 0x02;
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert!(cartridge.has_battery());
+        cartridge.write(0x42, 0xA123);
+        assert_eq!(cartridge.read(0xA123), 0x42);
+    }
+
+    #[test]
+    fn test_mbc3_save_data_includes_clock() {
+        let mut cartridge = Cartridge::new(&mbc3_rom()).unwrap();
+        cartridge.write(0x0A, 0x0000); // enable ram and rtc registers
+        cartridge.write(0x0C, 0x4000);
+        cartridge.write(0x40, 0xA000); // halt the clock so it can't tick mid-test
+        cartridge.write(0x09, 0x4000);
+        cartridge.write(42, 0xA000);
+        cartridge.write(0x01, 0x4000);
+        cartridge.write(0x5A, 0xA123);
+
+        let save_data = cartridge.read_save_data();
+        assert_eq!(save_data.len(), 0x8000 + 48);
+
+        let mut restored = Cartridge::new(&mbc3_rom()).unwrap();
+        assert!(restored.write_save_data(&save_data));
+        restored.write(0x0A, 0x0000);
+        restored.write(0x09, 0x4000);
+        assert_eq!(restored.read(0xA000), 42);
+        restored.write(0x01, 0x4000);
+        assert_eq!(restored.read(0xA123), 0x5A);
+
+        // Saves from before the clock was stored are still accepted.
+        assert!(restored.write_save_data(&save_data[..0x8000]));
+    }
+
+    #[test]
+    fn test_save_data_round_trip() {
+        // MBC1+RAM+BATTERY with 8KiB of RAM.
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.write(0x0A, 0x0000);
+        cartridge.write(0x12, 0xA000);
+        cartridge.write(0x34, 0xBFFF);
+        let save_data = cartridge.read_save_data();
+        assert_eq!(save_data.len(), 0x2000);
+        assert_eq!((save_data[0], save_data[0x1FFF]), (0x12, 0x34));
+
+        let mut restored = Cartridge::new(&rom).unwrap();
+        assert!(restored.write_save_data(&save_data));
+        assert_eq!(restored.read_save_data(), save_data);
+
+        // Saves of the wrong size are rejected without touching RAM.
+        assert!(!restored.write_save_data(&save_data[..0x1000]));
+        assert!(!restored.write_save_data(&[]));
+        assert_eq!(restored.read_save_data(), save_data);
+    }
+
+    #[test]
+    fn test_raw_sram() {
+        let mut cartridge = Cartridge::new(&mbc3_rom()).unwrap();
+        let sram: Vec<u8> = (0..0x8000).map(|i| (i % 251) as u8).collect();
+        assert_eq!(cartridge.import_raw_sram(&sram), Ok(()));
+        assert!(cartridge.is_ram_dirty());
+        assert_eq!(cartridge.export_raw_sram(), sram);
+        cartridge.write(0x0A, 0x0000);
+        cartridge.write(0x03, 0x4000);
+        assert_eq!(cartridge.read(0xA000), sram[0x6000]);
+
+        // Exports leave out the clock that read_save_data appends.
+        assert_eq!(cartridge.read_save_data().len(), 0x8000 + 48);
+
+        // Images of another size are rejected without touching RAM.
+        for size in [0, 0x10, 0x8000 + 48, 0x9000] {
+            assert_eq!(
+                cartridge.import_raw_sram(&vec![0x11; size]),
+                Err(CartridgeError::SaveSizeMismatch)
+            );
+        }
+        assert_eq!(cartridge.export_raw_sram(), sram);
+        assert!(!cartridge.is_ram_dirty());
+
+        // MBC2's built in RAM is 512 bytes.
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x06;
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.import_raw_sram(&[0x0F; 0x200]), Ok(()));
+        assert_eq!(cartridge.export_raw_sram(), vec![0x0F; 0x200]);
+
+        let mut cartridge = Cartridge::new(include_bytes!("../tests/cgb_acid2.gb")).unwrap();
+        assert!(cartridge.export_raw_sram().is_empty());
+        assert_eq!(cartridge.import_raw_sram(&[]), Err(CartridgeError::NoRam));
+    }
+
+    #[test]
+    fn test_ram_dirty() {
+        let mut cartridge = Cartridge::new(&mbc3_rom()).unwrap();
+        assert!(cartridge.has_battery());
+        assert!(!cartridge.is_ram_dirty());
+
+        // Writes while RAM is disabled are ignored.
+        cartridge.write(0x5A, 0xA000);
+        assert!(!cartridge.is_ram_dirty());
+
+        cartridge.write(0x0A, 0x0000);
+        cartridge.write(0x5A, 0xA000);
+        assert!(cartridge.is_ram_dirty());
+
+        cartridge.read_save_data();
+        assert!(!cartridge.is_ram_dirty());
+
+        // Loading a save only counts as saved if the save is accepted.
+        cartridge.write(0x5B, 0xA000);
+        assert!(!cartridge.write_save_data(&[0; 0x10]));
+        assert!(cartridge.is_ram_dirty());
+        assert!(cartridge.write_save_data(&[0; 0x8000]));
+        assert!(!cartridge.is_ram_dirty());
+
+        let cartridge = Cartridge::new(include_bytes!("../tests/cgb_acid2.gb")).unwrap();
+        assert!(!cartridge.has_battery());
+    }
+}
@@ -0,0 +1,158 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// Patches a single ROM byte. When a compare value is given, the patch only
+// applies while the byte being replaced matches it, so that codes only affect
+// the intended ROM bank.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameGenieCode {
+    pub address: u16,
+    pub new_value: u8,
+    pub compare_value: Option<u8>,
+}
+
+// Overwrites a byte of RAM once per frame. A bank of 0x80 through 0x87 selects
+// a specific WRAM bank for addresses in 0xD000..=0xDFFF, otherwise the byte
+// is written through whatever is currently mapped at the address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameSharkCode {
+    pub bank: u8,
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cheat {
+    GameGenie(GameGenieCode),
+    GameShark(GameSharkCode),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseCheatError;
+
+impl fmt::Display for ParseCheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid cheat code")
+    }
+}
+
+impl Error for ParseCheatError {}
+
+fn parse_hex_digits(digits: &str) -> Result<Vec<u8>, ParseCheatError> {
+    digits
+        .chars()
+        .map(|c| {
+            c.to_digit(16)
+                .map(|digit| digit as u8)
+                .ok_or(ParseCheatError)
+        })
+        .collect()
+}
+
+// Parses codes of the form ABC-DEF-GHI, or ABC-DEF for codes without a compare
+// value. AB is the new value and the address is (F ^ 0xF)CDE. The compare
+// value is GI rotated right by 2 and XORed with 0xBA; H is unused.
+impl FromStr for GameGenieCode {
+    type Err = ParseCheatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let groups: Vec<&str> = s.trim().split('-').collect();
+        if !matches!(groups.len(), 2 | 3) || groups.iter().any(|group| group.len() != 3) {
+            return Err(ParseCheatError);
+        }
+        let digits = parse_hex_digits(&groups.concat())?;
+
+        let new_value = (digits[0] << 4) | digits[1];
+        let address = (u16::from(digits[5] ^ 0xF) << 12)
+            | (u16::from(digits[2]) << 8)
+            | (u16::from(digits[3]) << 4)
+            | u16::from(digits[4]);
+        if address > 0x7FFF {
+            return Err(ParseCheatError);
+        }
+
+        let compare_value =
+            (digits.len() == 9).then(|| ((digits[6] << 4) | digits[8]).rotate_right(2) ^ 0xBA);
+
+        Ok(Self {
+            address,
+            new_value,
+            compare_value,
+        })
+    }
+}
+
+// Parses codes of the form BBVVLLHH: bank, value, then the address low byte
+// followed by the high byte.
+impl FromStr for GameSharkCode {
+    type Err = ParseCheatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.len() != 8 {
+            return Err(ParseCheatError);
+        }
+        let digits = parse_hex_digits(s)?;
+        let byte = |i: usize| (digits[i * 2] << 4) | digits[i * 2 + 1];
+
+        Ok(Self {
+            bank: byte(0),
+            value: byte(1),
+            address: u16::from_le_bytes([byte(2), byte(3)]),
+        })
+    }
+}
+
+impl FromStr for Cheat {
+    type Err = ParseCheatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('-') {
+            s.parse().map(Cheat::GameGenie)
+        } else {
+            s.parse().map(Cheat::GameShark)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_game_genie() {
+        assert_eq!(
+            "771-23B-E0A".parse(),
+            Ok(Cheat::GameGenie(GameGenieCode {
+                address: 0x4123,
+                new_value: 0x77,
+                compare_value: Some(0x00),
+            }))
+        );
+        assert_eq!(
+            "00A-17B".parse(),
+            Ok(Cheat::GameGenie(GameGenieCode {
+                address: 0x4A17,
+                new_value: 0x00,
+                compare_value: None,
+            }))
+        );
+        assert_eq!("771-237".parse::<GameGenieCode>(), Err(ParseCheatError));
+        assert_eq!("771-23B-E0".parse::<GameGenieCode>(), Err(ParseCheatError));
+        assert_eq!("77X-23B-E0A".parse::<GameGenieCode>(), Err(ParseCheatError));
+    }
+
+    #[test]
+    fn test_parse_game_shark() {
+        assert_eq!(
+            "01420DC0".parse(),
+            Ok(Cheat::GameShark(GameSharkCode {
+                bank: 0x01,
+                address: 0xC00D,
+                value: 0x42,
+            }))
+        );
+        assert_eq!("01420DC".parse::<GameSharkCode>(), Err(ParseCheatError));
+    }
+}
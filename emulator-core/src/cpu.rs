@@ -1,12 +1,28 @@
+use std::collections::HashSet;
+use std::error::Error;
 use std::fmt::{Debug, Display};
+use std::time::Duration;
 
 use crate::{
-    bus::{Bus, InterruptType, SpeedMode},
+    bus::{Bus, InterruptType, SpeedMode, WatchHit, WatchKind},
     cartridge::Cartridge,
-    joypad::Button,
+    cheats::Cheat,
+    doctor,
+    joypad::{Button, JoypadState},
+    ppu::{OamCorruption, PpuMode, DOTS_PER_FRAME},
+    CLOCK_FREQUENCY,
 };
 
+#[cfg(feature = "serde")]
+use crate::{
+    movie::{Movie, MovieMode},
+    state::{self, StateError, STATE_VERSION},
+};
+
+// All memory and frame buffers live behind a Box, so a Cpu is only about 1KiB
+// and cheap to move around (e.g. into a wasm-bindgen wrapper).
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
     af: u16,
     bc: u16,
@@ -17,7 +33,132 @@ pub struct Cpu {
     pub bus: Bus,
     halted: bool,
     stopped: bool,
+    // Set by an illegal opcode. The CPU locks up until reset, though the rest
+    // of the system keeps running.
+    hung: bool,
+    // Set by EI. Interrupts are only enabled once the instruction after EI has
+    // executed.
+    ime_enable_pending: bool,
     m_cycles_completed: u8,
+    last_interrupt: Option<InterruptType>,
+    // The instruction the last step fetched and executed, and the interrupt
+    // it dispatched, for `step_debug`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_instruction: Option<Instruction>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    last_dispatched: Option<InterruptType>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    profile: Option<Box<InstructionProfile>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stack_guard: Option<(u16, u16)>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stack_guard_violation: Option<StackGuardViolation>,
+    t_cycles_elapsed: u64,
+    instructions_executed: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    breakpoints: HashSet<u16>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    tracer: Tracer,
+    // The buttons the host is holding, before any opposing directions are
+    // filtered out.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    held_buttons: JoypadState,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    block_opposing_directions: bool,
+    #[cfg(feature = "serde")]
+    #[serde(skip)]
+    movie: Option<MovieMode>,
+    // The frame the current movie input was applied on.
+    #[cfg(feature = "serde")]
+    #[serde(skip)]
+    movie_frame: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+// Reported to the tracer for every executed instruction, with the state from
+// just before it ran.
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEvent {
+    pub pc: u16,
+    pub instruction: Instruction,
+    pub registers: Registers,
+    // T-cycles elapsed since power on, as in `emulated_time`.
+    pub cycles: u64,
+}
+
+// What a single `step_debug` did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepInfo {
+    // pc before the step.
+    pub pc: u16,
+    // The executed instruction, or None if the step was spent halted, stalled
+    // or dispatching an interrupt.
+    pub instruction: Option<Instruction>,
+    pub interrupt: Option<InterruptType>,
+    pub t_cycles: u8,
+}
+
+// Closures can't be cloned, so a cloned Cpu starts out without a tracer.
+#[derive(Default)]
+struct Tracer(Option<Box<dyn FnMut(TraceEvent)>>);
+
+impl Clone for Tracer {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStop {
+    // Execution reached the breakpoint at this address.
+    Breakpoint(u16),
+    CyclesExhausted,
+    // The CPU is halted, stopped or hung, and nothing can wake it without host
+    // input.
+    Halted,
+}
+
+// Emulation counters since power on or the last reset, for benchmarks to
+// report rates from. Interrupt dispatch and halted cycles aren't counted as
+// instructions, but their t-cycles are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub instructions: u64,
+    pub t_cycles: u64,
+    pub frames: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackGuardViolation {
+    // Address of the instruction (or interrupt dispatch) that moved sp out of bounds.
+    pub pc: u16,
+    pub sp: u16,
+}
+
+// Per-opcode execution counts. CB-prefixed opcodes are stored at an offset of
+// 0x100 from their postfix byte.
+#[derive(Clone)]
+struct InstructionProfile {
+    counts: [u64; 0x200],
+    examples: [Option<Instruction>; 0x200],
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProfileEntry {
+    // The executed opcode. CB-prefixed opcodes are reported as 0xCBxx.
+    pub opcode: u16,
+    pub count: u64,
+    // The most recently executed instruction decoded from this opcode.
+    pub example: Instruction,
 }
 
 impl Debug for Cpu {
@@ -30,16 +171,17 @@ impl Debug for Cpu {
             .field("sp", &self.sp)
             .field("pc", &self.pc)
             .field("halted", &self.halted)
+            .field("last_interrupt", &self.last_interrupt)
             .finish_non_exhaustive()
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Instruction {
     pub instruction_type: InstructionType,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InstructionType {
     AddByte {
         source: AddressingModeByte,
@@ -81,6 +223,11 @@ pub enum InstructionType {
     Di,
     Ei,
     Halt,
+    // One of the opcodes that the hardware doesn't define. Executing it locks up
+    // a real console.
+    Illegal {
+        opcode: u8,
+    },
     IncByte {
         target: AddressingModeByte,
     },
@@ -210,6 +357,7 @@ impl Display for Instruction {
             InstructionType::Di => f.write_str("di"),
             InstructionType::Ei => f.write_str("ei"),
             InstructionType::Halt => f.write_str("halt"),
+            InstructionType::Illegal { opcode } => write!(f, "illegal ${:02x}", opcode),
             InstructionType::IncByte { target } => write!(f, "inc {}", target),
             InstructionType::IncWord { target } => write!(f, "inc {}", target),
             InstructionType::Jp {
@@ -262,7 +410,7 @@ impl Display for Instruction {
                 source,
             } => write!(f, "sbc {}, {}", destination, source),
             InstructionType::Scf => f.write_str("scf"),
-            InstructionType::Set { bit, target } => write!(f, "{}, {}", bit, target),
+            InstructionType::Set { bit, target } => write!(f, "set {}, {}", bit, target),
             InstructionType::Sla { target } => write!(f, "sla {}", target),
             InstructionType::Sra { target } => write!(f, "sra {}", target),
             InstructionType::Srl { target } => write!(f, "srl {}", target),
@@ -296,7 +444,7 @@ impl Display for AddressingModeByte {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BranchConditionType {
     NotZero,
     NotCarry,
@@ -329,6 +477,16 @@ pub enum RegisterByte {
 }
 
 #[derive(Clone, Copy, Debug)]
+pub enum RegisterWord {
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Pc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AddressingModeByte {
     Accumulator,
     B,
@@ -347,7 +505,7 @@ pub enum AddressingModeByte {
     LiteralIndirect(u16),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AddressingModeWord {
     Af,
     Bc,
@@ -372,8 +530,506 @@ impl Display for AddressingModeWord {
     }
 }
 
+// What follows an opcode in memory.
+#[derive(Clone, Copy)]
+enum Operand {
+    None,
+    Byte,
+    Word,
+    // The opcode is 0xCB, and the next byte selects an instruction from
+    // CB_OPCODES.
+    CbPrefix,
+}
+
+impl Operand {
+    // Length in bytes of an instruction with this operand, including the opcode.
+    const fn length(self) -> u16 {
+        match self {
+            Operand::None => 1,
+            Operand::Byte | Operand::CbPrefix => 2,
+            Operand::Word => 3,
+        }
+    }
+}
+
+// The shape of the instruction an opcode decodes to. Literals in
+// `instruction_type` are placeholders, filled in by `with_operand` once the
+// operand has been fetched.
+#[derive(Clone, Copy)]
+struct OpcodeInfo {
+    instruction_type: InstructionType,
+    operand: Operand,
+}
+
+impl AddressingModeByte {
+    // Indirect literals are offsets from their placeholder, which is how LDH's
+    // single byte operand becomes an address in 0xFF00-0xFFFF.
+    const fn with_operand(self, operand: u16) -> Self {
+        match self {
+            AddressingModeByte::Literal(_) => AddressingModeByte::Literal(operand as u8),
+            AddressingModeByte::LiteralIndirect(base) => {
+                AddressingModeByte::LiteralIndirect(base.wrapping_add(operand))
+            }
+            mode => mode,
+        }
+    }
+}
+
+impl AddressingModeWord {
+    const fn with_operand(self, operand: u16) -> Self {
+        match self {
+            AddressingModeWord::Literal(_) => AddressingModeWord::Literal(operand),
+            AddressingModeWord::LiteralIndirect(_) => AddressingModeWord::LiteralIndirect(operand),
+            mode => mode,
+        }
+    }
+}
+
+impl InstructionType {
+    fn with_operand(self, operand: u16) -> Self {
+        match self {
+            InstructionType::AddByte {
+                source,
+                destination,
+            } => InstructionType::AddByte {
+                source: source.with_operand(operand),
+                destination,
+            },
+            InstructionType::AddSp { .. } => InstructionType::AddSp {
+                value: operand as i8,
+            },
+            InstructionType::Adc {
+                source,
+                destination,
+            } => InstructionType::Adc {
+                source: source.with_operand(operand),
+                destination,
+            },
+            InstructionType::And { source } => InstructionType::And {
+                source: source.with_operand(operand),
+            },
+            InstructionType::Call { target, condition } => InstructionType::Call {
+                target: target.with_operand(operand),
+                condition,
+            },
+            InstructionType::Cp { source } => InstructionType::Cp {
+                source: source.with_operand(operand),
+            },
+            InstructionType::Jp { target, condition } => InstructionType::Jp {
+                target: target.with_operand(operand),
+                condition,
+            },
+            InstructionType::Jr {
+                unsigned_offset,
+                condition,
+            } => InstructionType::Jr {
+                unsigned_offset: unsigned_offset.with_operand(operand),
+                condition,
+            },
+            InstructionType::LdByte {
+                source,
+                destination,
+            } => InstructionType::LdByte {
+                source: source.with_operand(operand),
+                destination: destination.with_operand(operand),
+            },
+            InstructionType::LdWord {
+                source,
+                destination,
+            } => InstructionType::LdWord {
+                source: source.with_operand(operand),
+                destination: destination.with_operand(operand),
+            },
+            InstructionType::Ldhl { source, .. } => InstructionType::Ldhl {
+                source,
+                offset: operand as i8,
+            },
+            InstructionType::Or { source } => InstructionType::Or {
+                source: source.with_operand(operand),
+            },
+            InstructionType::Sbc {
+                source,
+                destination,
+            } => InstructionType::Sbc {
+                source: source.with_operand(operand),
+                destination,
+            },
+            InstructionType::Sub { source } => InstructionType::Sub {
+                source: source.with_operand(operand),
+            },
+            InstructionType::Xor { source } => InstructionType::Xor {
+                source: source.with_operand(operand),
+            },
+            instruction_type => instruction_type,
+        }
+    }
+}
+
+// The 8 bit register (or [hl]) encoded in the low three bits of many opcodes.
+const fn register_byte(index: u8) -> AddressingModeByte {
+    match index & 0b111 {
+        0b000 => AddressingModeByte::B,
+        0b001 => AddressingModeByte::C,
+        0b010 => AddressingModeByte::D,
+        0b011 => AddressingModeByte::E,
+        0b100 => AddressingModeByte::H,
+        0b101 => AddressingModeByte::L,
+        0b110 => AddressingModeByte::HlIndirect,
+        _ => AddressingModeByte::Accumulator,
+    }
+}
+
+const fn register_word(index: u8) -> AddressingModeWord {
+    match index & 0b11 {
+        0b00 => AddressingModeWord::Bc,
+        0b01 => AddressingModeWord::De,
+        0b10 => AddressingModeWord::Hl,
+        _ => AddressingModeWord::Sp,
+    }
+}
+
+// PUSH and POP use AF where everything else uses SP.
+const fn stack_register_word(index: u8) -> AddressingModeWord {
+    match index & 0b11 {
+        0b11 => AddressingModeWord::Af,
+        index => register_word(index),
+    }
+}
+
+const fn indirect_byte(index: u8) -> AddressingModeByte {
+    match index & 0b11 {
+        0b00 => AddressingModeByte::BcIndirect,
+        0b01 => AddressingModeByte::DeIndirect,
+        0b10 => AddressingModeByte::HlIndirectIncrement,
+        _ => AddressingModeByte::HlIndirectDecrement,
+    }
+}
+
+const fn condition(index: u8) -> BranchConditionType {
+    match index & 0b11 {
+        0b00 => BranchConditionType::NotZero,
+        0b01 => BranchConditionType::Zero,
+        0b10 => BranchConditionType::NotCarry,
+        _ => BranchConditionType::Carry,
+    }
+}
+
+const fn alu(index: u8, source: AddressingModeByte) -> InstructionType {
+    let destination = AddressingModeByte::Accumulator;
+    match index & 0b111 {
+        0b000 => InstructionType::AddByte {
+            source,
+            destination,
+        },
+        0b001 => InstructionType::Adc {
+            source,
+            destination,
+        },
+        0b010 => InstructionType::Sub { source },
+        0b011 => InstructionType::Sbc {
+            source,
+            destination,
+        },
+        0b100 => InstructionType::And { source },
+        0b101 => InstructionType::Xor { source },
+        0b110 => InstructionType::Or { source },
+        _ => InstructionType::Cp { source },
+    }
+}
+
+const fn opcode_info(opcode: u8) -> OpcodeInfo {
+    let (instruction_type, operand) = match opcode {
+        0x00..=0x3F => match opcode & 0b1111 {
+            0x0 | 0x8 => match opcode {
+                0x00 => (InstructionType::Nop, Operand::None),
+                0x08 => (
+                    InstructionType::LdWord {
+                        source: AddressingModeWord::Sp,
+                        destination: AddressingModeWord::LiteralIndirect(0),
+                    },
+                    Operand::Word,
+                ),
+                0x10 => (InstructionType::Stop, Operand::None),
+                0x18 => (
+                    InstructionType::Jr {
+                        unsigned_offset: AddressingModeByte::Literal(0),
+                        condition: BranchConditionType::Unconditional,
+                    },
+                    Operand::Byte,
+                ),
+                _ => (
+                    InstructionType::Jr {
+                        unsigned_offset: AddressingModeByte::Literal(0),
+                        condition: condition(opcode >> 3),
+                    },
+                    Operand::Byte,
+                ),
+            },
+            0x7 | 0xF => {
+                let instruction_type = match opcode >> 3 {
+                    0b000 => InstructionType::Rlca,
+                    0b001 => InstructionType::Rrca,
+                    0b010 => InstructionType::Rla,
+                    0b011 => InstructionType::Rra,
+                    0b100 => InstructionType::Daa,
+                    0b101 => InstructionType::Cpl,
+                    0b110 => InstructionType::Scf,
+                    _ => InstructionType::Ccf,
+                };
+                (instruction_type, Operand::None)
+            }
+            0x1 => (
+                InstructionType::LdWord {
+                    source: AddressingModeWord::Literal(0),
+                    destination: register_word(opcode >> 4),
+                },
+                Operand::Word,
+            ),
+            0x2 => (
+                InstructionType::LdByte {
+                    source: AddressingModeByte::Accumulator,
+                    destination: indirect_byte(opcode >> 4),
+                },
+                Operand::None,
+            ),
+            0x3 => (
+                InstructionType::IncWord {
+                    target: register_word(opcode >> 4),
+                },
+                Operand::None,
+            ),
+            0x9 => (
+                InstructionType::AddHl {
+                    source: register_word(opcode >> 4),
+                },
+                Operand::None,
+            ),
+            0xA => (
+                InstructionType::LdByte {
+                    source: indirect_byte(opcode >> 4),
+                    destination: AddressingModeByte::Accumulator,
+                },
+                Operand::None,
+            ),
+            0xB => (
+                InstructionType::DecWord {
+                    target: register_word(opcode >> 4),
+                },
+                Operand::None,
+            ),
+            0x4 | 0xC => (
+                InstructionType::IncByte {
+                    target: register_byte(opcode >> 3),
+                },
+                Operand::None,
+            ),
+            0x5 | 0xD => (
+                InstructionType::DecByte {
+                    target: register_byte(opcode >> 3),
+                },
+                Operand::None,
+            ),
+            0x6 | 0xE => (
+                InstructionType::LdByte {
+                    source: AddressingModeByte::Literal(0),
+                    destination: register_byte(opcode >> 3),
+                },
+                Operand::Byte,
+            ),
+            _ => unreachable!(),
+        },
+        0x40..=0x7F => match opcode {
+            0x76 => (InstructionType::Halt, Operand::None),
+            _ => (
+                InstructionType::LdByte {
+                    source: register_byte(opcode),
+                    destination: register_byte(opcode >> 3),
+                },
+                Operand::None,
+            ),
+        },
+        0x80..=0xBF => (alu(opcode >> 3, register_byte(opcode)), Operand::None),
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => (
+            InstructionType::Ret {
+                condition: condition(opcode >> 3),
+            },
+            Operand::None,
+        ),
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => (
+            InstructionType::Pop {
+                target: stack_register_word(opcode >> 4),
+            },
+            Operand::None,
+        ),
+        0xC2 | 0xCA | 0xD2 | 0xDA => (
+            InstructionType::Jp {
+                target: AddressingModeWord::Literal(0),
+                condition: condition(opcode >> 3),
+            },
+            Operand::Word,
+        ),
+        0xC3 => (
+            InstructionType::Jp {
+                target: AddressingModeWord::Literal(0),
+                condition: BranchConditionType::Unconditional,
+            },
+            Operand::Word,
+        ),
+        0xC4 | 0xCC | 0xD4 | 0xDC => (
+            InstructionType::Call {
+                target: AddressingModeWord::Literal(0),
+                condition: condition(opcode >> 3),
+            },
+            Operand::Word,
+        ),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => (
+            InstructionType::Push {
+                source: stack_register_word(opcode >> 4),
+            },
+            Operand::None,
+        ),
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => (
+            alu(opcode >> 3, AddressingModeByte::Literal(0)),
+            Operand::Byte,
+        ),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => (
+            InstructionType::Rst {
+                offset: (opcode & 0b00111000) as u16,
+            },
+            Operand::None,
+        ),
+        0xC9 => (
+            InstructionType::Ret {
+                condition: BranchConditionType::Unconditional,
+            },
+            Operand::None,
+        ),
+        0xCB => (InstructionType::Nop, Operand::CbPrefix),
+        0xCD => (
+            InstructionType::Call {
+                target: AddressingModeWord::Literal(0),
+                condition: BranchConditionType::Unconditional,
+            },
+            Operand::Word,
+        ),
+        0xD9 => (InstructionType::Reti, Operand::None),
+        0xE0 => (
+            InstructionType::LdByte {
+                source: AddressingModeByte::Accumulator,
+                destination: AddressingModeByte::LiteralIndirect(0xFF00),
+            },
+            Operand::Byte,
+        ),
+        0xE2 => (
+            InstructionType::LdByte {
+                source: AddressingModeByte::Accumulator,
+                destination: AddressingModeByte::CIndirect,
+            },
+            Operand::None,
+        ),
+        0xE8 => (InstructionType::AddSp { value: 0 }, Operand::Byte),
+        0xE9 => (InstructionType::JpHl, Operand::None),
+        0xEA => (
+            InstructionType::LdByte {
+                source: AddressingModeByte::Accumulator,
+                destination: AddressingModeByte::LiteralIndirect(0),
+            },
+            Operand::Word,
+        ),
+        0xF0 => (
+            InstructionType::LdByte {
+                source: AddressingModeByte::LiteralIndirect(0xFF00),
+                destination: AddressingModeByte::Accumulator,
+            },
+            Operand::Byte,
+        ),
+        0xF2 => (
+            InstructionType::LdByte {
+                source: AddressingModeByte::CIndirect,
+                destination: AddressingModeByte::Accumulator,
+            },
+            Operand::None,
+        ),
+        0xF3 => (InstructionType::Di, Operand::None),
+        0xF8 => (
+            InstructionType::Ldhl {
+                source: AddressingModeWord::Sp,
+                offset: 0,
+            },
+            Operand::Byte,
+        ),
+        0xF9 => (
+            InstructionType::LdSp {
+                source: AddressingModeWord::Hl,
+            },
+            Operand::None,
+        ),
+        0xFA => (
+            InstructionType::LdByte {
+                source: AddressingModeByte::LiteralIndirect(0),
+                destination: AddressingModeByte::Accumulator,
+            },
+            Operand::Word,
+        ),
+        0xFB => (InstructionType::Ei, Operand::None),
+        _ => (InstructionType::Illegal { opcode }, Operand::None),
+    };
+
+    OpcodeInfo {
+        instruction_type,
+        operand,
+    }
+}
+
+const fn cb_instruction_type(postfix: u8) -> InstructionType {
+    let target = register_byte(postfix);
+    let bit = (postfix >> 3) & 0b111;
+    match postfix >> 3 {
+        0b00000 => InstructionType::Rlc { target },
+        0b00001 => InstructionType::Rrc { target },
+        0b00010 => InstructionType::Rl { target },
+        0b00011 => InstructionType::Rr { target },
+        0b00100 => InstructionType::Sla { target },
+        0b00101 => InstructionType::Sra { target },
+        0b00110 => InstructionType::Swap { target },
+        0b00111 => InstructionType::Srl { target },
+        0b01000..=0b01111 => InstructionType::Bit { target, bit },
+        0b10000..=0b10111 => InstructionType::Res { target, bit },
+        _ => InstructionType::Set { target, bit },
+    }
+}
+
+// Decoding is a lookup into these tables, built at compile time, followed by
+// fetching the operand.
+const OPCODES: [OpcodeInfo; 0x100] = {
+    let mut table = [OpcodeInfo {
+        instruction_type: InstructionType::Nop,
+        operand: Operand::None,
+    }; 0x100];
+    let mut opcode = 0;
+    while opcode < table.len() {
+        table[opcode] = opcode_info(opcode as u8);
+        opcode += 1;
+    }
+    table
+};
+
+const CB_OPCODES: [InstructionType; 0x100] = {
+    let mut table = [InstructionType::Nop; 0x100];
+    let mut postfix = 0;
+    while postfix < table.len() {
+        table[postfix] = cb_instruction_type(postfix as u8);
+        postfix += 1;
+    }
+    table
+};
+
 impl Cpu {
     pub fn new(cartridge: Cartridge) -> Self {
+        Self::with_bus(Bus::new(cartridge))
+    }
+
+    fn with_bus(bus: Bus) -> Self {
         Self {
             af: 0x0000,
             bc: 0x0000,
@@ -381,18 +1037,89 @@ impl Cpu {
             hl: 0x0000,
             sp: 0x0000,
             pc: 0x0000,
-            bus: Bus::new(cartridge),
+            bus,
             halted: false,
             stopped: false,
+            hung: false,
+            ime_enable_pending: false,
             m_cycles_completed: 0,
+            last_interrupt: None,
+            last_instruction: None,
+            last_dispatched: None,
+            profile: None,
+            stack_guard: None,
+            stack_guard_violation: None,
+            t_cycles_elapsed: 0,
+            instructions_executed: 0,
+            breakpoints: HashSet::new(),
+            tracer: Tracer::default(),
+            held_buttons: JoypadState::NONE,
+            block_opposing_directions: false,
+            #[cfg(feature = "serde")]
+            movie: None,
+            #[cfg(feature = "serde")]
+            movie_frame: 0,
         }
     }
+
+    // Like `new`, but runs the given DMG (0x100 byte) or CGB (0x900 byte) boot
+    // ROM instead of the built in CGB boot ROM.
+    pub fn new_with_boot_rom(
+        cartridge: Cartridge,
+        boot_rom: &[u8],
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::with_bus(Bus::new_with_boot_rom(cartridge, boot_rom)?))
+    }
+
+    // Like `new`, but runs the cartridge in `mode` instead of the mode the boot
+    // ROM selects from the cartridge's CGB flag. Only DMG and CGB modes are
+    // supported.
+    pub fn new_with_mode(cartridge: Cartridge, mode: PpuMode) -> Result<Self, Box<dyn Error>> {
+        let mut bus = Bus::new(cartridge);
+        bus.set_ppu_mode_override(mode)?;
+        Ok(Self::with_bus(bus))
+    }
+
+    // Restarts the console as if it had been power cycled, running the boot
+    // ROM again from 0x0000. The loaded cartridge is kept, along with its
+    // battery-backed RAM if `keep_ram` is set. Profiling and stack guard
+    // settings are left as they were.
+    pub fn reset(&mut self, keep_ram: bool) {
+        self.af = 0x0000;
+        self.bc = 0x0000;
+        self.de = 0x0000;
+        self.hl = 0x0000;
+        self.sp = 0x0000;
+        self.pc = 0x0000;
+        self.bus.reset(keep_ram);
+        self.halted = false;
+        self.stopped = false;
+        self.hung = false;
+        self.ime_enable_pending = false;
+        self.m_cycles_completed = 0;
+        self.last_interrupt = None;
+        self.stack_guard_violation = None;
+        self.t_cycles_elapsed = 0;
+        self.instructions_executed = 0;
+    }
 }
 
 impl Cpu {
     // Runs a single fetch/decode/execute cycle. Returns the number of t-cycles elapsed during this execution.
     pub fn fetch_decode_execute(&mut self) -> u8 {
+        let start_pc = self.pc;
+        let ime_enable_pending = self.ime_enable_pending;
+        self.bus.watch_pc = start_pc;
+        self.bus.watch_cycle = self.t_cycles_elapsed;
+
         if self.stopped {
+            // The system clock is stopped, so time passes without anything on
+            // the bus running.
+            self.m_cycles_completed += 1;
+        } else if self.hung {
+            // Not even interrupts are serviced.
+            self.delay_m_cycle();
+        } else if self.bus.take_dma_stall_m_cycle() {
             self.delay_m_cycle();
         } else if self.halted {
             self.delay_m_cycle();
@@ -404,100 +1131,632 @@ impl Cpu {
         } else if let Some(interrupt_type) = self.bus.poll_interrupt() {
             self.handle_interrupt(interrupt_type);
         } else {
-            // let start_pc = self.pc;
             // let info_string = format!("af: 0x{:04x} bc: 0x{:04x}, de: 0x{:04x}, hl: 0x{:04x}, IME: {} IE: 0b{:08b} IF: 0b{:08b} dot: {} timer counter: {} tick counter: 0b{:016b}", self.af, self.bc, self.de, self.hl, self.bus.interrupt_master_enable, self.bus.interrupt_enable, self.bus.interrupt_flag, self.bus.ppu.dot, self.bus.timer.timer_counter, self.bus.timer.tick_counter);
+            let profile_index = self.profile.is_some().then(|| self.get_profile_index());
+            let registers = self.tracer.0.is_some().then(|| self.registers());
             let decoded = self.decode();
+            self.last_instruction = Some(decoded);
+            if let (Some(tracer), Some(registers)) = (&mut self.tracer.0, registers) {
+                tracer(TraceEvent {
+                    pc: start_pc,
+                    instruction: decoded,
+                    registers,
+                    cycles: self.t_cycles_elapsed,
+                });
+            }
             // println!("{:04x}: {} {}", start_pc, decoded, info_string);
+            if let (Some(profile), Some(profile_index)) = (&mut self.profile, profile_index) {
+                profile.counts[profile_index] += 1;
+                profile.examples[profile_index] = Some(decoded);
+            }
             self.execute(decoded);
+            self.instructions_executed += 1;
+        }
+
+        // EI was executed last step, so this step ran the instruction following
+        // it. A DI in between cancels the pending enable.
+        if ime_enable_pending && self.ime_enable_pending {
+            self.ime_enable_pending = false;
+            self.bus.set_interrupt_master_enable(true);
+        }
+
+        if let Some((low, high)) = self.stack_guard {
+            if self.stack_guard_violation.is_none() && !(low..=high).contains(&self.sp) {
+                self.stack_guard_violation = Some(StackGuardViolation {
+                    pc: start_pc,
+                    sp: self.sp,
+                });
+            }
         }
 
         let m_cycles_completed = self.m_cycles_completed;
         self.m_cycles_completed = 0;
-        match self.bus.get_current_speed() {
+        let t_cycles_completed = match self.bus.get_current_speed() {
             SpeedMode::Normal => m_cycles_completed * 4,
             SpeedMode::Double => m_cycles_completed * 2,
+        };
+        self.t_cycles_elapsed += u64::from(t_cycles_completed);
+
+        #[cfg(feature = "serde")]
+        if self.movie.is_some() && self.frame_count() != self.movie_frame {
+            self.apply_movie_input();
         }
+
+        t_cycles_completed
     }
 
-    pub fn set_button_pressed(&mut self, button: Button, pressed: bool) {
-        if pressed {
-            self.stopped = false;
+    // Steps like `fetch_decode_execute`, additionally reporting what ran, for
+    // debuggers. The instruction is the one the step actually fetched, which
+    // can differ from what's in memory, e.g. while OAM DMA blocks the bus.
+    pub fn step_debug(&mut self) -> StepInfo {
+        let pc = self.pc;
+        self.last_instruction = None;
+        self.last_dispatched = None;
+
+        let t_cycles = self.fetch_decode_execute();
+
+        StepInfo {
+            pc,
+            instruction: self.last_instruction.take(),
+            interrupt: self.last_dispatched.take(),
+            t_cycles,
         }
+    }
 
-        match button {
-            Button::A => self.bus.joypad.set_a_pressed(pressed),
-            Button::B => self.bus.joypad.set_b_pressed(pressed),
-            Button::Start => self.bus.joypad.set_start_pressed(pressed),
-            Button::Select => self.bus.joypad.set_select_pressed(pressed),
-            Button::Up => self.bus.joypad.set_up_pressed(pressed),
-            Button::Down => self.bus.joypad.set_down_pressed(pressed),
-            Button::Left => self.bus.joypad.set_left_pressed(pressed),
-            Button::Right => self.bus.joypad.set_right_pressed(pressed),
-        }
+    pub fn frame_count(&self) -> u64 {
+        self.bus.ppu.frame_count()
     }
 
-    #[cfg(test)]
-    pub fn read_register(&self, register: RegisterByte) -> u8 {
-        match register {
-            RegisterByte::Accumulator => (self.af >> 8) as u8,
-            RegisterByte::B => (self.bc >> 8) as u8,
-            RegisterByte::C => self.bc as u8,
-            RegisterByte::D => (self.de >> 8) as u8,
-            RegisterByte::E => self.de as u8,
-            RegisterByte::H => (self.hl >> 8) as u8,
-            RegisterByte::L => self.hl as u8,
+    pub fn speed_mode(&self) -> SpeedMode {
+        self.bus.get_current_speed()
+    }
+
+    // Whether the next STOP will switch speed instead of stopping the CPU.
+    pub fn speed_switch_armed(&self) -> bool {
+        self.bus.get_prepare_speed_switch()
+    }
+
+    // Returns whether the PPU has finished a frame since the last call, i.e.
+    // whether `get_buffer` holds a frame that hasn't been seen yet.
+    pub fn take_frame_ready(&mut self) -> bool {
+        self.bus.ppu.take_frame_ready()
+    }
+
+    // Runs until the PPU finishes the current frame, returning the number of
+    // t-cycles that took. No frames are produced while the LCD is off or the
+    // CPU is stopped, so in that case this returns once a frame's worth of
+    // cycles has elapsed.
+    pub fn step_frame(&mut self) -> u64 {
+        let frame = self.frame_count() + 1;
+        let mut cycles = 0;
+        while self.frame_count() < frame
+            && ((self.bus.ppu.get_lcd_ppu_enable() && !self.stopped) || cycles < DOTS_PER_FRAME)
+        {
+            cycles += u64::from(self.fetch_decode_execute());
         }
+        cycles
     }
 
-    fn read_byte(&mut self, location: AddressingModeByte) -> u8 {
-        match location {
-            AddressingModeByte::Accumulator => (self.af >> 8) as u8,
-            AddressingModeByte::B => (self.bc >> 8) as u8,
-            AddressingModeByte::C => self.bc as u8,
-            AddressingModeByte::D => (self.de >> 8) as u8,
-            AddressingModeByte::E => self.de as u8,
-            AddressingModeByte::H => (self.hl >> 8) as u8,
-            AddressingModeByte::L => self.hl as u8,
-            AddressingModeByte::CIndirect => {
-                let address = 0xFF00 + u16::from(self.read_byte(AddressingModeByte::C));
-                self.delay_m_cycle(); // dereference (FF00 + C)
-                self.bus.read_byte_address(address)
-            }
-            AddressingModeByte::BcIndirect => {
-                self.delay_m_cycle(); // dereference bc
-                self.bus.read_byte_address(self.bc)
-            }
-            AddressingModeByte::DeIndirect => {
-                self.delay_m_cycle(); // dereference de
-                self.bus.read_byte_address(self.de)
-            }
-            AddressingModeByte::HlIndirect => {
-                self.delay_m_cycle(); // dereference hl
-                self.bus.read_byte_address(self.hl)
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(&pc);
+    }
+
+    // Runs until pc lands on a breakpoint, at least `max_cycles` t-cycles have
+    // elapsed, or the CPU can no longer make progress on its own. At least one
+    // instruction is always executed, so continuing from a breakpoint does not
+    // stop on it again immediately.
+    pub fn run_until_break(&mut self, max_cycles: u64) -> RunStop {
+        let mut cycles = 0;
+        loop {
+            cycles += u64::from(self.fetch_decode_execute());
+
+            if self.breakpoints.contains(&self.pc) {
+                return RunStop::Breakpoint(self.pc);
             }
-            AddressingModeByte::HlIndirectIncrement => {
-                self.delay_m_cycle(); // dereference hl
-                let result = self.bus.read_byte_address(self.hl);
-                self.hl = self.hl.wrapping_add(1);
-                result
+
+            if self.stopped || self.hung || (self.halted && self.bus.interrupt_enable == 0) {
+                return RunStop::Halted;
             }
-            AddressingModeByte::HlIndirectDecrement => {
-                self.delay_m_cycle(); // derefence hl
-                let result = self.bus.read_byte_address(self.hl);
-                self.hl = self.hl.wrapping_sub(1);
-                result
+
+            if cycles >= max_cycles {
+                return RunStop::CyclesExhausted;
             }
-            AddressingModeByte::Literal(val) => val,
-            AddressingModeByte::LiteralIndirect(address) => {
-                self.delay_m_cycle(); // dereference address
-                self.bus.read_byte_address(address)
+        }
+    }
+
+    // Runs until `frame_count()` reaches `frame`, stopping immediately after the
+    // target frame is completed. Returns immediately if the target frame has
+    // already been reached. Frames do not advance while the LCD is disabled or
+    // the CPU is stopped, so this gives up once `max_idle_frames` frames' worth
+    // of cycles pass in a row without a frame being produced. Returns false if
+    // it gave up, which may just mean the game kept the LCD off for longer than
+    // that, e.g. while loading.
+    pub fn run_to_frame(&mut self, frame: u64, max_idle_frames: u32) -> bool {
+        let mut idle_frames = 0;
+        while self.frame_count() < frame {
+            let frame_count = self.frame_count();
+            self.step_frame();
+            if self.frame_count() == frame_count {
+                idle_frames += 1;
+                if idle_frames >= max_idle_frames {
+                    return false;
+                }
+            } else {
+                idle_frames = 0;
             }
         }
+        true
     }
 
-    fn read_word(&mut self, location: AddressingModeWord) -> u16 {
-        match location {
-            AddressingModeWord::Af => self.af,
+    // Returns the amount of time that has passed on the emulated system. This
+    // only advances as instructions are executed, independent of wall-clock time.
+    pub fn emulated_time(&self) -> Duration {
+        let clock_frequency = u64::from(CLOCK_FREQUENCY);
+        let seconds = self.t_cycles_elapsed / clock_frequency;
+        let nanos = (self.t_cycles_elapsed % clock_frequency) * 1_000_000_000 / clock_frequency;
+
+        Duration::new(seconds, nanos as u32)
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            instructions: self.instructions_executed,
+            t_cycles: self.t_cycles_elapsed,
+            frames: self.frame_count(),
+        }
+    }
+
+    pub fn set_button_pressed(&mut self, button: Button, pressed: bool) {
+        self.set_joypad_state(self.held_buttons.with(button, pressed));
+    }
+
+    // Replaces the state of every button at once, e.g. for scripted input or
+    // replaying a recording.
+    // While a movie is recording or playing, the new state only reaches the
+    // game at the start of the next frame.
+    pub fn set_joypad_state(&mut self, state: JoypadState) {
+        self.held_buttons = state;
+
+        #[cfg(feature = "serde")]
+        if self.movie.is_some() {
+            return;
+        }
+
+        self.apply_joypad_state(self.filtered_held_buttons());
+    }
+
+    fn filtered_held_buttons(&self) -> JoypadState {
+        if self.block_opposing_directions {
+            self.held_buttons.without_opposing_directions()
+        } else {
+            self.held_buttons
+        }
+    }
+
+    fn apply_joypad_state(&mut self, state: JoypadState) {
+        let old_input = self.bus.joypad.read();
+        self.bus.joypad.set_state(state);
+
+        // Only a press on a selected line ends STOP.
+        if old_input & !self.bus.joypad.read() & 0x0F != 0 {
+            self.stopped = false;
+        }
+    }
+
+    // The buttons the game currently sees as pressed.
+    pub fn joypad_state(&self) -> JoypadState {
+        self.bus.joypad.state()
+    }
+
+    // When set, holding both Up and Down (or Left and Right) reads as neither,
+    // as a real D-pad can't press both. Takes effect on the next input change.
+    pub fn set_block_opposing_directions(&mut self, block: bool) {
+        self.block_opposing_directions = block;
+    }
+
+    // Starts recording a movie from the current state, see `Movie`. Any movie
+    // already recording or playing is discarded.
+    #[cfg(feature = "serde")]
+    pub fn start_recording(&mut self) {
+        self.movie = Some(MovieMode::Recording(Movie::new(self.save_state())));
+        self.apply_movie_input();
+    }
+
+    // Returns the recorded movie, or None if nothing was being recorded.
+    #[cfg(feature = "serde")]
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        match self.movie.take() {
+            Some(MovieMode::Recording(movie)) => Some(movie),
+            movie => {
+                self.movie = movie;
+                None
+            }
+        }
+    }
+
+    // Restores the movie's initial state and replays its inputs frame by
+    // frame. Host input is ignored until the movie ends or `stop_movie` is
+    // called.
+    #[cfg(feature = "serde")]
+    pub fn play_movie(&mut self, movie: &Movie) -> Result<(), StateError> {
+        self.load_state(movie.initial_state())?;
+        self.movie = Some(MovieMode::Playing {
+            movie: movie.clone(),
+            frame: 0,
+        });
+        self.apply_movie_input();
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieMode::Playing { .. }))
+    }
+
+    // Ends any recording or playback, handing input back to the host.
+    #[cfg(feature = "serde")]
+    pub fn stop_movie(&mut self) {
+        self.movie = None;
+        self.apply_joypad_state(self.filtered_held_buttons());
+    }
+
+    #[cfg(feature = "serde")]
+    fn apply_movie_input(&mut self) {
+        self.movie_frame = self.frame_count();
+        let held = self.filtered_held_buttons();
+        match self.movie.as_mut().and_then(|movie| movie.next_input(held)) {
+            Some(state) => self.apply_joypad_state(state),
+            None => self.stop_movie(),
+        }
+    }
+
+    // Whether the CPU has locked up on an illegal opcode. Only a reset gets it
+    // going again.
+    pub fn is_hung(&self) -> bool {
+        self.hung
+    }
+
+    // Whether the CPU is in STOP mode, waiting on a button press.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    // Snapshots the full emulator state. The cartridge ROM is not included, so
+    // the snapshot can only be restored into a Cpu running the same cartridge.
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut result = vec![STATE_VERSION];
+        bincode::serialize_into(&mut result, self).expect("failed to serialize save state");
+        result
+    }
+
+    // Restores a snapshot created by `save_state`. On error, the current state
+    // is left untouched. Instruction profiling and stack guard state are not
+    // part of the snapshot and are preserved.
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (&version, data) = data.split_first().ok_or(StateError::Empty)?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut loaded: Cpu = state::decode(data)?;
+        if !loaded
+            .bus
+            .cartridge
+            .restore_rom_from(&mut self.bus.cartridge)
+        {
+            return Err(StateError::CartridgeMismatch);
+        }
+
+        loaded
+            .bus
+            .cartridge
+            .carry_ram_dirty_from(&self.bus.cartridge);
+        loaded.profile = self.profile.take();
+        loaded.breakpoints = std::mem::take(&mut self.breakpoints);
+        loaded.stack_guard = self.stack_guard;
+        loaded.stack_guard_violation = self.stack_guard_violation;
+        loaded.tracer = std::mem::take(&mut self.tracer);
+        loaded.held_buttons = self.held_buttons;
+        loaded.block_opposing_directions = self.block_opposing_directions;
+        loaded.movie = self.movie.take();
+        loaded.movie_frame = self.movie_frame;
+        loaded.bus.boot_rom = std::mem::take(&mut self.bus.boot_rom);
+        loaded.bus.watchpoints = std::mem::take(&mut self.bus.watchpoints);
+        loaded.bus.io_log_enabled = self.bus.io_log_enabled;
+        loaded.bus.io_log = std::mem::take(&mut self.bus.io_log);
+        loaded.bus.cheats = std::mem::take(&mut self.bus.cheats);
+        loaded.bus.apu.restore_settings_from(&self.bus.apu);
+        loaded.bus.ppu.restore_settings_from(&self.bus.ppu);
+        std::mem::swap(&mut loaded.bus.serial.link, &mut self.bus.serial.link);
+        loaded.bus.serial.on_write = self.bus.serial.on_write.take();
+        *self = loaded;
+        Ok(())
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            af: self.af,
+            bc: self.bc,
+            de: self.de,
+            hl: self.hl,
+            sp: self.sp,
+            pc: self.pc,
+        }
+    }
+
+    // Returns the current state as a Gameboy Doctor log line. Memory at pc is
+    // peeked, so this has no side effects.
+    pub fn doctor_line(&self) -> String {
+        let pc_memory =
+            std::array::from_fn(|i| self.bus.peek_byte_address(self.pc.wrapping_add(i as u16)));
+        doctor::format_line(self.registers(), pc_memory)
+    }
+
+    // Calls `tracer` before each instruction executes. Interrupt dispatch and
+    // cycles spent halted or stopped aren't reported.
+    pub fn set_tracer(&mut self, tracer: Box<dyn FnMut(TraceEvent)>) {
+        self.tracer = Tracer(Some(tracer));
+    }
+
+    pub fn clear_tracer(&mut self) {
+        self.tracer = Tracer(None);
+    }
+
+    pub fn interrupt_master_enable(&self) -> bool {
+        self.bus.interrupt_master_enable
+    }
+
+    // IE, 0xFFFF.
+    pub fn interrupt_enable(&self) -> u8 {
+        self.bus.interrupt_enable
+    }
+
+    // IF, 0xFF0F.
+    pub fn interrupt_flag(&self) -> u8 {
+        self.bus.interrupt_flag
+    }
+
+    pub fn pending_interrupts(&self) -> Vec<InterruptType> {
+        self.bus.pending_interrupts()
+    }
+
+    // Returns the interrupt most recently serviced by the CPU, if any interrupt
+    // has been serviced yet.
+    pub fn last_interrupt(&self) -> Option<InterruptType> {
+        self.last_interrupt
+    }
+
+    // Game Genie codes take effect on the next ROM read, GameShark codes at the
+    // end of every frame.
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.bus.add_cheat(cheat);
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.bus.clear_cheats();
+    }
+
+    // Records every matching access to `address` until the watchpoint is
+    // removed. Hits are collected with `take_watch_hits`.
+    pub fn add_watchpoint(&mut self, address: u16, kind: WatchKind) {
+        self.bus.add_watchpoint(address, kind);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) {
+        self.bus.remove_watchpoint(address);
+    }
+
+    // Returns the watchpoint hits recorded since the last call, oldest first.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.bus.take_watch_hits()
+    }
+
+    // Reads memory as the CPU would see it, through the current MBC banks and
+    // I/O register read logic, but without ticking the bus or triggering
+    // watchpoints. OAM DMA doesn't block these reads.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.bus.peek_byte_address(address)
+    }
+
+    // Like `peek`, for `len` bytes starting at `address`. Wraps at 0xFFFF.
+    pub fn peek_range(&self, address: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.peek(address.wrapping_add(offset as u16)))
+            .collect()
+    }
+
+    // Writes memory as the CPU would, without triggering watchpoints. Writes
+    // have their usual side effects, so poking an MBC or I/O register switches
+    // banks, starts DMA and so on.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.bus.write_byte_address_unwatched(value, address);
+    }
+
+    // Flags any instruction that leaves sp outside of `low..=high` (inclusive).
+    // The first violation is held until retrieved with
+    // `poll_stack_guard_violation`. The guard is disabled by default.
+    pub fn set_stack_guard(&mut self, low: u16, high: u16) {
+        self.stack_guard = Some((low, high));
+        self.stack_guard_violation = None;
+    }
+
+    pub fn clear_stack_guard(&mut self) {
+        self.stack_guard = None;
+        self.stack_guard_violation = None;
+    }
+
+    // Returns and clears the pending stack guard violation, if any.
+    pub fn poll_stack_guard_violation(&mut self) -> Option<StackGuardViolation> {
+        self.stack_guard_violation.take()
+    }
+
+    // Enables or disables counting of executed instructions by opcode.
+    // Disabling profiling discards all counts collected so far.
+    pub fn set_profiling_enabled(&mut self, enabled: bool) {
+        self.profile = if enabled {
+            Some(self.profile.take().unwrap_or_else(|| {
+                Box::new(InstructionProfile {
+                    counts: [0; 0x200],
+                    examples: [None; 0x200],
+                })
+            }))
+        } else {
+            None
+        };
+    }
+
+    // Returns the execution count of every opcode executed since profiling was
+    // enabled, sorted from most to least executed. Returns an empty report if
+    // profiling is not enabled.
+    pub fn profile_report(&self) -> Vec<ProfileEntry> {
+        let mut result: Vec<ProfileEntry> = match &self.profile {
+            Some(profile) => (0..0x200)
+                .filter_map(|index| {
+                    profile.examples[index].map(|example| ProfileEntry {
+                        opcode: if index < 0x100 {
+                            index as u16
+                        } else {
+                            0xCB00 | (index as u16 - 0x100)
+                        },
+                        count: profile.counts[index],
+                        example,
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        result.sort_by(|a, b| b.count.cmp(&a.count).then(a.opcode.cmp(&b.opcode)));
+        result
+    }
+
+    // Peeks the opcode at pc without ticking the bus, for indexing into the profile.
+    fn get_profile_index(&self) -> usize {
+        let opcode = self.bus.peek_byte_address(self.pc);
+        if opcode == 0xCB {
+            0x100 + usize::from(self.bus.peek_byte_address(self.pc.wrapping_add(1)))
+        } else {
+            usize::from(opcode)
+        }
+    }
+
+    pub fn register(&self, register: RegisterByte) -> u8 {
+        match register {
+            RegisterByte::Accumulator => (self.af >> 8) as u8,
+            RegisterByte::B => (self.bc >> 8) as u8,
+            RegisterByte::C => self.bc as u8,
+            RegisterByte::D => (self.de >> 8) as u8,
+            RegisterByte::E => self.de as u8,
+            RegisterByte::H => (self.hl >> 8) as u8,
+            RegisterByte::L => self.hl as u8,
+        }
+    }
+
+    pub fn set_register(&mut self, register: RegisterByte, val: u8) {
+        let (word, high) = match register {
+            RegisterByte::Accumulator => (&mut self.af, true),
+            RegisterByte::B => (&mut self.bc, true),
+            RegisterByte::C => (&mut self.bc, false),
+            RegisterByte::D => (&mut self.de, true),
+            RegisterByte::E => (&mut self.de, false),
+            RegisterByte::H => (&mut self.hl, true),
+            RegisterByte::L => (&mut self.hl, false),
+        };
+
+        if high {
+            *word = (*word & 0x00FF) | (u16::from(val) << 8);
+        } else {
+            *word = (*word & 0xFF00) | u16::from(val);
+        }
+    }
+
+    pub fn register_word(&self, register: RegisterWord) -> u16 {
+        match register {
+            RegisterWord::Af => self.af,
+            RegisterWord::Bc => self.bc,
+            RegisterWord::De => self.de,
+            RegisterWord::Hl => self.hl,
+            RegisterWord::Sp => self.sp,
+            RegisterWord::Pc => self.pc,
+        }
+    }
+
+    // The low nibble of f is always zero on hardware, so it is masked off when
+    // writing af.
+    pub fn set_register_word(&mut self, register: RegisterWord, val: u16) {
+        match register {
+            RegisterWord::Af => self.af = val & 0xFFF0,
+            RegisterWord::Bc => self.bc = val,
+            RegisterWord::De => self.de = val,
+            RegisterWord::Hl => self.hl = val,
+            RegisterWord::Sp => self.sp = val,
+            RegisterWord::Pc => self.pc = val,
+        }
+    }
+
+    fn read_byte(&mut self, location: AddressingModeByte) -> u8 {
+        match location {
+            AddressingModeByte::Accumulator => (self.af >> 8) as u8,
+            AddressingModeByte::B => (self.bc >> 8) as u8,
+            AddressingModeByte::C => self.bc as u8,
+            AddressingModeByte::D => (self.de >> 8) as u8,
+            AddressingModeByte::E => self.de as u8,
+            AddressingModeByte::H => (self.hl >> 8) as u8,
+            AddressingModeByte::L => self.hl as u8,
+            AddressingModeByte::CIndirect => {
+                let address = 0xFF00 + u16::from(self.read_byte(AddressingModeByte::C));
+                self.delay_m_cycle(); // dereference (FF00 + C)
+                self.bus.read_byte_address(address)
+            }
+            AddressingModeByte::BcIndirect => {
+                self.delay_m_cycle(); // dereference bc
+                self.read_memory(self.bc)
+            }
+            AddressingModeByte::DeIndirect => {
+                self.delay_m_cycle(); // dereference de
+                self.read_memory(self.de)
+            }
+            AddressingModeByte::HlIndirect => {
+                self.delay_m_cycle(); // dereference hl
+                self.read_memory(self.hl)
+            }
+            AddressingModeByte::HlIndirectIncrement => {
+                self.delay_m_cycle(); // dereference hl
+                self.bus
+                    .trigger_oam_bug(self.hl, OamCorruption::ReadDuringIncDec);
+                let result = self.bus.read_byte_address(self.hl);
+                self.hl = self.hl.wrapping_add(1);
+                result
+            }
+            AddressingModeByte::HlIndirectDecrement => {
+                self.delay_m_cycle(); // derefence hl
+                self.bus
+                    .trigger_oam_bug(self.hl, OamCorruption::ReadDuringIncDec);
+                let result = self.bus.read_byte_address(self.hl);
+                self.hl = self.hl.wrapping_sub(1);
+                result
+            }
+            AddressingModeByte::Literal(val) => val,
+            AddressingModeByte::LiteralIndirect(address) => {
+                self.delay_m_cycle(); // dereference address
+                self.read_memory(address)
+            }
+        }
+    }
+
+    fn read_word(&mut self, location: AddressingModeWord) -> u16 {
+        match location {
+            AddressingModeWord::Af => self.af,
             AddressingModeWord::Bc => self.bc,
             AddressingModeWord::De => self.de,
             AddressingModeWord::Hl => self.hl,
@@ -505,9 +1764,9 @@ impl Cpu {
             AddressingModeWord::Literal(val) => val,
             AddressingModeWord::LiteralIndirect(address) => {
                 self.delay_m_cycle(); // read data LSB
-                let lsb_byte = self.bus.read_byte_address(address);
+                let lsb_byte = self.read_memory(address);
                 self.delay_m_cycle(); // read data MSB
-                let msb_byte = self.bus.read_byte_address(address + 1);
+                let msb_byte = self.read_memory(address + 1);
 
                 u16::from_be_bytes([msb_byte, lsb_byte])
             }
@@ -547,34 +1806,34 @@ impl Cpu {
             AddressingModeByte::CIndirect => {
                 self.delay_m_cycle(); // dereference (FF00 + C)
                 let address = 0xFF00 + u16::from(self.read_byte(AddressingModeByte::C));
-                self.bus.write_byte_address(val, address);
+                self.write_memory(val, address);
             }
             AddressingModeByte::BcIndirect => {
                 self.delay_m_cycle(); // dereference bc
-                self.bus.write_byte_address(val, self.bc);
+                self.write_memory(val, self.bc);
             }
             AddressingModeByte::DeIndirect => {
                 self.delay_m_cycle(); // dereference de
-                self.bus.write_byte_address(val, self.de);
+                self.write_memory(val, self.de);
             }
             AddressingModeByte::HlIndirect => {
                 self.delay_m_cycle(); // dereference hl
-                self.bus.write_byte_address(val, self.hl);
+                self.write_memory(val, self.hl);
             }
             AddressingModeByte::HlIndirectIncrement => {
                 self.delay_m_cycle(); // dereference hl
-                self.bus.write_byte_address(val, self.hl);
+                self.write_memory(val, self.hl);
                 self.hl = self.hl.wrapping_add(1)
             }
             AddressingModeByte::HlIndirectDecrement => {
                 self.delay_m_cycle(); // dereference hl
-                self.bus.write_byte_address(val, self.hl);
+                self.write_memory(val, self.hl);
                 self.hl = self.hl.wrapping_sub(1)
             }
             AddressingModeByte::Literal(_) => unreachable!(),
             AddressingModeByte::LiteralIndirect(address) => {
                 self.delay_m_cycle(); // write data byte
-                self.bus.write_byte_address(val, address);
+                self.write_memory(val, address);
             }
         }
     }
@@ -592,186 +1851,955 @@ impl Cpu {
             AddressingModeWord::LiteralIndirect(address) => {
                 let [msb_data, lsb_data] = val.to_be_bytes();
                 self.delay_m_cycle(); // write data LSB
-                self.bus.write_byte_address(lsb_data, address);
+                self.write_memory(lsb_data, address);
                 self.delay_m_cycle(); // write data MSB
-                self.bus.write_byte_address(msb_data, address + 1);
+                self.write_memory(msb_data, address + 1);
             }
         }
     }
 
+    // Data reads and writes go through these so that they can trigger the OAM
+    // bug. Instruction fetches don't.
+    fn read_memory(&mut self, address: u16) -> u8 {
+        self.bus.trigger_oam_bug(address, OamCorruption::Read);
+        self.bus.read_byte_address(address)
+    }
+
+    fn write_memory(&mut self, val: u8, address: u16) {
+        self.bus.trigger_oam_bug(address, OamCorruption::Write);
+        self.bus.write_byte_address(val, address);
+    }
+
     fn delay_m_cycle(&mut self) {
         self.bus.step_m_cycle();
         self.m_cycles_completed += 1;
     }
 
     fn decode(&mut self) -> Instruction {
-        let opcode = self.read_byte(AddressingModeByte::LiteralIndirect(self.pc));
-        // print!("opcode: {:02x} ", opcode);
+        let (instruction, length) = Self::decode_with(self.pc, |address| {
+            self.delay_m_cycle(); // read instruction byte
+            self.bus.read_byte_address(address)
+        });
+        self.pc = self.pc.wrapping_add(length);
+        instruction
+    }
 
-        match opcode {
-            0x00 => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Nop,
-                }
-            }
-            0x01 | 0x11 | 0x21 | 0x31 => {
-                let source_value = self.read_word(AddressingModeWord::LiteralIndirect(self.pc + 1));
-                let source = AddressingModeWord::Literal(source_value);
-                let destination = match (opcode & 0b00110000) >> 4 {
-                    0b00 => AddressingModeWord::Bc,
-                    0b01 => AddressingModeWord::De,
-                    0b10 => AddressingModeWord::Hl,
-                    0b11 => AddressingModeWord::Sp,
-                    _ => unreachable!(),
-                };
+    // Returns the instruction at `address` without executing it, along with its
+    // length in bytes. Memory is only peeked, so no cycles elapse and no
+    // watchpoints fire.
+    pub fn disassemble_at(&self, address: u16) -> (Instruction, u16) {
+        Self::decode_with(address, |address| self.bus.peek_byte_address(address))
+    }
 
-                self.pc += 3;
-                Instruction {
-                    instruction_type: InstructionType::LdWord {
-                        source,
-                        destination,
-                    },
-                }
-            }
-            0x02 | 0x12 | 0x22 | 0x32 => {
-                let source = AddressingModeByte::Accumulator;
-                let destination = match (opcode & 0b00110000) >> 4 {
-                    0b00 => AddressingModeByte::BcIndirect,
-                    0b01 => AddressingModeByte::DeIndirect,
-                    0b10 => AddressingModeByte::HlIndirectIncrement,
-                    0b11 => AddressingModeByte::HlIndirectDecrement,
-                    _ => unreachable!(),
-                };
+    // Decodes the instruction at `address`, fetching each of its bytes in order
+    // through `read`. Returns the instruction and its length in bytes.
+    fn decode_with(address: u16, mut read: impl FnMut(u16) -> u8) -> (Instruction, u16) {
+        let opcode = read(address);
+        let info = OPCODES[usize::from(opcode)];
+
+        let instruction_type = match info.operand {
+            Operand::None => info.instruction_type,
+            Operand::Byte => info
+                .instruction_type
+                .with_operand(u16::from(read(address.wrapping_add(1)))),
+            Operand::Word => info.instruction_type.with_operand(u16::from_le_bytes([
+                read(address.wrapping_add(1)),
+                read(address.wrapping_add(2)),
+            ])),
+            Operand::CbPrefix => CB_OPCODES[usize::from(read(address.wrapping_add(1)))],
+        };
 
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::LdByte {
-                        source,
-                        destination,
-                    },
-                }
-            }
-            0x03 | 0x0B | 0x13 | 0x1B | 0x23 | 0x2B | 0x33 | 0x3B => {
-                fn get_addressing_mode(val: u8) -> AddressingModeWord {
-                    match val {
-                        0b00 => AddressingModeWord::Bc,
-                        0b01 => AddressingModeWord::De,
-                        0b10 => AddressingModeWord::Hl,
-                        0b11 => AddressingModeWord::Sp,
-                        _ => unreachable!(),
-                    }
-                }
+        (Instruction { instruction_type }, info.operand.length())
+    }
 
-                let target = get_addressing_mode((opcode & 0b00110000) >> 4);
-                self.pc += 1;
-                let instruction_type = match (opcode & 0b00001100) >> 2 {
-                    0b00 => InstructionType::IncWord { target },
-                    0b10 => InstructionType::DecWord { target },
-                    _ => unreachable!(),
-                };
-                Instruction { instruction_type }
-            }
-            0x04 | 0x05 | 0x0C | 0x0D | 0x14 | 0x15 | 0x1C | 0x1D | 0x24 | 0x25 | 0x2C | 0x2D
-            | 0x34 | 0x35 | 0x3C | 0x3D => {
-                fn get_addressing_mode(val: u8) -> AddressingModeByte {
-                    match val {
-                        0b000 => AddressingModeByte::B,
-                        0b001 => AddressingModeByte::C,
-                        0b010 => AddressingModeByte::D,
-                        0b011 => AddressingModeByte::E,
-                        0b100 => AddressingModeByte::H,
-                        0b101 => AddressingModeByte::L,
-                        0b110 => AddressingModeByte::HlIndirect,
-                        0b111 => AddressingModeByte::Accumulator,
-                        _ => unreachable!(),
-                    }
-                }
+    fn execute(&mut self, instruction: Instruction) {
+        match instruction.instruction_type {
+            InstructionType::AddByte {
+                source,
+                destination,
+            } => self.execute_add_byte(source, destination),
+            InstructionType::AddHl { source } => self.execute_add_hl(source),
+            InstructionType::AddSp { value } => self.execute_add_sp(value),
+            InstructionType::Adc {
+                source,
+                destination,
+            } => self.execute_adc(source, destination),
+            InstructionType::And { source } => self.execute_and(source),
+            InstructionType::Bit { target, bit } => self.execute_bit(target, bit),
+            InstructionType::Call {
+                target: address,
+                condition,
+            } => self.execute_call(address, condition),
+            InstructionType::Ccf => self.execute_ccf(),
+            InstructionType::Cp { source } => self.execute_cp(source),
+            InstructionType::Cpl => self.execute_cpl(),
+            InstructionType::Daa => self.execute_daa(),
+            InstructionType::DecByte { target } => self.execute_dec_byte(target),
+            InstructionType::DecWord { target } => self.execute_dec_word(target),
+            InstructionType::Di => self.execute_di(),
+            InstructionType::Ei => self.execute_ei(),
+            InstructionType::Halt => self.execute_halt(),
+            InstructionType::Illegal { .. } => self.hung = true,
+            InstructionType::IncByte { target } => self.execute_inc_byte(target),
+            InstructionType::IncWord { target } => self.execute_inc_word(target),
+            InstructionType::Jp { target, condition } => self.execute_jp(target, condition),
+            InstructionType::JpHl => self.execute_jp_hl(),
+            InstructionType::Jr {
+                unsigned_offset,
+                condition,
+            } => self.execute_jr(unsigned_offset, condition),
+            InstructionType::LdByte {
+                source,
+                destination,
+            } => self.execute_ld_byte(source, destination),
+            InstructionType::LdSp { source } => self.execute_ld_sp(source),
+            InstructionType::LdWord {
+                source,
+                destination,
+            } => self.execute_ld_word(source, destination),
+            InstructionType::Ldhl { source, offset } => self.execute_ldhl(source, offset),
+            InstructionType::Nop => {}
+            InstructionType::Or { source } => self.execute_or(source),
+            InstructionType::Pop { target } => self.execute_pop(target),
+            InstructionType::Push { source } => self.execute_push(source),
+            InstructionType::Res { target, bit } => self.execute_res(target, bit),
+            InstructionType::Ret { condition } => self.execute_ret(condition),
+            InstructionType::Reti => self.execute_reti(),
+            InstructionType::Rl { target } => self.execute_rl(target),
+            InstructionType::Rla => self.execute_rla(),
+            InstructionType::Rlc { target } => self.execute_rlc(target),
+            InstructionType::Rlca => self.execute_rlca(),
+            InstructionType::Rr { target } => self.execute_rr(target),
+            InstructionType::Rra => self.execute_rra(),
+            InstructionType::Rrc { target } => self.execute_rrc(target),
+            InstructionType::Rrca => self.execute_rrca(),
+            InstructionType::Rst { offset } => self.execute_rst(offset),
+            InstructionType::Sbc {
+                source,
+                destination,
+            } => self.execute_sbc(source, destination),
+            InstructionType::Scf => self.execute_scf(),
+            InstructionType::Set { target, bit } => self.execute_set(target, bit),
+            InstructionType::Sla { target } => self.execute_sla(target),
+            InstructionType::Sra { target } => self.execute_sra(target),
+            InstructionType::Srl { target } => self.execute_srl(target),
+            InstructionType::Stop => self.execute_stop(),
+            InstructionType::Sub { source } => self.execute_sub(source),
+            InstructionType::Swap { target } => self.execute_swap(target),
+            InstructionType::Xor { source } => self.execute_xor(source),
+        }
+    }
+
+    fn handle_interrupt(&mut self, interrupt_type: InterruptType) {
+        self.delay_m_cycle(); // interrupt wait state (CPU likely executing NOPs)
+        self.delay_m_cycle(); // interrupt wait state (CPU likely executing NOPs)
+
+        let [pc_msb, pc_lsb] = self.pc.to_be_bytes();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(pc_msb, AddressingModeByte::LiteralIndirect(self.sp));
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(pc_lsb, AddressingModeByte::LiteralIndirect(self.sp));
+
+        self.delay_m_cycle(); // set PC?
+        self.pc = match interrupt_type {
+            InterruptType::VBlank => 0x40,
+            InterruptType::LcdStat => 0x48,
+            InterruptType::Timer => 0x50,
+            InterruptType::Serial => 0x58,
+            InterruptType::Joypad => 0x60,
+        };
+        self.last_interrupt = Some(interrupt_type);
+        self.last_dispatched = Some(interrupt_type);
+    }
+}
+
+impl Cpu {
+    fn execute_add_byte(&mut self, source: AddressingModeByte, destination: AddressingModeByte) {
+        let source_value = self.read_byte(source);
+        let destination_value = self.read_byte(destination);
+        let (result, carry_out) = destination_value.overflowing_add(source_value);
+        let half_carry =
+            (((source_value & 0b0000_1111) + (destination_value & 0b0000_1111)) & 0b0001_0000) != 0;
+        self.write_byte(result, destination);
+
+        self.set_zero_flag(result == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(half_carry);
+        self.set_carry_flag(carry_out);
+    }
+
+    fn execute_add_hl(&mut self, source: AddressingModeWord) {
+        let source_value = self.read_word(source);
+        let destination_value = self.read_word(AddressingModeWord::Hl);
+
+        // Takes extra cycle for the add to propogate to upper byte
+        self.delay_m_cycle();
+        let (result, carry_out) = destination_value.overflowing_add(source_value);
+        self.write_word(result, AddressingModeWord::Hl);
+
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(
+            (((source_value & 0b0000_1111_1111_1111)
+                + (destination_value & 0b0000_1111_1111_1111))
+                & 0b0001_0000_0000_0000)
+                != 0,
+        );
+        self.set_carry_flag(carry_out);
+    }
+
+    fn execute_add_sp(&mut self, value: i8) {
+        let destination_value = self.read_word(AddressingModeWord::Sp);
+
+        // Takes extra cycle for add to propgate to upper byte.
+        self.delay_m_cycle();
+        let result = destination_value.wrapping_add(i16::from(value) as u16);
+
+        // internal (likely delay when writing to sp).
+        self.delay_m_cycle();
+        self.write_word(result, AddressingModeWord::Sp);
+
+        // sp += value uses value as a signed value (and negative value correctly
+        // affects the entire sp, including carry in from upper byte).
+        //
+        // Flags are only set from the addition of value to the lower byte of sp.
+        // This means that half-carry flag is set if carry from bit 3 -> 4, and
+        // carry flag is set if carry out from bit 7. High byte of sp is ignored
+        // for both half-carry and carry flags.
+        self.set_zero_flag(false);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(
+            ((((value as u8) & 0b0000_1111) + ((destination_value as u8) & 0b0000_1111))
+                & 0b0001_0000)
+                != 0,
+        );
+        let (_, carry_out) = (destination_value as u8).overflowing_add(value as u8);
+        self.set_carry_flag(carry_out);
+    }
+
+    fn execute_adc(&mut self, source: AddressingModeByte, destination: AddressingModeByte) {
+        let source_value = self.read_byte(source);
+        let destination_value = self.read_byte(destination);
+        let (result, half_carry, carry) = if self.get_carry_flag() {
+            let (intermediate_result, carry_one) = source_value.overflowing_add(destination_value);
+            let (result, carry_two) = intermediate_result.overflowing_add(1);
+            let half_carry =
+                (((source_value & 0b0000_1111) + (destination_value & 0b0000_1111) + 1)
+                    & 0b0001_0000)
+                    != 0;
+
+            (result, half_carry, carry_one | carry_two)
+        } else {
+            let (result, carry) = source_value.overflowing_add(destination_value);
+            let half_carry = (((source_value & 0b0000_1111) + (destination_value & 0b0000_1111))
+                & 0b0001_0000)
+                != 0;
+
+            (result, half_carry, carry)
+        };
+
+        self.write_byte(result, destination);
+
+        self.set_zero_flag(result == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(half_carry);
+        self.set_carry_flag(carry);
+    }
+
+    fn execute_and(&mut self, source: AddressingModeByte) {
+        let source_value = self.read_byte(source) as u8;
+        let destination_value = self.read_byte(AddressingModeByte::Accumulator) & source_value;
+        self.write_byte(destination_value, AddressingModeByte::Accumulator);
+
+        self.set_zero_flag(destination_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(true);
+        self.set_carry_flag(false);
+    }
+
+    fn execute_bit(&mut self, target: AddressingModeByte, bit: u8) {
+        let source_value = self.read_byte(target);
+
+        self.set_zero_flag((source_value & (1 << bit)) == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(true);
+    }
+
+    fn execute_call(&mut self, address: AddressingModeWord, condition: BranchConditionType) {
+        let call_address = self.read_word(address);
+
+        if self.should_branch(condition) {
+            self.delay_m_cycle(); // internal
+
+            let [pc_msb, pc_lsb] = self.pc.to_be_bytes();
+            self.sp = self.sp.wrapping_sub(1);
+            self.write_byte(pc_msb, AddressingModeByte::LiteralIndirect(self.sp));
+            self.sp = self.sp.wrapping_sub(1);
+            self.write_byte(pc_lsb, AddressingModeByte::LiteralIndirect(self.sp));
+
+            self.pc = call_address;
+        }
+    }
+
+    fn execute_inc_byte(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        let new_value = old_value.wrapping_add(1);
+        self.write_byte(new_value, target);
+
+        self.set_zero_flag(new_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag((old_value & 0b0001_0000) != (new_value & 0b0001_0000));
+    }
+
+    fn execute_inc_word(&mut self, target: AddressingModeWord) {
+        let old_value = self.read_word(target);
+
+        // Takes extra cycle for add to propogate to upper byte.
+        self.delay_m_cycle();
+        self.bus.trigger_oam_bug(old_value, OamCorruption::Write);
+        let new_value = old_value.wrapping_add(1);
+        self.write_word(new_value, target);
+    }
+
+    fn execute_ccf(&mut self) {
+        let old_carry_flag = self.get_carry_flag();
+        let new_carry_flag = !old_carry_flag;
+
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag(new_carry_flag);
+    }
+
+    fn execute_cp(&mut self, source: AddressingModeByte) {
+        let source_value = self.read_byte(source);
+        let accumulator_value = self.read_byte(AddressingModeByte::Accumulator);
+
+        self.set_zero_flag(source_value == accumulator_value);
+        self.set_subtract_flag(true);
+        self.set_half_carry_flag((accumulator_value & 0b0000_1111) < (source_value & 0b0000_1111));
+        self.set_carry_flag(accumulator_value < source_value);
+    }
+
+    fn execute_cpl(&mut self) {
+        let source_value = self.read_byte(AddressingModeByte::Accumulator);
+        self.write_byte(!source_value, AddressingModeByte::Accumulator);
+
+        self.set_subtract_flag(true);
+        self.set_half_carry_flag(true);
+    }
+
+    fn execute_daa(&mut self) {
+        let source_value = self.read_byte(AddressingModeByte::Accumulator);
+        let mut result_value = source_value;
+
+        if self.get_subtract_flag() {
+            if self.get_carry_flag() {
+                result_value = result_value.wrapping_sub(0x60);
+            }
+
+            if self.get_half_carry_flag() {
+                result_value = result_value.wrapping_sub(0x06);
+            }
+        } else {
+            if self.get_carry_flag() || (source_value > 0x99) {
+                result_value = result_value.wrapping_add(0x60);
+                self.set_carry_flag(true);
+            }
+
+            if self.get_half_carry_flag() || ((source_value & 0x0F) > 0x09) {
+                result_value = result_value.wrapping_add(0x06);
+            }
+        }
+
+        self.write_byte(result_value, AddressingModeByte::Accumulator);
+
+        self.set_zero_flag(result_value == 0);
+        self.set_half_carry_flag(false);
+        // carry flag already (possibly) set above
+    }
+
+    fn execute_dec_byte(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        let new_value = old_value.wrapping_sub(1);
+        self.write_byte(new_value, target);
+
+        self.set_zero_flag(new_value == 0);
+        self.set_subtract_flag(true);
+        self.set_half_carry_flag((old_value & 0b0001_0000) != (new_value & 0b0001_0000));
+    }
+
+    fn execute_dec_word(&mut self, target: AddressingModeWord) {
+        let old_value = self.read_word(target);
+
+        // Takes extra cycle for dec to propogate to upper byte.
+        self.delay_m_cycle();
+        self.bus.trigger_oam_bug(old_value, OamCorruption::Write);
+        let new_value = old_value.wrapping_sub(1);
+        self.write_word(new_value, target);
+    }
+
+    fn execute_di(&mut self) {
+        self.ime_enable_pending = false;
+        self.bus.set_interrupt_master_enable(false);
+    }
+
+    fn execute_ei(&mut self) {
+        self.ime_enable_pending = true;
+    }
+
+    fn execute_halt(&mut self) {
+        self.halted = true;
+    }
+
+    fn execute_ld_byte(&mut self, source: AddressingModeByte, destination: AddressingModeByte) {
+        let value = self.read_byte(source);
+        self.write_byte(value, destination);
+    }
+
+    fn execute_ld_sp(&mut self, source: AddressingModeWord) {
+        let value = self.read_word(source);
+
+        // internal (likely delay when writing to sp).
+        self.delay_m_cycle();
+        self.write_word(value, AddressingModeWord::Sp);
+    }
+
+    fn execute_ld_word(&mut self, source: AddressingModeWord, destination: AddressingModeWord) {
+        let value = self.read_word(source);
+        self.write_word(value, destination);
+    }
+
+    fn execute_ldhl(&mut self, source: AddressingModeWord, offset: i8) {
+        let source_value = self.read_word(source);
+
+        // internal (likely waiting for add to propogate to upper byte).
+        self.delay_m_cycle();
+        let result_value = source_value.wrapping_add(i16::from(offset) as u16);
+
+        self.write_word(result_value, AddressingModeWord::Hl);
+
+        // sp + offset uses offset as a signed value (and negative value correctly
+        // affects the entire sp, including carry in from upper byte).
+        //
+        // Flags are only set from the addition of offset to the lower byte of sp.
+        // This means that half-carry flag is set if carry from bit 3 -> 4, and
+        // carry flag is set if carry out from bit 7. High byte of sp is ignored
+        // for both half-carry and carry flags.
+        self.set_zero_flag(false);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(
+            ((((source_value as u8) & 0b0000_1111) + ((offset as u8) & 0b0000_1111)) & 0b0001_0000)
+                != 0,
+        );
+        let (_, carry_out) = (source_value as u8).overflowing_add(offset as u8);
+        self.set_carry_flag(carry_out);
+    }
+
+    fn execute_jp(&mut self, target: AddressingModeWord, condition: BranchConditionType) {
+        let new_address = self.read_word(target);
+
+        if self.should_branch(condition) {
+            self.delay_m_cycle();
+            self.pc = new_address;
+        }
+    }
+
+    fn execute_jp_hl(&mut self) {
+        let new_address = self.read_word(AddressingModeWord::Hl);
+        self.pc = new_address;
+    }
+
+    fn execute_jr(&mut self, unsigned_offset: AddressingModeByte, condition: BranchConditionType) {
+        let signed_offset = self.read_byte(unsigned_offset) as i8;
+        if self.should_branch(condition) {
+            // Signed numbers are stored as 2's complement. Wrapping add after
+            // casting to unsigned has same effect as wrapping add of signed to
+            // unsigned.
+            self.delay_m_cycle(); // internal modify PC
+            self.pc = self.pc.wrapping_add(signed_offset as u16);
+        }
+    }
+
+    fn execute_or(&mut self, source: AddressingModeByte) {
+        let source_value = self.read_byte(source);
+        let destination_value = self.read_byte(AddressingModeByte::Accumulator);
+        let result_value = source_value | destination_value;
+        self.write_byte(result_value, AddressingModeByte::Accumulator);
+
+        self.set_zero_flag(result_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag(false);
+    }
+
+    fn execute_pop(&mut self, target: AddressingModeWord) {
+        let value_lsb = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
+        self.sp = self.sp.wrapping_add(1);
+        let value_msb = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
+        self.sp = self.sp.wrapping_add(1);
+
+        let value = u16::from_be_bytes([value_msb, value_lsb]);
+        self.write_word(value, target);
+    }
+
+    fn execute_push(&mut self, source: AddressingModeWord) {
+        let value = self.read_word(source);
+        let [value_msb, value_lsb] = value.to_be_bytes();
+
+        self.delay_m_cycle(); // internal
+        self.bus.trigger_oam_bug(self.sp, OamCorruption::Write);
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(value_msb, AddressingModeByte::LiteralIndirect(self.sp));
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(value_lsb, AddressingModeByte::LiteralIndirect(self.sp));
+    }
+
+    fn execute_res(&mut self, target: AddressingModeByte, bit: u8) {
+        let source_value = self.read_byte(target);
+        let result_value = source_value & !(1 << bit);
+        self.write_byte(result_value, target);
+    }
+
+    fn execute_ret(&mut self, condition: BranchConditionType) {
+        if matches!(condition, BranchConditionType::Unconditional) {
+            let lsb_return_address = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
+            self.sp = self.sp.wrapping_add(1);
+            let msb_return_address = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
+            self.sp = self.sp.wrapping_add(1);
+
+            let return_address = u16::from_be_bytes([msb_return_address, lsb_return_address]);
+
+            self.delay_m_cycle(); // set PC?
+            self.pc = return_address;
+        } else {
+            self.delay_m_cycle(); // branch decision?
+            if self.should_branch(condition) {
+                let lsb_return_address =
+                    self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
+                self.sp = self.sp.wrapping_add(1);
+                let msb_return_address =
+                    self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
+                self.sp = self.sp.wrapping_add(1);
+
+                let return_address = u16::from_be_bytes([msb_return_address, lsb_return_address]);
+
+                self.delay_m_cycle(); // set PC?
+                self.pc = return_address;
+            }
+        }
+    }
+
+    fn execute_reti(&mut self) {
+        let lsb_return_address = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
+        self.sp = self.sp.wrapping_add(1);
+        let msb_return_address = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
+        self.sp = self.sp.wrapping_add(1);
+
+        let return_address = u16::from_be_bytes([msb_return_address, lsb_return_address]);
+
+        self.delay_m_cycle(); // set PC?
+        self.pc = return_address;
+
+        self.bus.set_interrupt_master_enable(true);
+    }
+
+    fn execute_rl(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        let new_value = (old_value << 1) | (self.get_carry_flag() as u8);
+        self.write_byte(new_value, target);
+
+        self.set_zero_flag(new_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_value & 0b1000_0000) != 0);
+    }
+
+    fn execute_rla(&mut self) {
+        let old_accumulator = self.read_byte(AddressingModeByte::Accumulator);
+        let new_accumulator = (old_accumulator << 1) | (self.get_carry_flag() as u8);
+        self.write_byte(new_accumulator, AddressingModeByte::Accumulator);
+
+        // The manual states that the zero flag is set when the result is zero, but
+        // other documentation states that the zero flag is unconditionally reset.
+        //
+        // The zero flag being unconditionally reset passes blargg's cpu tests
+        // (whereas conditionally setting the zero flag fails).
+        self.set_zero_flag(false);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_accumulator & 0b1000_0000) != 0);
+    }
+
+    fn execute_rlc(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        let new_value = old_value.rotate_left(1);
+        self.write_byte(new_value, target);
+
+        self.set_zero_flag(new_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_value & 0b1000_0000) != 0);
+    }
+
+    fn execute_rlca(&mut self) {
+        let old_accumulator = self.read_byte(AddressingModeByte::Accumulator);
+        let new_accumulator = old_accumulator.rotate_left(1);
+        self.write_byte(new_accumulator, AddressingModeByte::Accumulator);
+
+        // The manual states that the zero flag is set when the result is zero, but
+        // other documentation states that the zero flag is unconditionally reset.
+        //
+        // The zero flag being unconditionally reset passes blargg's cpu tests
+        // (whereas conditionally setting the zero flag fails).
+        self.set_zero_flag(false);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_accumulator & 0b1000_0000) != 0);
+    }
+
+    fn execute_rr(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        let new_value = (old_value >> 1) | (self.get_carry_flag() as u8).rotate_right(1);
+        self.write_byte(new_value, target);
+
+        self.set_zero_flag(new_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_value & 0b0000_0001) != 0);
+    }
+
+    fn execute_rra(&mut self) {
+        let old_accumulator = self.read_byte(AddressingModeByte::Accumulator);
+        let new_accumulator =
+            (old_accumulator >> 1) | (self.get_carry_flag() as u8).rotate_right(1);
+        self.write_byte(new_accumulator, AddressingModeByte::Accumulator);
+
+        // The manual states that the zero flag is set when the result is zero, but
+        // other documentation states that the zero flag is unconditionally reset.
+        //
+        // The zero flag being unconditionally reset passes blargg's cpu tests
+        // (whereas conditionally setting the zero flag fails).
+        self.set_zero_flag(false);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_accumulator & 0b0000_0001) != 0);
+    }
+
+    fn execute_rrc(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        let new_value = old_value.rotate_right(1);
+        self.write_byte(new_value, target);
+
+        self.set_zero_flag(new_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_value & 0b0000_0001) != 0);
+    }
+
+    fn execute_rrca(&mut self) {
+        let old_accumulator = self.read_byte(AddressingModeByte::Accumulator);
+        let new_accumulator = old_accumulator.rotate_right(1);
+        self.write_byte(new_accumulator, AddressingModeByte::Accumulator);
+
+        // The manual states that the zero flag is set when the result is zero, but
+        // other documentation states that the zero flag is unconditionally reset.
+        //
+        // The zero flag being unconditionally reset passes blargg's cpu tests
+        // (whereas conditionally setting the zero flag fails).
+        self.set_zero_flag(false);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_accumulator & 0b0000_0001) != 0);
+    }
+
+    fn execute_rst(&mut self, offset: u16) {
+        self.delay_m_cycle(); // internal
+
+        let [pc_msb, pc_lsb] = self.pc.to_be_bytes();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(pc_msb, AddressingModeByte::LiteralIndirect(self.sp));
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_byte(pc_lsb, AddressingModeByte::LiteralIndirect(self.sp));
+
+        self.pc = offset;
+    }
+
+    // Some gameboy documentation has carry/half-carry documentation backwards for this op.
+    // Carry and half-carry flags are set when there is a borrow-in to bit 7 for carry flag,
+    // or borrow-in to bit 3 for half-carry flag, respectively.
+    fn execute_sbc(&mut self, source: AddressingModeByte, destination: AddressingModeByte) {
+        let source_value = self.read_byte(source);
+        let destination_value = self.read_byte(destination);
+
+        let (result, half_carry, carry) = if self.get_carry_flag() {
+            let (intermediate_result, borrow_one) = destination_value.overflowing_sub(source_value);
+            let (result, borrow_two) = intermediate_result.overflowing_sub(1);
+            let half_borrow =
+                (destination_value & 0b0000_1111) < ((source_value & 0b0000_1111) + 1);
+
+            (result, half_borrow, borrow_one | borrow_two)
+        } else {
+            let (result, borrow) = destination_value.overflowing_sub(source_value);
+            let half_borrow = (destination_value & 0b0000_1111) < (source_value & 0b0000_1111);
+
+            (result, half_borrow, borrow)
+        };
+
+        self.write_byte(result, destination);
+
+        self.set_zero_flag(result == 0);
+        self.set_subtract_flag(true);
+        self.set_half_carry_flag(half_carry);
+        self.set_carry_flag(carry);
+    }
+
+    fn execute_scf(&mut self) {
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag(true);
+    }
+
+    fn execute_set(&mut self, target: AddressingModeByte, bit: u8) {
+        let old_value = self.read_byte(target);
+        let result_value = old_value | (1 << bit);
+        self.write_byte(result_value, target);
+    }
+
+    fn execute_sla(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        let result_value = old_value << 1;
+        self.write_byte(result_value, target);
+
+        self.set_zero_flag(result_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_value & 0b1000_0000) != 0);
+    }
+
+    fn execute_sra(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        // Signed right shift performs sign extension.
+        let result_value = ((old_value as i8) >> 1) as u8;
+        self.write_byte(result_value, target);
+
+        self.set_zero_flag(result_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_value & 0b0000_0001) != 0);
+    }
+
+    fn execute_srl(&mut self, target: AddressingModeByte) {
+        let old_value = self.read_byte(target);
+        // Signed right shift performs sign extension.
+        let result_value = old_value >> 1;
+        self.write_byte(result_value, target);
+
+        self.set_zero_flag(result_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag((old_value & 0b0000_0001) != 0);
+    }
+
+    // Unless the bus handles it as a speed switch, STOP stops the system clock
+    // and blanks the screen until a button press pulls a selected joypad line
+    // low. If one is already low, STOP doesn't stop at all.
+    fn execute_stop(&mut self) {
+        if self.bus.maybe_handle_stop() || self.bus.joypad.read() & 0x0F != 0x0F {
+            return;
+        }
+
+        self.stopped = true;
+        self.bus.timer.set_divider_register(0);
+        self.bus.ppu.blank_screen();
+    }
+
+    // Some gameboy documentation has carry/half-carry documentation backwards for this op.
+    // Carry and half-carry flags are set when there is a borrow-in to bit 7 for carry flag,
+    // or borrow-in to bit 3 for half-carry flag, respectively.
+    fn execute_sub(&mut self, source: AddressingModeByte) {
+        let source_value = self.read_byte(source);
+        let destination_value = self.read_byte(AddressingModeByte::Accumulator);
+        let (result_value, carry_in) = destination_value.overflowing_sub(source_value);
+        let half_carry_in = (destination_value & 0b0000_1111) < (source_value & 0b0000_1111);
+
+        self.write_byte(result_value, AddressingModeByte::Accumulator);
+
+        self.set_zero_flag(result_value == 0);
+        self.set_subtract_flag(true);
+        self.set_half_carry_flag(half_carry_in);
+        self.set_carry_flag(carry_in);
+    }
+    fn execute_swap(&mut self, target: AddressingModeByte) {
+        let source_value = self.read_byte(target);
+        // Original low nibble will be shifted out when shifting right, and likewise,
+        // original high nibble will be shifted out when shifting left.
+        let result_value = (source_value >> 4) | (source_value << 4);
+        self.write_byte(result_value, target);
+
+        self.set_zero_flag(result_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag(false);
+    }
+
+    fn execute_xor(&mut self, source: AddressingModeByte) {
+        let source_value = self.read_byte(source);
+        let result_value = self.read_byte(AddressingModeByte::Accumulator) ^ source_value;
+        self.write_byte(result_value, AddressingModeByte::Accumulator);
+
+        self.set_zero_flag(result_value == 0);
+        self.set_subtract_flag(false);
+        self.set_half_carry_flag(false);
+        self.set_carry_flag(false);
+    }
+
+    fn should_branch(&self, condition: BranchConditionType) -> bool {
+        match condition {
+            BranchConditionType::NotZero => !self.get_zero_flag(),
+            BranchConditionType::NotCarry => !self.get_carry_flag(),
+            BranchConditionType::Zero => self.get_zero_flag(),
+            BranchConditionType::Carry => self.get_carry_flag(),
+            BranchConditionType::Unconditional => true,
+        }
+    }
+}
+
+impl Cpu {
+    const ZERO_FLAG_MASK: u16 = 0b00000000_1000_0000;
+    const SUBTRACT_FLAG_MASK: u16 = 0b00000000_0100_0000;
+    const HALF_CARRY_FLAG_MASK: u16 = 0b00000000_0010_0000;
+    const CARRY_FLAG_MASK: u16 = 0b00000000_0001_0000;
+
+    fn get_zero_flag(&self) -> bool {
+        (self.af & Self::ZERO_FLAG_MASK) != 0
+    }
+
+    fn get_subtract_flag(&self) -> bool {
+        (self.af & Self::SUBTRACT_FLAG_MASK) != 0
+    }
+
+    fn get_half_carry_flag(&self) -> bool {
+        (self.af & Self::HALF_CARRY_FLAG_MASK) != 0
+    }
+
+    fn get_carry_flag(&self) -> bool {
+        (self.af & Self::CARRY_FLAG_MASK) != 0
+    }
+
+    fn set_zero_flag(&mut self, set: bool) {
+        if set {
+            self.af |= Self::ZERO_FLAG_MASK;
+        } else {
+            self.af &= !Self::ZERO_FLAG_MASK;
+        }
+    }
+
+    fn set_subtract_flag(&mut self, set: bool) {
+        if set {
+            self.af |= Self::SUBTRACT_FLAG_MASK;
+        } else {
+            self.af &= !Self::SUBTRACT_FLAG_MASK;
+        }
+    }
+
+    fn set_half_carry_flag(&mut self, set: bool) {
+        if set {
+            self.af |= Self::HALF_CARRY_FLAG_MASK;
+        } else {
+            self.af &= !Self::HALF_CARRY_FLAG_MASK;
+        }
+    }
+
+    fn set_carry_flag(&mut self, set: bool) {
+        if set {
+            self.af |= Self::CARRY_FLAG_MASK;
+        } else {
+            self.af &= !Self::CARRY_FLAG_MASK;
+        }
+    }
+}
 
-                let target = get_addressing_mode((opcode & 0b00111000) >> 3);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{IoAccess, OPEN_BUS};
+    use crate::ppu::{PaletteColorRgb555, PpuRenderStatus};
 
-                self.pc += 1;
-                let instruction_type = match opcode & 0b00000111 {
-                    0b100 => InstructionType::IncByte { target },
-                    0b101 => InstructionType::DecByte { target },
-                    _ => unreachable!(),
-                };
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
-                Instruction { instruction_type }
-            }
-            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
-                fn get_addressing_mode(val: u8) -> AddressingModeByte {
-                    match val {
-                        0b000 => AddressingModeByte::B,
-                        0b001 => AddressingModeByte::C,
-                        0b010 => AddressingModeByte::D,
-                        0b011 => AddressingModeByte::E,
-                        0b100 => AddressingModeByte::H,
-                        0b101 => AddressingModeByte::L,
-                        0b110 => AddressingModeByte::HlIndirect,
-                        0b111 => AddressingModeByte::Accumulator,
-                        _ => unreachable!(),
-                    }
-                }
-                let r = get_addressing_mode((opcode & 0b00111000) >> 3);
-                let n = self.read_byte(AddressingModeByte::LiteralIndirect(self.pc + 1));
+    fn test_cpu() -> Cpu {
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        Cpu::new(cartridge)
+    }
 
-                self.pc += 2;
-                Instruction {
-                    instruction_type: InstructionType::LdByte {
-                        source: AddressingModeByte::Literal(n),
-                        destination: r,
-                    },
-                }
-            }
-            0x07 => {
-                self.pc += 1;
+    // Boots dmg-acid2 with `code` patched in where its header jumps to, either
+    // on a DMG, through a stand-in for its boot ROM, or on a CGB through the
+    // built in boot ROM. Returns once the boot ROM has handed over.
+    fn boot_dmg_cartridge(code: &[u8], dmg_hardware: bool) -> Cpu {
+        let mut rom = include_bytes!("../tests/dmg_acid2.gb").to_vec();
+        rom[0x150..0x150 + code.len()].copy_from_slice(code);
+        let cartridge = Cartridge::new(&rom).unwrap();
+        let mut cpu = if dmg_hardware {
+            // ld a, $91; ldh ($40), a; ld a, $01; jp $00fe; ...; ldh ($50), a
+            let mut boot_rom = [0; 0x100];
+            boot_rom[..9].copy_from_slice(&[0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x01, 0xC3, 0xFE, 0x00]);
+            boot_rom[0xFE..].copy_from_slice(&[0xE0, 0x50]);
+            Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap()
+        } else {
+            Cpu::new(cartridge)
+        };
+        while cpu.pc != 0x150 {
+            cpu.fetch_decode_execute();
+        }
+        cpu
+    }
+
+    // The decoder from before the opcode tables, kept to check them against.
+    fn decode_with_match(address: u16, mut read: impl FnMut(u16) -> u8) -> (Instruction, u16) {
+        let length;
+        let opcode = read(address);
+
+        let instruction = match opcode {
+            0x00 => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Rlca,
+                    instruction_type: InstructionType::Nop,
                 }
             }
-            0x08 => {
-                let destination_address =
-                    self.read_word(AddressingModeWord::LiteralIndirect(self.pc + 1));
-                let destination = AddressingModeWord::LiteralIndirect(destination_address);
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                let source_value = u16::from_le_bytes([
+                    read(address.wrapping_add(1)),
+                    read(address.wrapping_add(2)),
+                ]);
+                let source = AddressingModeWord::Literal(source_value);
+                let destination = match (opcode & 0b00110000) >> 4 {
+                    0b00 => AddressingModeWord::Bc,
+                    0b01 => AddressingModeWord::De,
+                    0b10 => AddressingModeWord::Hl,
+                    0b11 => AddressingModeWord::Sp,
+                    _ => unreachable!(),
+                };
 
-                self.pc += 3;
+                length = 3;
                 Instruction {
                     instruction_type: InstructionType::LdWord {
-                        source: AddressingModeWord::Sp,
+                        source,
                         destination,
                     },
                 }
             }
-            0x09 | 0x19 | 0x29 | 0x39 => {
-                let source = match opcode {
-                    0x09 => AddressingModeWord::Bc,
-                    0x19 => AddressingModeWord::De,
-                    0x29 => AddressingModeWord::Hl,
-                    0x39 => AddressingModeWord::Sp,
+            0x02 | 0x12 | 0x22 | 0x32 => {
+                let source = AddressingModeByte::Accumulator;
+                let destination = match (opcode & 0b00110000) >> 4 {
+                    0b00 => AddressingModeByte::BcIndirect,
+                    0b01 => AddressingModeByte::DeIndirect,
+                    0b10 => AddressingModeByte::HlIndirectIncrement,
+                    0b11 => AddressingModeByte::HlIndirectDecrement,
                     _ => unreachable!(),
                 };
 
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::AddHl { source },
-                }
-            }
-            0x0A | 0x1A | 0x2A | 0x3A => {
-                fn get_addressing_mode(val: u8) -> AddressingModeByte {
-                    match val {
-                        0b00 => AddressingModeByte::BcIndirect,
-                        0b01 => AddressingModeByte::DeIndirect,
-                        0b10 => AddressingModeByte::HlIndirectIncrement,
-                        0b11 => AddressingModeByte::HlIndirectDecrement,
-                        _ => unreachable!(),
-                    }
-                }
-
-                let source = get_addressing_mode((opcode & 0b00110000) >> 4);
-                let destination = AddressingModeByte::Accumulator;
-
-                self.pc += 1;
+                length = 1;
                 Instruction {
                     instruction_type: InstructionType::LdByte {
                         source,
@@ -779,99 +2807,54 @@ impl Cpu {
                     },
                 }
             }
-            0x0F => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Rrca,
-                }
-            }
-            0x10 => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Stop,
-                }
-            }
-            0x17 => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Rla,
+            0x03 | 0x0B | 0x13 | 0x1B | 0x23 | 0x2B | 0x33 | 0x3B => {
+                fn get_addressing_mode(val: u8) -> AddressingModeWord {
+                    match val {
+                        0b00 => AddressingModeWord::Bc,
+                        0b01 => AddressingModeWord::De,
+                        0b10 => AddressingModeWord::Hl,
+                        0b11 => AddressingModeWord::Sp,
+                        _ => unreachable!(),
+                    }
                 }
-            }
-            0x18 => {
-                let unsigned_offset_value =
-                    self.read_byte(AddressingModeByte::LiteralIndirect(self.pc + 1));
-                let unsigned_offset = AddressingModeByte::Literal(unsigned_offset_value);
 
-                self.pc += 2;
-                Instruction {
-                    instruction_type: InstructionType::Jr {
-                        unsigned_offset,
-                        condition: BranchConditionType::Unconditional,
-                    },
-                }
-            }
-            0x1F => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Rra,
-                }
+                let target = get_addressing_mode((opcode & 0b00110000) >> 4);
+                length = 1;
+                let instruction_type = match (opcode & 0b00001100) >> 2 {
+                    0b00 => InstructionType::IncWord { target },
+                    0b10 => InstructionType::DecWord { target },
+                    _ => unreachable!(),
+                };
+                Instruction { instruction_type }
             }
-            0x20 | 0x28 | 0x30 | 0x38 => {
-                fn get_branch_condition_type(val: u8) -> BranchConditionType {
+            0x04 | 0x05 | 0x0C | 0x0D | 0x14 | 0x15 | 0x1C | 0x1D | 0x24 | 0x25 | 0x2C | 0x2D
+            | 0x34 | 0x35 | 0x3C | 0x3D => {
+                fn get_addressing_mode(val: u8) -> AddressingModeByte {
                     match val {
-                        0b100 => BranchConditionType::NotZero,
-                        0b101 => BranchConditionType::Zero,
-                        0b110 => BranchConditionType::NotCarry,
-                        0b111 => BranchConditionType::Carry,
+                        0b000 => AddressingModeByte::B,
+                        0b001 => AddressingModeByte::C,
+                        0b010 => AddressingModeByte::D,
+                        0b011 => AddressingModeByte::E,
+                        0b100 => AddressingModeByte::H,
+                        0b101 => AddressingModeByte::L,
+                        0b110 => AddressingModeByte::HlIndirect,
+                        0b111 => AddressingModeByte::Accumulator,
                         _ => unreachable!(),
                     }
                 }
 
-                let condition = get_branch_condition_type((opcode & 0b00111000) >> 3);
-
-                let unsigned_offset_value =
-                    self.read_byte(AddressingModeByte::LiteralIndirect(self.pc + 1));
-                let unsigned_offset = AddressingModeByte::Literal(unsigned_offset_value);
+                let target = get_addressing_mode((opcode & 0b00111000) >> 3);
 
-                self.pc += 2;
+                length = 1;
+                let instruction_type = match opcode & 0b00000111 {
+                    0b100 => InstructionType::IncByte { target },
+                    0b101 => InstructionType::DecByte { target },
+                    _ => unreachable!(),
+                };
 
-                Instruction {
-                    instruction_type: InstructionType::Jr {
-                        unsigned_offset,
-                        condition,
-                    },
-                }
-            }
-            0x27 => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Daa,
-                }
-            }
-            0x2F => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Cpl,
-                }
-            }
-            0x37 => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Scf,
-                }
-            }
-            0x3F => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Ccf,
-                }
+                Instruction { instruction_type }
             }
-            0x40 | 0x41 | 0x42 | 0x43 | 0x44 | 0x45 | 0x46 | 0x47 | 0x48 | 0x49 | 0x4A | 0x4B
-            | 0x4C | 0x4D | 0x4E | 0x4F | 0x50 | 0x51 | 0x52 | 0x53 | 0x54 | 0x55 | 0x56 | 0x57
-            | 0x58 | 0x59 | 0x5A | 0x5B | 0x5C | 0x5D | 0x5E | 0x5F | 0x60 | 0x61 | 0x62 | 0x63
-            | 0x64 | 0x65 | 0x66 | 0x67 | 0x68 | 0x69 | 0x6A | 0x6B | 0x6C | 0x6D | 0x6E | 0x6F
-            | 0x70 | 0x71 | 0x72 | 0x73 | 0x74 | 0x75 | 0x77 | 0x78 | 0x79 | 0x7A | 0x7B | 0x7C
-            | 0x7D | 0x7E | 0x7F => {
+            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
                 fn get_addressing_mode(val: u8) -> AddressingModeByte {
                     match val {
                         0b000 => AddressingModeByte::B,
@@ -885,344 +2868,183 @@ impl Cpu {
                         _ => unreachable!(),
                     }
                 }
+                let r = get_addressing_mode((opcode & 0b00111000) >> 3);
+                let n = read(address.wrapping_add(1));
 
-                let source = get_addressing_mode(opcode & 0b00000111);
-                let destination = get_addressing_mode((opcode & 0b00111000) >> 3);
-
-                self.pc += 1;
+                length = 2;
                 Instruction {
                     instruction_type: InstructionType::LdByte {
-                        source,
-                        destination,
+                        source: AddressingModeByte::Literal(n),
+                        destination: r,
                     },
                 }
             }
-            0x76 => {
-                self.pc += 1;
+            0x07 => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Halt,
+                    instruction_type: InstructionType::Rlca,
                 }
             }
-            0x80 | 0x81 | 0x82 | 0x83 | 0x84 | 0x85 | 0x86 | 0x87 | 0x88 | 0x89 | 0x8A | 0x8B
-            | 0x8C | 0x8D | 0x8E | 0x8F | 0x90 | 0x91 | 0x92 | 0x93 | 0x94 | 0x95 | 0x96 | 0x97
-            | 0x98 | 0x99 | 0x9A | 0x9B | 0x9C | 0x9D | 0x9E | 0x9F | 0xA0 | 0xA1 | 0xA2 | 0xA3
-            | 0xA4 | 0xA5 | 0xA6 | 0xA7 | 0xA8 | 0xA9 | 0xAA | 0xAB | 0xAC | 0xAD | 0xAE | 0xAF
-            | 0xB0 | 0xB1 | 0xB2 | 0xB3 | 0xB4 | 0xB5 | 0xB6 | 0xB7 | 0xB8 | 0xB9 | 0xBA | 0xBB
-            | 0xBC | 0xBD | 0xBE | 0xBF => {
-                let source = match opcode & 0b00000111 {
-                    0b000 => AddressingModeByte::B,
-                    0b001 => AddressingModeByte::C,
-                    0b010 => AddressingModeByte::D,
-                    0b011 => AddressingModeByte::E,
-                    0b100 => AddressingModeByte::H,
-                    0b101 => AddressingModeByte::L,
-                    0b110 => AddressingModeByte::HlIndirect,
-                    0b111 => AddressingModeByte::Accumulator,
-                    _ => unreachable!(),
-                };
-
-                let instruction_type = match (opcode & 0b00111000) >> 3 {
-                    0b000 => InstructionType::AddByte {
-                        source,
-                        destination: AddressingModeByte::Accumulator,
-                    },
-                    0b001 => InstructionType::Adc {
-                        source,
-                        destination: AddressingModeByte::Accumulator,
-                    },
-                    0b010 => InstructionType::Sub { source },
-                    0b011 => InstructionType::Sbc {
-                        source,
-                        destination: AddressingModeByte::Accumulator,
-                    },
-                    0b100 => InstructionType::And { source },
-                    0b101 => InstructionType::Xor { source },
-                    0b110 => InstructionType::Or { source },
-                    0b111 => InstructionType::Cp { source },
-                    _ => unreachable!(),
-                };
-
-                self.pc += 1;
-                Instruction { instruction_type }
-            }
-            0xC0 | 0xC8 | 0xD0 | 0xD8 => {
-                let condition = match (opcode & 0b00011000) >> 3 {
-                    0b00 => BranchConditionType::NotZero,
-                    0b01 => BranchConditionType::Zero,
-                    0b10 => BranchConditionType::NotCarry,
-                    0b11 => BranchConditionType::Carry,
-                    _ => unreachable!(),
-                };
+            0x08 => {
+                let destination_address = u16::from_le_bytes([
+                    read(address.wrapping_add(1)),
+                    read(address.wrapping_add(2)),
+                ]);
+                let destination = AddressingModeWord::LiteralIndirect(destination_address);
 
-                self.pc += 1;
+                length = 3;
                 Instruction {
-                    instruction_type: InstructionType::Ret { condition },
+                    instruction_type: InstructionType::LdWord {
+                        source: AddressingModeWord::Sp,
+                        destination,
+                    },
                 }
             }
-            0xC1 | 0xD1 | 0xE1 | 0xF1 => {
-                let target = match opcode {
-                    0xC1 => AddressingModeWord::Bc,
-                    0xD1 => AddressingModeWord::De,
-                    0xE1 => AddressingModeWord::Hl,
-                    0xF1 => AddressingModeWord::Af,
+            0x09 | 0x19 | 0x29 | 0x39 => {
+                let source = match opcode {
+                    0x09 => AddressingModeWord::Bc,
+                    0x19 => AddressingModeWord::De,
+                    0x29 => AddressingModeWord::Hl,
+                    0x39 => AddressingModeWord::Sp,
                     _ => unreachable!(),
                 };
 
-                self.pc += 1;
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Pop { target },
+                    instruction_type: InstructionType::AddHl { source },
                 }
             }
-            0xC2 | 0xCA | 0xD2 | 0xDA => {
-                fn get_branch_condition_type(val: u8) -> BranchConditionType {
+            0x0A | 0x1A | 0x2A | 0x3A => {
+                fn get_addressing_mode(val: u8) -> AddressingModeByte {
                     match val {
-                        0b000 => BranchConditionType::NotZero,
-                        0b001 => BranchConditionType::Zero,
-                        0b010 => BranchConditionType::NotCarry,
-                        0b011 => BranchConditionType::Carry,
+                        0b00 => AddressingModeByte::BcIndirect,
+                        0b01 => AddressingModeByte::DeIndirect,
+                        0b10 => AddressingModeByte::HlIndirectIncrement,
+                        0b11 => AddressingModeByte::HlIndirectDecrement,
                         _ => unreachable!(),
                     }
                 }
 
-                let condition = get_branch_condition_type((opcode & 0b00111000) >> 3);
-                let target_address =
-                    self.read_word(AddressingModeWord::LiteralIndirect(self.pc + 1));
-                let target = AddressingModeWord::Literal(target_address);
+                let source = get_addressing_mode((opcode & 0b00110000) >> 4);
+                let destination = AddressingModeByte::Accumulator;
 
-                self.pc += 3;
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Jp { target, condition },
+                    instruction_type: InstructionType::LdByte {
+                        source,
+                        destination,
+                    },
                 }
             }
-            0xC3 => {
-                let target_address =
-                    self.read_word(AddressingModeWord::LiteralIndirect(self.pc + 1));
-                let target = AddressingModeWord::Literal(target_address);
-                self.pc += 3;
-
+            0x0F => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Jp {
-                        target,
-                        condition: BranchConditionType::Unconditional,
-                    },
+                    instruction_type: InstructionType::Rrca,
                 }
             }
-            0xC4 | 0xCC | 0xD4 | 0xDC => {
-                let target_address =
-                    self.read_word(AddressingModeWord::LiteralIndirect(self.pc + 1));
-                let target = AddressingModeWord::Literal(target_address);
-
-                let condition = match opcode {
-                    0xC4 => BranchConditionType::NotZero,
-                    0xCC => BranchConditionType::Zero,
-                    0xD4 => BranchConditionType::NotCarry,
-                    0xDC => BranchConditionType::Carry,
-                    _ => unreachable!(),
-                };
-
-                self.pc += 3;
+            0x10 => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Call { target, condition },
+                    instruction_type: InstructionType::Stop,
                 }
             }
-            0xC5 | 0xD5 | 0xE5 | 0xF5 => {
-                let source = match opcode {
-                    0xC5 => AddressingModeWord::Bc,
-                    0xD5 => AddressingModeWord::De,
-                    0xE5 => AddressingModeWord::Hl,
-                    0xF5 => AddressingModeWord::Af,
-                    _ => unreachable!(),
-                };
-
-                self.pc += 1;
+            0x17 => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Push { source },
+                    instruction_type: InstructionType::Rla,
                 }
             }
-            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
-                let offset = opcode & 0b00111000;
+            0x18 => {
+                let unsigned_offset_value = read(address.wrapping_add(1));
+                let unsigned_offset = AddressingModeByte::Literal(unsigned_offset_value);
 
-                self.pc += 1;
+                length = 2;
                 Instruction {
-                    instruction_type: InstructionType::Rst {
-                        offset: u16::from(offset),
+                    instruction_type: InstructionType::Jr {
+                        unsigned_offset,
+                        condition: BranchConditionType::Unconditional,
                     },
                 }
             }
-            0xC9 => {
-                self.pc += 1;
+            0x1F => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Ret {
-                        condition: BranchConditionType::Unconditional,
-                    },
+                    instruction_type: InstructionType::Rra,
                 }
             }
-            0xCB => {
-                let cb_postfix = self.read_byte(AddressingModeByte::LiteralIndirect(self.pc + 1));
-
-                let target = match cb_postfix & 0b00000111 {
-                    0b000 => AddressingModeByte::B,
-                    0b001 => AddressingModeByte::C,
-                    0b010 => AddressingModeByte::D,
-                    0b011 => AddressingModeByte::E,
-                    0b100 => AddressingModeByte::H,
-                    0b101 => AddressingModeByte::L,
-                    0b110 => AddressingModeByte::HlIndirect,
-                    0b111 => AddressingModeByte::Accumulator,
-                    _ => unreachable!(),
-                };
-
-                let instruction_type = match (cb_postfix & 0b11111000) >> 3 {
-                    0b00000 => InstructionType::Rlc { target },
-                    0b00001 => InstructionType::Rrc { target },
-                    0b00010 => InstructionType::Rl { target },
-                    0b00011 => InstructionType::Rr { target },
-                    0b00100 => InstructionType::Sla { target },
-                    0b00101 => InstructionType::Sra { target },
-                    0b00110 => InstructionType::Swap { target },
-                    0b00111 => InstructionType::Srl { target },
-                    0b01000..=0b01111 => {
-                        let bit = (cb_postfix & 0b00111000) >> 3;
-                        InstructionType::Bit { target, bit }
-                    }
-                    0b10000..=0b10111 => {
-                        let bit = (cb_postfix & 0b00111000) >> 3;
-                        InstructionType::Res { target, bit }
-                    }
-                    0b11000..=0b11111 => {
-                        let bit = (cb_postfix & 0b00111000) >> 3;
-                        InstructionType::Set { target, bit }
+            0x20 | 0x28 | 0x30 | 0x38 => {
+                fn get_branch_condition_type(val: u8) -> BranchConditionType {
+                    match val {
+                        0b100 => BranchConditionType::NotZero,
+                        0b101 => BranchConditionType::Zero,
+                        0b110 => BranchConditionType::NotCarry,
+                        0b111 => BranchConditionType::Carry,
+                        _ => unreachable!(),
                     }
-                    _ => unreachable!(),
-                };
-
-                self.pc += 2;
+                }
 
-                Instruction { instruction_type }
-            }
-            0xCD => {
-                let target_address =
-                    self.read_word(AddressingModeWord::LiteralIndirect(self.pc + 1));
-                let target = AddressingModeWord::Literal(target_address);
+                let condition = get_branch_condition_type((opcode & 0b00111000) >> 3);
 
-                self.pc += 3;
-                Instruction {
-                    instruction_type: InstructionType::Call {
-                        target,
-                        condition: BranchConditionType::Unconditional,
-                    },
-                }
-            }
-            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
-                let source_value = self.read_byte(AddressingModeByte::LiteralIndirect(self.pc + 1));
-                let source = AddressingModeByte::Literal(source_value);
+                let unsigned_offset_value = read(address.wrapping_add(1));
+                let unsigned_offset = AddressingModeByte::Literal(unsigned_offset_value);
 
-                let instruction_type = match (opcode & 0b00111000) >> 3 {
-                    0b000 => InstructionType::AddByte {
-                        source,
-                        destination: AddressingModeByte::Accumulator,
-                    },
-                    0b001 => InstructionType::Adc {
-                        source,
-                        destination: AddressingModeByte::Accumulator,
-                    },
-                    0b010 => InstructionType::Sub { source },
-                    0b011 => InstructionType::Sbc {
-                        source,
-                        destination: AddressingModeByte::Accumulator,
-                    },
-                    0b100 => InstructionType::And { source },
-                    0b101 => InstructionType::Xor { source },
-                    0b110 => InstructionType::Or { source },
-                    0b111 => InstructionType::Cp { source },
-                    _ => unreachable!(),
-                };
+                length = 2;
 
-                self.pc += 2;
-                Instruction { instruction_type }
-            }
-            0xD9 => {
-                self.pc += 1;
                 Instruction {
-                    instruction_type: InstructionType::Reti,
+                    instruction_type: InstructionType::Jr {
+                        unsigned_offset,
+                        condition,
+                    },
                 }
             }
-            0xE0 | 0xF0 => {
-                let offset = self.read_byte(AddressingModeByte::LiteralIndirect(self.pc + 1));
-                let address = 0xFF00 + u16::from(offset);
-                let (source, destination) = match opcode {
-                    0xE0 => (
-                        AddressingModeByte::Accumulator,
-                        AddressingModeByte::LiteralIndirect(address),
-                    ),
-                    0xF0 => (
-                        AddressingModeByte::LiteralIndirect(address),
-                        AddressingModeByte::Accumulator,
-                    ),
-                    _ => unreachable!(),
-                };
-
-                self.pc += 2;
+            0x27 => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::LdByte {
-                        source,
-                        destination,
-                    },
+                    instruction_type: InstructionType::Daa,
                 }
             }
-            0xE2 | 0xF2 => {
-                let (source, destination) = match opcode {
-                    0xE2 => (
-                        AddressingModeByte::Accumulator,
-                        AddressingModeByte::CIndirect,
-                    ),
-                    0xF2 => (
-                        AddressingModeByte::CIndirect,
-                        AddressingModeByte::Accumulator,
-                    ),
-                    _ => unreachable!(),
-                };
-
-                self.pc += 1;
+            0x2F => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::LdByte {
-                        source,
-                        destination,
-                    },
+                    instruction_type: InstructionType::Cpl,
                 }
             }
-            0xE8 => {
-                let source_value = self.read_byte(AddressingModeByte::LiteralIndirect(self.pc + 1));
-
-                self.pc += 2;
+            0x37 => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::AddSp {
-                        value: source_value as i8,
-                    },
+                    instruction_type: InstructionType::Scf,
                 }
             }
-            0xE9 => {
-                self.pc += 1;
+            0x3F => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::JpHl,
+                    instruction_type: InstructionType::Ccf,
                 }
             }
-            0xEA | 0xFA => {
-                let address = self.read_word(AddressingModeWord::LiteralIndirect(self.pc + 1));
-
-                let (source, destination) = match opcode {
-                    0xEA => (
-                        AddressingModeByte::Accumulator,
-                        AddressingModeByte::LiteralIndirect(address),
-                    ),
-                    0xFA => (
-                        AddressingModeByte::LiteralIndirect(address),
-                        AddressingModeByte::Accumulator,
-                    ),
-                    _ => unreachable!(),
-                };
+            0x40 | 0x41 | 0x42 | 0x43 | 0x44 | 0x45 | 0x46 | 0x47 | 0x48 | 0x49 | 0x4A | 0x4B
+            | 0x4C | 0x4D | 0x4E | 0x4F | 0x50 | 0x51 | 0x52 | 0x53 | 0x54 | 0x55 | 0x56 | 0x57
+            | 0x58 | 0x59 | 0x5A | 0x5B | 0x5C | 0x5D | 0x5E | 0x5F | 0x60 | 0x61 | 0x62 | 0x63
+            | 0x64 | 0x65 | 0x66 | 0x67 | 0x68 | 0x69 | 0x6A | 0x6B | 0x6C | 0x6D | 0x6E | 0x6F
+            | 0x70 | 0x71 | 0x72 | 0x73 | 0x74 | 0x75 | 0x77 | 0x78 | 0x79 | 0x7A | 0x7B | 0x7C
+            | 0x7D | 0x7E | 0x7F => {
+                fn get_addressing_mode(val: u8) -> AddressingModeByte {
+                    match val {
+                        0b000 => AddressingModeByte::B,
+                        0b001 => AddressingModeByte::C,
+                        0b010 => AddressingModeByte::D,
+                        0b011 => AddressingModeByte::E,
+                        0b100 => AddressingModeByte::H,
+                        0b101 => AddressingModeByte::L,
+                        0b110 => AddressingModeByte::HlIndirect,
+                        0b111 => AddressingModeByte::Accumulator,
+                        _ => unreachable!(),
+                    }
+                }
+
+                let source = get_addressing_mode(opcode & 0b00000111);
+                let destination = get_addressing_mode((opcode & 0b00111000) >> 3);
 
-                self.pc += 3;
+                length = 1;
                 Instruction {
                     instruction_type: InstructionType::LdByte {
                         source,
@@ -1230,831 +3052,1365 @@ impl Cpu {
                     },
                 }
             }
-            0xF3 => {
-                self.pc += 1;
+            0x76 => {
+                length = 1;
                 Instruction {
-                    instruction_type: InstructionType::Di,
+                    instruction_type: InstructionType::Halt,
                 }
             }
-            0xF8 => {
-                let offset = self.read_byte(AddressingModeByte::LiteralIndirect(self.pc + 1));
+            0x80 | 0x81 | 0x82 | 0x83 | 0x84 | 0x85 | 0x86 | 0x87 | 0x88 | 0x89 | 0x8A | 0x8B
+            | 0x8C | 0x8D | 0x8E | 0x8F | 0x90 | 0x91 | 0x92 | 0x93 | 0x94 | 0x95 | 0x96 | 0x97
+            | 0x98 | 0x99 | 0x9A | 0x9B | 0x9C | 0x9D | 0x9E | 0x9F | 0xA0 | 0xA1 | 0xA2 | 0xA3
+            | 0xA4 | 0xA5 | 0xA6 | 0xA7 | 0xA8 | 0xA9 | 0xAA | 0xAB | 0xAC | 0xAD | 0xAE | 0xAF
+            | 0xB0 | 0xB1 | 0xB2 | 0xB3 | 0xB4 | 0xB5 | 0xB6 | 0xB7 | 0xB8 | 0xB9 | 0xBA | 0xBB
+            | 0xBC | 0xBD | 0xBE | 0xBF => {
+                let source = match opcode & 0b00000111 {
+                    0b000 => AddressingModeByte::B,
+                    0b001 => AddressingModeByte::C,
+                    0b010 => AddressingModeByte::D,
+                    0b011 => AddressingModeByte::E,
+                    0b100 => AddressingModeByte::H,
+                    0b101 => AddressingModeByte::L,
+                    0b110 => AddressingModeByte::HlIndirect,
+                    0b111 => AddressingModeByte::Accumulator,
+                    _ => unreachable!(),
+                };
 
-                self.pc += 2;
-                Instruction {
-                    instruction_type: InstructionType::Ldhl {
-                        source: AddressingModeWord::Sp,
-                        offset: offset as i8,
+                let instruction_type = match (opcode & 0b00111000) >> 3 {
+                    0b000 => InstructionType::AddByte {
+                        source,
+                        destination: AddressingModeByte::Accumulator,
                     },
-                }
-            }
-            0xF9 => {
-                self.pc += 1;
-
-                Instruction {
-                    instruction_type: InstructionType::LdSp {
-                        source: AddressingModeWord::Hl,
+                    0b001 => InstructionType::Adc {
+                        source,
+                        destination: AddressingModeByte::Accumulator,
                     },
-                }
-            }
-            0xFB => {
-                self.pc += 1;
-                Instruction {
-                    instruction_type: InstructionType::Ei,
-                }
-            }
-            _ => unreachable!("unknown opcode 0x{:02X}, PC: 0x{:02X}", opcode, self.pc),
-        }
-    }
-
-    fn execute(&mut self, instruction: Instruction) {
-        match instruction.instruction_type {
-            InstructionType::AddByte {
-                source,
-                destination,
-            } => self.execute_add_byte(source, destination),
-            InstructionType::AddHl { source } => self.execute_add_hl(source),
-            InstructionType::AddSp { value } => self.execute_add_sp(value),
-            InstructionType::Adc {
-                source,
-                destination,
-            } => self.execute_adc(source, destination),
-            InstructionType::And { source } => self.execute_and(source),
-            InstructionType::Bit { target, bit } => self.execute_bit(target, bit),
-            InstructionType::Call {
-                target: address,
-                condition,
-            } => self.execute_call(address, condition),
-            InstructionType::Ccf => self.execute_ccf(),
-            InstructionType::Cp { source } => self.execute_cp(source),
-            InstructionType::Cpl => self.execute_cpl(),
-            InstructionType::Daa => self.execute_daa(),
-            InstructionType::DecByte { target } => self.execute_dec_byte(target),
-            InstructionType::DecWord { target } => self.execute_dec_word(target),
-            InstructionType::Di => self.execute_di(),
-            InstructionType::Ei => self.execute_ei(),
-            InstructionType::Halt => self.execute_halt(),
-            InstructionType::IncByte { target } => self.execute_inc_byte(target),
-            InstructionType::IncWord { target } => self.execute_inc_word(target),
-            InstructionType::Jp { target, condition } => self.execute_jp(target, condition),
-            InstructionType::JpHl => self.execute_jp_hl(),
-            InstructionType::Jr {
-                unsigned_offset,
-                condition,
-            } => self.execute_jr(unsigned_offset, condition),
-            InstructionType::LdByte {
-                source,
-                destination,
-            } => self.execute_ld_byte(source, destination),
-            InstructionType::LdSp { source } => self.execute_ld_sp(source),
-            InstructionType::LdWord {
-                source,
-                destination,
-            } => self.execute_ld_word(source, destination),
-            InstructionType::Ldhl { source, offset } => self.execute_ldhl(source, offset),
-            InstructionType::Nop => {}
-            InstructionType::Or { source } => self.execute_or(source),
-            InstructionType::Pop { target } => self.execute_pop(target),
-            InstructionType::Push { source } => self.execute_push(source),
-            InstructionType::Res { target, bit } => self.execute_res(target, bit),
-            InstructionType::Ret { condition } => self.execute_ret(condition),
-            InstructionType::Reti => self.execute_reti(),
-            InstructionType::Rl { target } => self.execute_rl(target),
-            InstructionType::Rla => self.execute_rla(),
-            InstructionType::Rlc { target } => self.execute_rlc(target),
-            InstructionType::Rlca => self.execute_rlca(),
-            InstructionType::Rr { target } => self.execute_rr(target),
-            InstructionType::Rra => self.execute_rra(),
-            InstructionType::Rrc { target } => self.execute_rrc(target),
-            InstructionType::Rrca => self.execute_rrca(),
-            InstructionType::Rst { offset } => self.execute_rst(offset),
-            InstructionType::Sbc {
-                source,
-                destination,
-            } => self.execute_sbc(source, destination),
-            InstructionType::Scf => self.execute_scf(),
-            InstructionType::Set { target, bit } => self.execute_set(target, bit),
-            InstructionType::Sla { target } => self.execute_sla(target),
-            InstructionType::Sra { target } => self.execute_sra(target),
-            InstructionType::Srl { target } => self.execute_srl(target),
-            InstructionType::Stop => self.execute_stop(),
-            InstructionType::Sub { source } => self.execute_sub(source),
-            InstructionType::Swap { target } => self.execute_swap(target),
-            InstructionType::Xor { source } => self.execute_xor(source),
-        }
-    }
-
-    fn handle_interrupt(&mut self, interrupt_type: InterruptType) {
-        self.delay_m_cycle(); // interrupt wait state (CPU likely executing NOPs)
-        self.delay_m_cycle(); // interrupt wait state (CPU likely executing NOPs)
-
-        let [pc_msb, pc_lsb] = self.pc.to_be_bytes();
-        self.sp -= 1;
-        self.write_byte(pc_msb, AddressingModeByte::LiteralIndirect(self.sp));
-        self.sp -= 1;
-        self.write_byte(pc_lsb, AddressingModeByte::LiteralIndirect(self.sp));
-
-        self.delay_m_cycle(); // set PC?
-        self.pc = match interrupt_type {
-            InterruptType::VBlank => 0x40,
-            InterruptType::LcdStat => 0x48,
-            InterruptType::Timer => 0x50,
-            InterruptType::Serial => 0x58,
-            InterruptType::Joypad => 0x60,
-        };
-    }
-}
-
-impl Cpu {
-    fn execute_add_byte(&mut self, source: AddressingModeByte, destination: AddressingModeByte) {
-        let source_value = self.read_byte(source);
-        let destination_value = self.read_byte(destination);
-        let (result, carry_out) = destination_value.overflowing_add(source_value);
-        let half_carry =
-            (((source_value & 0b0000_1111) + (destination_value & 0b0000_1111)) & 0b0001_0000) != 0;
-        self.write_byte(result, destination);
-
-        self.set_zero_flag(result == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(half_carry);
-        self.set_carry_flag(carry_out);
-    }
-
-    fn execute_add_hl(&mut self, source: AddressingModeWord) {
-        let source_value = self.read_word(source);
-        let destination_value = self.read_word(AddressingModeWord::Hl);
-
-        // Takes extra cycle for the add to propogate to upper byte
-        self.delay_m_cycle();
-        let (result, carry_out) = destination_value.overflowing_add(source_value);
-        self.write_word(result, AddressingModeWord::Hl);
-
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(
-            (((source_value & 0b0000_1111_1111_1111)
-                + (destination_value & 0b0000_1111_1111_1111))
-                & 0b0001_0000_0000_0000)
-                != 0,
-        );
-        self.set_carry_flag(carry_out);
-    }
-
-    fn execute_add_sp(&mut self, value: i8) {
-        let destination_value = self.read_word(AddressingModeWord::Sp);
-
-        // Takes extra cycle for add to propgate to upper byte.
-        self.delay_m_cycle();
-        let result = destination_value.wrapping_add(i16::from(value) as u16);
-
-        // internal (likely delay when writing to sp).
-        self.delay_m_cycle();
-        self.write_word(result, AddressingModeWord::Sp);
-
-        // sp += value uses value as a signed value (and negative value correctly
-        // affects the entire sp, including carry in from upper byte).
-        //
-        // Flags are only set from the addition of value to the lower byte of sp.
-        // This means that half-carry flag is set if carry from bit 3 -> 4, and
-        // carry flag is set if carry out from bit 7. High byte of sp is ignored
-        // for both half-carry and carry flags.
-        self.set_zero_flag(false);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(
-            ((((value as u8) & 0b0000_1111) + ((destination_value as u8) & 0b0000_1111))
-                & 0b0001_0000)
-                != 0,
-        );
-        let (_, carry_out) = (destination_value as u8).overflowing_add(value as u8);
-        self.set_carry_flag(carry_out);
-    }
-
-    fn execute_adc(&mut self, source: AddressingModeByte, destination: AddressingModeByte) {
-        let source_value = self.read_byte(source);
-        let destination_value = self.read_byte(destination);
-        let (result, half_carry, carry) = if self.get_carry_flag() {
-            let (intermediate_result, carry_one) = source_value.overflowing_add(destination_value);
-            let (result, carry_two) = intermediate_result.overflowing_add(1);
-            let half_carry =
-                (((source_value & 0b0000_1111) + (destination_value & 0b0000_1111) + 1)
-                    & 0b0001_0000)
-                    != 0;
-
-            (result, half_carry, carry_one | carry_two)
-        } else {
-            let (result, carry) = source_value.overflowing_add(destination_value);
-            let half_carry = (((source_value & 0b0000_1111) + (destination_value & 0b0000_1111))
-                & 0b0001_0000)
-                != 0;
-
-            (result, half_carry, carry)
-        };
-
-        self.write_byte(result, destination);
-
-        self.set_zero_flag(result == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(half_carry);
-        self.set_carry_flag(carry);
-    }
+                    0b010 => InstructionType::Sub { source },
+                    0b011 => InstructionType::Sbc {
+                        source,
+                        destination: AddressingModeByte::Accumulator,
+                    },
+                    0b100 => InstructionType::And { source },
+                    0b101 => InstructionType::Xor { source },
+                    0b110 => InstructionType::Or { source },
+                    0b111 => InstructionType::Cp { source },
+                    _ => unreachable!(),
+                };
 
-    fn execute_and(&mut self, source: AddressingModeByte) {
-        let source_value = self.read_byte(source) as u8;
-        let destination_value = self.read_byte(AddressingModeByte::Accumulator) & source_value;
-        self.write_byte(destination_value, AddressingModeByte::Accumulator);
+                length = 1;
+                Instruction { instruction_type }
+            }
+            0xC0 | 0xC8 | 0xD0 | 0xD8 => {
+                let condition = match (opcode & 0b00011000) >> 3 {
+                    0b00 => BranchConditionType::NotZero,
+                    0b01 => BranchConditionType::Zero,
+                    0b10 => BranchConditionType::NotCarry,
+                    0b11 => BranchConditionType::Carry,
+                    _ => unreachable!(),
+                };
 
-        self.set_zero_flag(destination_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(true);
-        self.set_carry_flag(false);
-    }
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Ret { condition },
+                }
+            }
+            0xC1 | 0xD1 | 0xE1 | 0xF1 => {
+                let target = match opcode {
+                    0xC1 => AddressingModeWord::Bc,
+                    0xD1 => AddressingModeWord::De,
+                    0xE1 => AddressingModeWord::Hl,
+                    0xF1 => AddressingModeWord::Af,
+                    _ => unreachable!(),
+                };
 
-    fn execute_bit(&mut self, target: AddressingModeByte, bit: u8) {
-        let source_value = self.read_byte(target);
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Pop { target },
+                }
+            }
+            0xC2 | 0xCA | 0xD2 | 0xDA => {
+                fn get_branch_condition_type(val: u8) -> BranchConditionType {
+                    match val {
+                        0b000 => BranchConditionType::NotZero,
+                        0b001 => BranchConditionType::Zero,
+                        0b010 => BranchConditionType::NotCarry,
+                        0b011 => BranchConditionType::Carry,
+                        _ => unreachable!(),
+                    }
+                }
 
-        self.set_zero_flag((source_value & (1 << bit)) == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(true);
-    }
+                let condition = get_branch_condition_type((opcode & 0b00111000) >> 3);
+                let target_address = u16::from_le_bytes([
+                    read(address.wrapping_add(1)),
+                    read(address.wrapping_add(2)),
+                ]);
+                let target = AddressingModeWord::Literal(target_address);
 
-    fn execute_call(&mut self, address: AddressingModeWord, condition: BranchConditionType) {
-        let call_address = self.read_word(address);
+                length = 3;
+                Instruction {
+                    instruction_type: InstructionType::Jp { target, condition },
+                }
+            }
+            0xC3 => {
+                let target_address = u16::from_le_bytes([
+                    read(address.wrapping_add(1)),
+                    read(address.wrapping_add(2)),
+                ]);
+                let target = AddressingModeWord::Literal(target_address);
+                length = 3;
 
-        if self.should_branch(condition) {
-            self.delay_m_cycle(); // internal
+                Instruction {
+                    instruction_type: InstructionType::Jp {
+                        target,
+                        condition: BranchConditionType::Unconditional,
+                    },
+                }
+            }
+            0xC4 | 0xCC | 0xD4 | 0xDC => {
+                let target_address = u16::from_le_bytes([
+                    read(address.wrapping_add(1)),
+                    read(address.wrapping_add(2)),
+                ]);
+                let target = AddressingModeWord::Literal(target_address);
 
-            let [pc_msb, pc_lsb] = self.pc.to_be_bytes();
-            self.sp -= 1;
-            self.write_byte(pc_msb, AddressingModeByte::LiteralIndirect(self.sp));
-            self.sp -= 1;
-            self.write_byte(pc_lsb, AddressingModeByte::LiteralIndirect(self.sp));
+                let condition = match opcode {
+                    0xC4 => BranchConditionType::NotZero,
+                    0xCC => BranchConditionType::Zero,
+                    0xD4 => BranchConditionType::NotCarry,
+                    0xDC => BranchConditionType::Carry,
+                    _ => unreachable!(),
+                };
 
-            self.pc = call_address;
-        }
-    }
+                length = 3;
+                Instruction {
+                    instruction_type: InstructionType::Call { target, condition },
+                }
+            }
+            0xC5 | 0xD5 | 0xE5 | 0xF5 => {
+                let source = match opcode {
+                    0xC5 => AddressingModeWord::Bc,
+                    0xD5 => AddressingModeWord::De,
+                    0xE5 => AddressingModeWord::Hl,
+                    0xF5 => AddressingModeWord::Af,
+                    _ => unreachable!(),
+                };
 
-    fn execute_inc_byte(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        let new_value = old_value.wrapping_add(1);
-        self.write_byte(new_value, target);
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Push { source },
+                }
+            }
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+                let offset = opcode & 0b00111000;
 
-        self.set_zero_flag(new_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag((old_value & 0b0001_0000) != (new_value & 0b0001_0000));
-    }
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Rst {
+                        offset: u16::from(offset),
+                    },
+                }
+            }
+            0xC9 => {
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Ret {
+                        condition: BranchConditionType::Unconditional,
+                    },
+                }
+            }
+            0xCB => {
+                let cb_postfix = read(address.wrapping_add(1));
 
-    fn execute_inc_word(&mut self, target: AddressingModeWord) {
-        let old_value = self.read_word(target);
+                let target = match cb_postfix & 0b00000111 {
+                    0b000 => AddressingModeByte::B,
+                    0b001 => AddressingModeByte::C,
+                    0b010 => AddressingModeByte::D,
+                    0b011 => AddressingModeByte::E,
+                    0b100 => AddressingModeByte::H,
+                    0b101 => AddressingModeByte::L,
+                    0b110 => AddressingModeByte::HlIndirect,
+                    0b111 => AddressingModeByte::Accumulator,
+                    _ => unreachable!(),
+                };
 
-        // Takes extra cycle for add to propogate to upper byte.
-        self.delay_m_cycle();
-        let new_value = old_value.wrapping_add(1);
-        self.write_word(new_value, target);
-    }
+                let instruction_type = match (cb_postfix & 0b11111000) >> 3 {
+                    0b00000 => InstructionType::Rlc { target },
+                    0b00001 => InstructionType::Rrc { target },
+                    0b00010 => InstructionType::Rl { target },
+                    0b00011 => InstructionType::Rr { target },
+                    0b00100 => InstructionType::Sla { target },
+                    0b00101 => InstructionType::Sra { target },
+                    0b00110 => InstructionType::Swap { target },
+                    0b00111 => InstructionType::Srl { target },
+                    0b01000..=0b01111 => {
+                        let bit = (cb_postfix & 0b00111000) >> 3;
+                        InstructionType::Bit { target, bit }
+                    }
+                    0b10000..=0b10111 => {
+                        let bit = (cb_postfix & 0b00111000) >> 3;
+                        InstructionType::Res { target, bit }
+                    }
+                    0b11000..=0b11111 => {
+                        let bit = (cb_postfix & 0b00111000) >> 3;
+                        InstructionType::Set { target, bit }
+                    }
+                    _ => unreachable!(),
+                };
 
-    fn execute_ccf(&mut self) {
-        let old_carry_flag = self.get_carry_flag();
-        let new_carry_flag = !old_carry_flag;
+                length = 2;
 
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag(new_carry_flag);
-    }
+                Instruction { instruction_type }
+            }
+            0xCD => {
+                let target_address = u16::from_le_bytes([
+                    read(address.wrapping_add(1)),
+                    read(address.wrapping_add(2)),
+                ]);
+                let target = AddressingModeWord::Literal(target_address);
 
-    fn execute_cp(&mut self, source: AddressingModeByte) {
-        let source_value = self.read_byte(source);
-        let accumulator_value = self.read_byte(AddressingModeByte::Accumulator);
+                length = 3;
+                Instruction {
+                    instruction_type: InstructionType::Call {
+                        target,
+                        condition: BranchConditionType::Unconditional,
+                    },
+                }
+            }
+            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+                let source_value = read(address.wrapping_add(1));
+                let source = AddressingModeByte::Literal(source_value);
 
-        self.set_zero_flag(source_value == accumulator_value);
-        self.set_subtract_flag(true);
-        self.set_half_carry_flag((accumulator_value & 0b0000_1111) < (source_value & 0b0000_1111));
-        self.set_carry_flag(accumulator_value < source_value);
-    }
+                let instruction_type = match (opcode & 0b00111000) >> 3 {
+                    0b000 => InstructionType::AddByte {
+                        source,
+                        destination: AddressingModeByte::Accumulator,
+                    },
+                    0b001 => InstructionType::Adc {
+                        source,
+                        destination: AddressingModeByte::Accumulator,
+                    },
+                    0b010 => InstructionType::Sub { source },
+                    0b011 => InstructionType::Sbc {
+                        source,
+                        destination: AddressingModeByte::Accumulator,
+                    },
+                    0b100 => InstructionType::And { source },
+                    0b101 => InstructionType::Xor { source },
+                    0b110 => InstructionType::Or { source },
+                    0b111 => InstructionType::Cp { source },
+                    _ => unreachable!(),
+                };
 
-    fn execute_cpl(&mut self) {
-        let source_value = self.read_byte(AddressingModeByte::Accumulator);
-        self.write_byte(!source_value, AddressingModeByte::Accumulator);
+                length = 2;
+                Instruction { instruction_type }
+            }
+            0xD9 => {
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Reti,
+                }
+            }
+            0xE0 | 0xF0 => {
+                let offset = read(address.wrapping_add(1));
+                let address = 0xFF00 + u16::from(offset);
+                let (source, destination) = match opcode {
+                    0xE0 => (
+                        AddressingModeByte::Accumulator,
+                        AddressingModeByte::LiteralIndirect(address),
+                    ),
+                    0xF0 => (
+                        AddressingModeByte::LiteralIndirect(address),
+                        AddressingModeByte::Accumulator,
+                    ),
+                    _ => unreachable!(),
+                };
 
-        self.set_subtract_flag(true);
-        self.set_half_carry_flag(true);
-    }
+                length = 2;
+                Instruction {
+                    instruction_type: InstructionType::LdByte {
+                        source,
+                        destination,
+                    },
+                }
+            }
+            0xE2 | 0xF2 => {
+                let (source, destination) = match opcode {
+                    0xE2 => (
+                        AddressingModeByte::Accumulator,
+                        AddressingModeByte::CIndirect,
+                    ),
+                    0xF2 => (
+                        AddressingModeByte::CIndirect,
+                        AddressingModeByte::Accumulator,
+                    ),
+                    _ => unreachable!(),
+                };
 
-    fn execute_daa(&mut self) {
-        let source_value = self.read_byte(AddressingModeByte::Accumulator);
-        let mut result_value = source_value;
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::LdByte {
+                        source,
+                        destination,
+                    },
+                }
+            }
+            0xE8 => {
+                let source_value = read(address.wrapping_add(1));
 
-        if self.get_subtract_flag() {
-            if self.get_carry_flag() {
-                result_value = result_value.wrapping_sub(0x60);
+                length = 2;
+                Instruction {
+                    instruction_type: InstructionType::AddSp {
+                        value: source_value as i8,
+                    },
+                }
             }
+            0xE9 => {
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::JpHl,
+                }
+            }
+            0xEA | 0xFA => {
+                let address = u16::from_le_bytes([
+                    read(address.wrapping_add(1)),
+                    read(address.wrapping_add(2)),
+                ]);
 
-            if self.get_half_carry_flag() {
-                result_value = result_value.wrapping_sub(0x06);
+                let (source, destination) = match opcode {
+                    0xEA => (
+                        AddressingModeByte::Accumulator,
+                        AddressingModeByte::LiteralIndirect(address),
+                    ),
+                    0xFA => (
+                        AddressingModeByte::LiteralIndirect(address),
+                        AddressingModeByte::Accumulator,
+                    ),
+                    _ => unreachable!(),
+                };
+
+                length = 3;
+                Instruction {
+                    instruction_type: InstructionType::LdByte {
+                        source,
+                        destination,
+                    },
+                }
             }
-        } else {
-            if self.get_carry_flag() || (source_value > 0x99) {
-                result_value = result_value.wrapping_add(0x60);
-                self.set_carry_flag(true);
+            0xF3 => {
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Di,
+                }
             }
+            0xF8 => {
+                let offset = read(address.wrapping_add(1));
 
-            if self.get_half_carry_flag() || ((source_value & 0x0F) > 0x09) {
-                result_value = result_value.wrapping_add(0x06);
+                length = 2;
+                Instruction {
+                    instruction_type: InstructionType::Ldhl {
+                        source: AddressingModeWord::Sp,
+                        offset: offset as i8,
+                    },
+                }
             }
-        }
+            0xF9 => {
+                length = 1;
 
-        self.write_byte(result_value, AddressingModeByte::Accumulator);
+                Instruction {
+                    instruction_type: InstructionType::LdSp {
+                        source: AddressingModeWord::Hl,
+                    },
+                }
+            }
+            0xFB => {
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Ei,
+                }
+            }
+            _ => {
+                length = 1;
+                Instruction {
+                    instruction_type: InstructionType::Illegal { opcode },
+                }
+            }
+        };
 
-        self.set_zero_flag(result_value == 0);
-        self.set_half_carry_flag(false);
-        // carry flag already (possibly) set above
+        (instruction, length)
     }
 
-    fn execute_dec_byte(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        let new_value = old_value.wrapping_sub(1);
-        self.write_byte(new_value, target);
+    #[test]
+    fn test_decode_table_matches() {
+        let decode = |bytes: [u8; 3]| {
+            (
+                Cpu::decode_with(0, |address| bytes[usize::from(address)]),
+                decode_with_match(0, |address| bytes[usize::from(address)]),
+            )
+        };
 
-        self.set_zero_flag(new_value == 0);
-        self.set_subtract_flag(true);
-        self.set_half_carry_flag((old_value & 0b0001_0000) != (new_value & 0b0001_0000));
+        for opcode in 0..=0xFF {
+            for operand in [[0x00, 0x00], [0x12, 0xFE], [0xFF, 0x80]] {
+                let (table, reference) = decode([opcode, operand[0], operand[1]]);
+                assert_eq!(table, reference, "opcode 0x{:02X}", opcode);
+            }
+        }
+        for postfix in 0..=0xFF {
+            let (table, reference) = decode([0xCB, postfix, 0x00]);
+            assert_eq!(table, reference, "opcode 0xCB{:02X}", postfix);
+        }
     }
 
-    fn execute_dec_word(&mut self, target: AddressingModeWord) {
-        let old_value = self.read_word(target);
-
-        // Takes extra cycle for dec to propogate to upper byte.
-        self.delay_m_cycle();
-        let new_value = old_value.wrapping_sub(1);
-        self.write_word(new_value, target);
+    #[test]
+    fn test_cpu_size() {
+        assert!(std::mem::size_of::<Cpu>() <= 2048);
     }
 
-    fn execute_di(&mut self) {
-        self.bus.set_interrupt_master_enable(false);
-    }
+    #[test]
+    fn test_boot_rom_unmaps_on_write() {
+        // xor a; ldh ($50), a
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..3].copy_from_slice(&[0xAF, 0xE0, 0x50]);
 
-    fn execute_ei(&mut self) {
-        self.bus.set_interrupt_master_enable(true);
-    }
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        assert_eq!(cpu.bus.read_byte_address(0x0000), 0xAF);
 
-    fn execute_halt(&mut self) {
-        self.halted = true;
-    }
+        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute();
+        assert!(!cpu.bus.boot_rom_enabled);
+        assert_eq!(cpu.bus.read_byte_address(0x0000), 0x00);
 
-    fn execute_ld_byte(&mut self, source: AddressingModeByte, destination: AddressingModeByte) {
-        let value = self.read_byte(source);
-        self.write_byte(value, destination);
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        assert!(Cpu::new_with_boot_rom(cartridge, &[0; 0x200]).is_err());
     }
 
-    fn execute_ld_sp(&mut self, source: AddressingModeWord) {
-        let value = self.read_word(source);
+    #[test]
+    fn test_set_register_word_masks_f() {
+        let mut cpu = test_cpu();
 
-        // internal (likely delay when writing to sp).
-        self.delay_m_cycle();
-        self.write_word(value, AddressingModeWord::Sp);
+        cpu.set_register_word(RegisterWord::Af, 0x12FF);
+        assert_eq!(cpu.register_word(RegisterWord::Af), 0x12F0);
+        assert_eq!(cpu.register(RegisterByte::Accumulator), 0x12);
+
+        cpu.set_register(RegisterByte::Accumulator, 0x34);
+        assert_eq!(cpu.register_word(RegisterWord::Af), 0x34F0);
     }
 
-    fn execute_ld_word(&mut self, source: AddressingModeWord, destination: AddressingModeWord) {
-        let value = self.read_word(source);
-        self.write_word(value, destination);
+    #[test]
+    fn test_ei_enables_interrupts_after_next_instruction() {
+        // ei; nop; nop
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..3].copy_from_slice(&[0xFB, 0x00, 0x00]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        cpu.sp = 0xFFFE;
+        cpu.bus.write_byte_address(0b0000_0001, 0xFFFF);
+        cpu.bus.write_byte_address(0b0000_0001, 0xFF0F);
+
+        cpu.fetch_decode_execute(); // ei
+        assert_eq!(cpu.pc, 0x0001);
+        cpu.fetch_decode_execute(); // nop, still with interrupts disabled
+        assert_eq!(cpu.pc, 0x0002);
+        assert!(cpu.last_interrupt().is_none());
+
+        cpu.fetch_decode_execute();
+        assert_eq!(cpu.pc, 0x0040);
+        assert!(matches!(cpu.last_interrupt(), Some(InterruptType::VBlank)));
     }
 
-    fn execute_ldhl(&mut self, source: AddressingModeWord, offset: i8) {
-        let source_value = self.read_word(source);
+    #[test]
+    fn test_hram() {
+        let mut bus = test_cpu().bus;
+        assert_eq!(bus.hram().len(), 0x7F);
 
-        // internal (likely waiting for add to propogate to upper byte).
-        self.delay_m_cycle();
-        let result_value = source_value.wrapping_add(i16::from(offset) as u16);
+        bus.hram_mut()[0] = 0x12;
+        bus.hram_mut()[0x7E] = 0x34;
+        assert_eq!(bus.read_byte_address(0xFF80), 0x12);
+        assert_eq!(bus.read_byte_address(0xFFFE), 0x34);
 
-        self.write_word(result_value, AddressingModeWord::Hl);
+        bus.write_byte_address(0x56, 0xFF81);
+        bus.write_byte_address(0x78, 0xFFFD);
+        assert_eq!(bus.hram()[1], 0x56);
+        assert_eq!(bus.hram()[0x7D], 0x78);
 
-        // sp + offset uses offset as a signed value (and negative value correctly
-        // affects the entire sp, including carry in from upper byte).
-        //
-        // Flags are only set from the addition of offset to the lower byte of sp.
-        // This means that half-carry flag is set if carry from bit 3 -> 4, and
-        // carry flag is set if carry out from bit 7. High byte of sp is ignored
-        // for both half-carry and carry flags.
-        self.set_zero_flag(false);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(
-            ((((source_value as u8) & 0b0000_1111) + ((offset as u8) & 0b0000_1111)) & 0b0001_0000)
-                != 0,
-        );
-        let (_, carry_out) = (source_value as u8).overflowing_add(offset as u8);
-        self.set_carry_flag(carry_out);
+        // IE at 0xFFFF isn't part of HRAM.
+        bus.write_byte_address(0x1F, 0xFFFF);
+        assert!(!bus.hram().contains(&0x1F));
     }
 
-    fn execute_jp(&mut self, target: AddressingModeWord, condition: BranchConditionType) {
-        let new_address = self.read_word(target);
-
-        if self.should_branch(condition) {
-            self.delay_m_cycle();
-            self.pc = new_address;
-        }
+    #[test]
+    fn test_last_interrupt() {
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &[0; 0x100]).unwrap();
+        cpu.sp = 0xFFFE;
+        cpu.bus.set_interrupt_master_enable(true);
+        cpu.bus.write_byte_address(0b0000_0100, 0xFFFF);
+        cpu.bus.write_byte_address(0b0000_0100, 0xFF0F);
+        assert_eq!(cpu.last_interrupt(), None);
+
+        cpu.fetch_decode_execute();
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.last_interrupt(), Some(InterruptType::Timer));
     }
 
-    fn execute_jp_hl(&mut self) {
-        let new_address = self.read_word(AddressingModeWord::Hl);
-        self.pc = new_address;
+    #[test]
+    fn test_write_watchpoint() {
+        // ld hl, $c000; ld [hl], $42
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..5].copy_from_slice(&[0x21, 0x00, 0xC0, 0x36, 0x42]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        cpu.add_watchpoint(0xC000, WatchKind::Write);
+
+        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute();
+
+        assert_eq!(
+            cpu.take_watch_hits(),
+            vec![WatchHit {
+                address: 0xC000,
+                value: 0x42,
+                kind: WatchKind::Write,
+                pc: 0x0003,
+            }]
+        );
+        assert!(cpu.take_watch_hits().is_empty());
     }
 
-    fn execute_jr(&mut self, unsigned_offset: AddressingModeByte, condition: BranchConditionType) {
-        let signed_offset = self.read_byte(unsigned_offset) as i8;
-        if self.should_branch(condition) {
-            // Signed numbers are stored as 2's complement. Wrapping add after
-            // casting to unsigned has same effect as wrapping add of signed to
-            // unsigned.
-            self.delay_m_cycle(); // internal modify PC
-            self.pc = self.pc.wrapping_add(signed_offset as u16);
-        }
-    }
+    #[test]
+    fn test_game_genie_cheat() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x4123] = 0x12;
+        let mut cpu = Cpu::new(Cartridge::new(&rom).unwrap());
 
-    fn execute_or(&mut self, source: AddressingModeByte) {
-        let source_value = self.read_byte(source);
-        let destination_value = self.read_byte(AddressingModeByte::Accumulator);
-        let result_value = source_value | destination_value;
-        self.write_byte(result_value, AddressingModeByte::Accumulator);
+        // Patches 0x4123 to 0x77 when it holds 0x12, and when it holds 0x00.
+        cpu.add_cheat("771-23B-E0A".parse().unwrap());
+        assert_eq!(cpu.bus.read_byte_address(0x4123), 0x12);
+        cpu.add_cheat("771-23B-A02".parse().unwrap());
+        assert_eq!(cpu.bus.read_byte_address(0x4123), 0x77);
 
-        self.set_zero_flag(result_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag(false);
+        cpu.clear_cheats();
+        assert_eq!(cpu.bus.read_byte_address(0x4123), 0x12);
     }
 
-    fn execute_pop(&mut self, target: AddressingModeWord) {
-        let value_lsb = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
-        self.sp += 1;
-        let value_msb = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
-        self.sp += 1;
-
-        let value = u16::from_be_bytes([value_msb, value_lsb]);
-        self.write_word(value, target);
-    }
+    #[test]
+    fn test_speed_switch() {
+        // ld a, $01; ldh ($4d), a; stop
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..6].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00]);
 
-    fn execute_push(&mut self, source: AddressingModeWord) {
-        let value = self.read_word(source);
-        let [value_msb, value_lsb] = value.to_be_bytes();
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        assert_eq!(cpu.speed_mode(), SpeedMode::Normal);
 
-        self.delay_m_cycle(); // internal
+        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute();
+        assert!(cpu.speed_switch_armed());
+        assert_eq!(cpu.speed_mode(), SpeedMode::Normal);
 
-        self.sp -= 1;
-        self.write_byte(value_msb, AddressingModeByte::LiteralIndirect(self.sp));
-        self.sp -= 1;
-        self.write_byte(value_lsb, AddressingModeByte::LiteralIndirect(self.sp));
+        cpu.fetch_decode_execute();
+        assert!(!cpu.speed_switch_armed());
+        assert_eq!(cpu.speed_mode(), SpeedMode::Double);
     }
 
-    fn execute_res(&mut self, target: AddressingModeByte, bit: u8) {
-        let source_value = self.read_byte(target);
-        let result_value = source_value & !(1 << bit);
-        self.write_byte(result_value, target);
-    }
+    #[test]
+    fn test_pending_interrupts() {
+        // ld a, $05; ldh ($ff), a; ld a, $07; ldh ($0f), a
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..8].copy_from_slice(&[0x3E, 0x05, 0xE0, 0xFF, 0x3E, 0x07, 0xE0, 0x0F]);
 
-    fn execute_ret(&mut self, condition: BranchConditionType) {
-        if matches!(condition, BranchConditionType::Unconditional) {
-            let lsb_return_address = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
-            self.sp += 1;
-            let msb_return_address = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
-            self.sp += 1;
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        for _ in 0..4 {
+            cpu.fetch_decode_execute();
+        }
 
-            let return_address = u16::from_be_bytes([msb_return_address, lsb_return_address]);
+        assert!(!cpu.interrupt_master_enable());
+        assert_eq!(cpu.interrupt_enable(), 0x05);
+        assert_eq!(cpu.interrupt_flag() & 0x07, 0x07);
+        assert_eq!(
+            cpu.pending_interrupts(),
+            [InterruptType::VBlank, InterruptType::Timer]
+        );
+    }
 
-            self.delay_m_cycle(); // set PC?
-            self.pc = return_address;
-        } else {
-            self.delay_m_cycle(); // branch decision?
-            if self.should_branch(condition) {
-                let lsb_return_address =
-                    self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
-                self.sp += 1;
-                let msb_return_address =
-                    self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
-                self.sp += 1;
+    #[test]
+    fn test_io_log() {
+        // ld a, $91; ldh ($40), a; ld a, $11; ldh ($40), a; ldh a, ($44)
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..10]
+            .copy_from_slice(&[0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x11, 0xE0, 0x40, 0xF0, 0x44]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        cpu.bus.enable_io_log();
+        for _ in 0..5 {
+            cpu.fetch_decode_execute();
+        }
 
-                let return_address = u16::from_be_bytes([msb_return_address, lsb_return_address]);
+        let access = |address, value, is_write, pc, cycle| IoAccess {
+            address,
+            value,
+            is_write,
+            pc,
+            cycle,
+        };
+        assert_eq!(
+            cpu.bus.take_io_log(),
+            [
+                access(0xFF40, 0x91, true, 0x0002, 8),
+                access(0xFF40, 0x11, true, 0x0006, 28),
+                access(0xFF44, 0x00, false, 0x0008, 40),
+            ]
+        );
 
-                self.delay_m_cycle(); // set PC?
-                self.pc = return_address;
-            }
-        }
+        cpu.bus.disable_io_log();
+        cpu.bus.write_byte_address(0x91, 0xFF40);
+        assert!(cpu.bus.take_io_log().is_empty());
     }
 
-    fn execute_reti(&mut self) {
-        let lsb_return_address = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
-        self.sp += 1;
-        let msb_return_address = self.read_byte(AddressingModeByte::LiteralIndirect(self.sp));
-        self.sp += 1;
+    #[test]
+    fn test_joypad_state() {
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new(cartridge);
+
+        let all: JoypadState = Button::ALL.into_iter().collect();
+        cpu.set_joypad_state(all);
+        assert_eq!(cpu.joypad_state(), all);
+        cpu.set_joypad_state(JoypadState::NONE);
+        assert_eq!(cpu.joypad_state(), JoypadState::NONE);
+
+        cpu.set_block_opposing_directions(true);
+        cpu.set_button_pressed(Button::Up, true);
+        cpu.set_button_pressed(Button::Down, true);
+        cpu.set_button_pressed(Button::A, true);
+        assert_eq!(cpu.joypad_state(), JoypadState::NONE.with(Button::A, true));
+
+        // Releasing one of the pair brings back the other.
+        cpu.set_button_pressed(Button::Down, false);
+        assert!(cpu.joypad_state().is_pressed(Button::Up));
+    }
 
-        let return_address = u16::from_be_bytes([msb_return_address, lsb_return_address]);
+    #[test]
+    fn test_joypad_interrupt_wakes_stop() {
+        // ld a, $10; ldh ($ff), a; ld a, $20; ldh ($00), a; stop
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..10]
+            .copy_from_slice(&[0x3E, 0x10, 0xE0, 0xFF, 0x3E, 0x20, 0xE0, 0x00, 0x10, 0x00]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        for _ in 0..5 {
+            cpu.fetch_decode_execute();
+        }
+        assert!(cpu.stopped);
 
-        self.delay_m_cycle(); // set PC?
-        self.pc = return_address;
+        cpu.set_button_pressed(Button::Down, true);
+        assert!(!cpu.stopped);
+        assert!(cpu.bus.joypad.interrupt_pending());
 
-        self.bus.set_interrupt_master_enable(true);
+        cpu.fetch_decode_execute();
+        assert_eq!(cpu.pending_interrupts(), [InterruptType::Joypad]);
     }
 
-    fn execute_rl(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        let new_value = (old_value << 1) | (self.get_carry_flag() as u8);
-        self.write_byte(new_value, target);
-
-        self.set_zero_flag(new_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_value & 0b1000_0000) != 0);
+    #[test]
+    fn test_stop() {
+        // ld a, $91; ldh ($40), a; ld a, $20; ldh ($00), a; stop; stop
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..10]
+            .copy_from_slice(&[0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x20, 0xE0, 0x00, 0x10, 0x10]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        for _ in 0..5 {
+            cpu.fetch_decode_execute();
+        }
+        assert!(cpu.is_stopped());
+        let white = PaletteColorRgb555::from_rgb888(0xFFFFFF);
+        assert!(cpu
+            .bus
+            .ppu
+            .get_buffer()
+            .iter()
+            .flatten()
+            .all(|&color| color == white));
+
+        // Nothing runs while stopped, and only buttons on the selected lines
+        // (here the directions) wake the CPU.
+        let dot = cpu.bus.ppu.dot();
+        assert_eq!(cpu.step_frame(), DOTS_PER_FRAME);
+        assert_eq!(cpu.bus.ppu.dot(), dot);
+        cpu.set_button_pressed(Button::A, true);
+        assert!(cpu.is_stopped());
+        cpu.set_button_pressed(Button::Right, true);
+        assert!(!cpu.is_stopped());
+
+        // With Right still held, the second STOP doesn't stop.
+        cpu.fetch_decode_execute();
+        assert!(!cpu.is_stopped());
+        assert_eq!(cpu.pc, 0x000A);
     }
 
-    fn execute_rla(&mut self) {
-        let old_accumulator = self.read_byte(AddressingModeByte::Accumulator);
-        let new_accumulator = (old_accumulator << 1) | (self.get_carry_flag() as u8);
-        self.write_byte(new_accumulator, AddressingModeByte::Accumulator);
+    #[test]
+    fn test_oam_dma_timing() {
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new(cartridge);
+        let bus = &mut cpu.bus;
+        for offset in 0..0xA0 {
+            bus.write_byte_address(offset as u8 + 1, 0xC000 + offset);
+        }
+        bus.write_byte_address(0x42, 0xFF80);
 
-        // The manual states that the zero flag is set when the result is zero, but
-        // other documentation states that the zero flag is unconditionally reset.
-        //
-        // The zero flag being unconditionally reset passes blargg's cpu tests
-        // (whereas conditionally setting the zero flag fails).
-        self.set_zero_flag(false);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_accumulator & 0b1000_0000) != 0);
+        bus.write_byte_address(0xC0, 0xFF46);
+        assert!(bus.dma_active());
+        for _ in 0..0x9F {
+            bus.step_m_cycle();
+        }
+
+        // Only high RAM and I/O are reachable while the transfer runs.
+        assert!(bus.dma_active());
+        assert_eq!(bus.read_byte_address(0xC000), 0xFF);
+        assert_eq!(bus.read_byte_address(0xFE00), 0xFF);
+        assert_eq!(bus.read_byte_address(0xFF80), 0x42);
+        assert_eq!(bus.peek_byte_address(0xFE9E), 0x9F);
+        assert_eq!(bus.peek_byte_address(0xFE9F), 0x00);
+
+        bus.step_m_cycle();
+        assert!(!bus.dma_active());
+        assert_eq!(bus.read_byte_address(0xC000), 0x01);
+        for offset in 0..0xA0 {
+            assert_eq!(bus.read_byte_address(0xFE00 + offset), offset as u8 + 1);
+        }
     }
 
-    fn execute_rlc(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        let new_value = old_value.rotate_left(1);
-        self.write_byte(new_value, target);
+    #[test]
+    fn test_oam_bug() {
+        // ld hl, $fe10; loop: inc hl; dec hl; jr loop
+        let run = |dmg_hardware| {
+            let mut cpu =
+                boot_dmg_cartridge(&[0x21, 0x10, 0xFE, 0x23, 0x2B, 0x18, 0xFC], dmg_hardware);
+            for offset in 0..0xA0 {
+                cpu.bus
+                    .ppu
+                    .write_object_attribute_memory(offset as u8, offset);
+            }
+            // A whole frame, so the loop runs through every OAM search.
+            cpu.step_frame();
+            (
+                cpu.bus.ppu.get_ppu_mode(),
+                std::array::from_fn::<u8, 0xA0, _>(|i| {
+                    cpu.bus.ppu.read_object_attribute_memory(i as u16)
+                }),
+            )
+        };
 
-        self.set_zero_flag(new_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_value & 0b1000_0000) != 0);
+        let untouched = std::array::from_fn(|i| i as u8);
+        assert_ne!(run(true).1, untouched);
+        // A CGB running a DMG cartridge doesn't have the bug, even though the
+        // PPU is in DMG compatibility mode.
+        assert_eq!(run(false), (PpuMode::Dmg, untouched));
     }
 
-    fn execute_rlca(&mut self) {
-        let old_accumulator = self.read_byte(AddressingModeByte::Accumulator);
-        let new_accumulator = old_accumulator.rotate_left(1);
-        self.write_byte(new_accumulator, AddressingModeByte::Accumulator);
+    #[test]
+    fn test_wave_ram_access_while_playing() {
+        let read_while_playing = |dmg_hardware| {
+            let mut cpu = boot_dmg_cartridge(&[], dmg_hardware);
+            cpu.bus.write_byte_address(0x80, 0xFF26);
+            cpu.bus.apu.set_wave_ram([0x11; 16]);
+            cpu.bus.write_byte_address(0x80, 0xFF1A);
+            cpu.bus.write_byte_address(0x80, 0xFF1E);
+            cpu.bus.read_byte_address(0xFF35)
+        };
 
-        // The manual states that the zero flag is set when the result is zero, but
-        // other documentation states that the zero flag is unconditionally reset.
-        //
-        // The zero flag being unconditionally reset passes blargg's cpu tests
-        // (whereas conditionally setting the zero flag fails).
-        self.set_zero_flag(false);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_accumulator & 0b1000_0000) != 0);
+        assert_eq!(read_while_playing(true), 0xFF);
+        // DMG cartridges on a CGB see the byte being played.
+        assert_eq!(read_while_playing(false), 0x11);
     }
 
-    fn execute_rr(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        let new_value = (old_value >> 1) | (self.get_carry_flag() as u8).rotate_right(1);
-        self.write_byte(new_value, target);
+    #[test]
+    fn test_length_writes_while_apu_off() {
+        // A length of 2 written while powered off only sticks on the DMG, and
+        // then runs out within two length clocks.
+        let plays_past_two_length_clocks = |dmg_hardware| {
+            let mut cpu = boot_dmg_cartridge(&[], dmg_hardware);
+            cpu.bus.write_byte_address(0x00, 0xFF26);
+            cpu.bus.write_byte_address(0x3E, 0xFF11);
+            cpu.bus.write_byte_address(0x80, 0xFF26);
+            cpu.bus.write_byte_address(0xF0, 0xFF12);
+            cpu.bus.write_byte_address(0xC0, 0xFF14);
+            for _ in 0..CLOCK_FREQUENCY / 128 {
+                cpu.bus.apu.step();
+            }
+            cpu.bus.apu.channel_status()[0]
+        };
 
-        self.set_zero_flag(new_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_value & 0b0000_0001) != 0);
+        assert!(!plays_past_two_length_clocks(true));
+        assert!(plays_past_two_length_clocks(false));
     }
 
-    fn execute_rra(&mut self) {
-        let old_accumulator = self.read_byte(AddressingModeByte::Accumulator);
-        let new_accumulator =
-            (old_accumulator >> 1) | (self.get_carry_flag() as u8).rotate_right(1);
-        self.write_byte(new_accumulator, AddressingModeByte::Accumulator);
+    #[test]
+    fn test_vram_blocked_during_pixel_transfer() {
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new(cartridge);
+        let bus = &mut cpu.bus;
+        bus.write_byte_address(0x91, 0xFF40);
+        let step_until = |bus: &mut Bus, mode| {
+            while bus.ppu.get_stat_mode() != mode {
+                bus.step_m_cycle();
+            }
+        };
 
-        // The manual states that the zero flag is set when the result is zero, but
-        // other documentation states that the zero flag is unconditionally reset.
-        //
-        // The zero flag being unconditionally reset passes blargg's cpu tests
-        // (whereas conditionally setting the zero flag fails).
-        self.set_zero_flag(false);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_accumulator & 0b0000_0001) != 0);
+        step_until(bus, PpuRenderStatus::PixelTransfer);
+        bus.write_byte_address(0x42, 0x8000);
+        bus.write_byte_address(0x42, 0xFE00);
+        assert_eq!(bus.peek_byte_address(0x8000), 0x00);
+        assert_eq!(bus.peek_byte_address(0xFE00), 0x00);
+        assert_eq!(bus.read_byte_address(0x8000), 0xFF);
+
+        step_until(bus, PpuRenderStatus::HBlank);
+        bus.write_byte_address(0x42, 0x8000);
+        assert_eq!(bus.read_byte_address(0x8000), 0x42);
+
+        // OAM is also off limits during OAM search, but VRAM isn't.
+        step_until(bus, PpuRenderStatus::OAMSearch);
+        bus.write_byte_address(0x43, 0x8000);
+        bus.write_byte_address(0x43, 0xFE00);
+        assert_eq!(bus.read_byte_address(0x8000), 0x43);
+        assert_eq!(bus.peek_byte_address(0xFE00), 0x00);
     }
 
-    fn execute_rrc(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        let new_value = old_value.rotate_right(1);
-        self.write_byte(new_value, target);
+    #[test]
+    fn test_general_purpose_dma() {
+        let mut rom = vec![0; 0x8000];
+        for (offset, byte) in rom[0x4000..0x4040].iter_mut().enumerate() {
+            *byte = offset as u8 + 1;
+        }
+        let mut cpu = Cpu::new(Cartridge::new(&rom).unwrap());
+
+        // Copy 4 blocks from 0x4000 to 0x8800.
+        for (value, address) in [
+            (0x40, 0xFF51),
+            (0x00, 0xFF52),
+            (0x08, 0xFF53),
+            (0x00, 0xFF54),
+        ] {
+            cpu.bus.write_byte_address(value, address);
+        }
+        cpu.bus.write_byte_address(0x03, 0xFF55);
 
-        self.set_zero_flag(new_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_value & 0b0000_0001) != 0);
-    }
+        assert_eq!(cpu.bus.read_byte_address(0xFF55), 0xFF);
+        for offset in 0..0x40 {
+            assert_eq!(cpu.bus.peek_byte_address(0x8800 + offset), offset as u8 + 1);
+        }
+        assert_eq!(cpu.bus.peek_byte_address(0x8840), 0x00);
 
-    fn execute_rrca(&mut self) {
-        let old_accumulator = self.read_byte(AddressingModeByte::Accumulator);
-        let new_accumulator = old_accumulator.rotate_right(1);
-        self.write_byte(new_accumulator, AddressingModeByte::Accumulator);
+        // The CPU sits out 8 m-cycles per block before running anything else.
+        let pc = cpu.pc;
+        for _ in 0..32 {
+            assert_eq!(cpu.fetch_decode_execute(), 4);
+            assert_eq!(cpu.pc, pc);
+        }
+        cpu.fetch_decode_execute();
+        assert_ne!(cpu.pc, pc);
+    }
 
-        // The manual states that the zero flag is set when the result is zero, but
-        // other documentation states that the zero flag is unconditionally reset.
-        //
-        // The zero flag being unconditionally reset passes blargg's cpu tests
-        // (whereas conditionally setting the zero flag fails).
-        self.set_zero_flag(false);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_accumulator & 0b0000_0001) != 0);
+    #[test]
+    fn test_open_bus_reads() {
+        let mut bus = test_cpu().bus;
+        assert_eq!(bus.read_byte_address(0xFEA0), OPEN_BUS);
+        assert_eq!(bus.read_byte_address(0xFEFF), OPEN_BUS);
+        assert_eq!(bus.read_byte_address(0xFF03), OPEN_BUS);
+        assert_eq!(bus.read_byte_address(0xFF7F), OPEN_BUS);
+
+        // Unused register bits read back as 1.
+        bus.write_byte_address(0x01, 0xFF0F);
+        assert_eq!(bus.read_byte_address(0xFF0F), 0xE1);
+        bus.write_byte_address(0x02, 0xFF70);
+        assert_eq!(bus.read_byte_address(0xFF70), 0xFA);
+        assert_eq!(bus.read_byte_address(0xFF4D), 0x7E);
+        bus.write_byte_address(0x05, 0xFF07);
+        assert_eq!(bus.read_byte_address(0xFF07), 0xFD);
     }
 
-    fn execute_rst(&mut self, offset: u16) {
-        self.delay_m_cycle(); // internal
+    #[test]
+    fn test_doctor_line() {
+        // ld sp, $fffe; xor a
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..4].copy_from_slice(&[0x31, 0xFE, 0xFF, 0xAF]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        assert_eq!(
+            cpu.doctor_line(),
+            "A:00 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0000 PCMEM:31,FE,FF,AF"
+        );
 
-        let [pc_msb, pc_lsb] = self.pc.to_be_bytes();
-        self.sp -= 1;
-        self.write_byte(pc_msb, AddressingModeByte::LiteralIndirect(self.sp));
-        self.sp -= 1;
-        self.write_byte(pc_lsb, AddressingModeByte::LiteralIndirect(self.sp));
+        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute();
+        assert_eq!(
+            cpu.doctor_line(),
+            "A:00 F:80 B:00 C:00 D:00 E:00 H:00 L:00 SP:FFFE PC:0004 PCMEM:00,00,00,00"
+        );
+    }
 
-        self.pc = offset;
+    #[test]
+    fn test_peek_poke() {
+        let mut cpu = test_cpu();
+        cpu.add_watchpoint(0xC000, WatchKind::ReadWrite);
+
+        cpu.poke(0xC000, 0x42);
+        cpu.poke(0xC001, 0x43);
+        assert_eq!(cpu.peek(0xC000), 0x42);
+        assert_eq!(cpu.peek_range(0xBFFF, 4), [0xFF, 0x42, 0x43, 0x00]);
+        assert!(cpu.take_watch_hits().is_empty());
     }
 
-    // Some gameboy documentation has carry/half-carry documentation backwards for this op.
-    // Carry and half-carry flags are set when there is a borrow-in to bit 7 for carry flag,
-    // or borrow-in to bit 3 for half-carry flag, respectively.
-    fn execute_sbc(&mut self, source: AddressingModeByte, destination: AddressingModeByte) {
-        let source_value = self.read_byte(source);
-        let destination_value = self.read_byte(destination);
+    #[test]
+    fn test_tracer() {
+        // nop; ld a, $01; inc a; jr -3
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..6].copy_from_slice(&[0x00, 0x3E, 0x01, 0x3C, 0x18, 0xFD]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&events);
+        cpu.set_tracer(Box::new(move |event| recorded.borrow_mut().push(event)));
+        let mut cycles = 0;
+        let mut last_cycles = 0;
+        for _ in 0..10 {
+            last_cycles = u64::from(cpu.fetch_decode_execute());
+            cycles += last_cycles;
+        }
 
-        let (result, half_carry, carry) = if self.get_carry_flag() {
-            let (intermediate_result, borrow_one) = destination_value.overflowing_sub(source_value);
-            let (result, borrow_two) = intermediate_result.overflowing_sub(1);
-            let half_borrow =
-                (destination_value & 0b0000_1111) < ((source_value & 0b0000_1111) + 1);
+        let events = events.borrow();
+        assert_eq!(events.len(), 10);
+        assert_eq!(events[0].pc, 0x0000);
+        assert_eq!(events[0].cycles, 0);
+        assert_eq!(events[1].pc, 0x0001);
+        assert_eq!(events[1].registers.pc, 0x0001);
+        assert_eq!(events[3].registers.af >> 8, 0x02);
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].cycles < pair[1].cycles));
+        assert_eq!(events[9].cycles, cycles - last_cycles);
+    }
 
-            (result, half_borrow, borrow_one | borrow_two)
-        } else {
-            let (result, borrow) = destination_value.overflowing_sub(source_value);
-            let half_borrow = (destination_value & 0b0000_1111) < (source_value & 0b0000_1111);
+    #[test]
+    fn test_run_until_break() {
+        // nop; nop; jr -2
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..4].copy_from_slice(&[0x00, 0x00, 0x18, 0xFE]);
 
-            (result, half_borrow, borrow)
-        };
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        cpu.add_breakpoint(0x0002);
 
-        self.write_byte(result, destination);
+        assert_eq!(cpu.run_until_break(1_000), RunStop::Breakpoint(0x0002));
+        assert_eq!(cpu.run_until_break(1_000), RunStop::Breakpoint(0x0002));
 
-        self.set_zero_flag(result == 0);
-        self.set_subtract_flag(true);
-        self.set_half_carry_flag(half_carry);
-        self.set_carry_flag(carry);
+        cpu.remove_breakpoint(0x0002);
+        assert_eq!(cpu.run_until_break(1_000), RunStop::CyclesExhausted);
     }
 
-    fn execute_scf(&mut self) {
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag(true);
-    }
+    #[test]
+    fn test_daa() {
+        const Z: u16 = 0x80;
+        const N: u16 = 0x40;
+        const H: u16 = 0x20;
+        const C: u16 = 0x10;
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new(cartridge);
+
+        // Every accumulator value and N/H/C combination, against a model
+        // that builds the correction up front.
+        for a in 0..=0xFF_u8 {
+            for flags in [0, N, H, C, N | H, N | C, H | C, N | H | C] {
+                let (n, h, c) = (flags & N != 0, flags & H != 0, flags & C != 0);
+                let mut correction = 0;
+                let mut carry = c;
+                if h || (!n && a & 0x0F > 0x09) {
+                    correction |= 0x06;
+                }
+                if c || (!n && a > 0x99) {
+                    correction |= 0x60;
+                    carry = true;
+                }
+                let result = if n {
+                    a.wrapping_sub(correction)
+                } else {
+                    a.wrapping_add(correction)
+                };
 
-    fn execute_set(&mut self, target: AddressingModeByte, bit: u8) {
-        let old_value = self.read_byte(target);
-        let result_value = old_value | (1 << bit);
-        self.write_byte(result_value, target);
-    }
+                cpu.af = u16::from(a) << 8 | flags;
+                cpu.execute_daa();
+                let expected_flags =
+                    if result == 0 { Z } else { 0 } | (flags & N) | if carry { C } else { 0 };
+                assert_eq!(
+                    cpu.af,
+                    u16::from(result) << 8 | expected_flags,
+                    "daa of {:02x} with flags {:02x}",
+                    a,
+                    flags
+                );
+            }
+        }
 
-    fn execute_sla(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        let result_value = old_value << 1;
-        self.write_byte(result_value, target);
+        // After adding or subtracting two BCD numbers, DAA leaves the BCD
+        // result, with carry as the decimal carry or borrow.
+        let to_bcd = |value: u8| (value / 10) << 4 | (value % 10);
+        for x in 0..100 {
+            for y in 0..100 {
+                cpu.af = u16::from(to_bcd(x)) << 8;
+                cpu.execute_add_byte(
+                    AddressingModeByte::Literal(to_bcd(y)),
+                    AddressingModeByte::Accumulator,
+                );
+                cpu.execute_daa();
+                assert_eq!(cpu.af >> 8, u16::from(to_bcd((x + y) % 100)));
+                assert_eq!(cpu.get_carry_flag(), x + y >= 100);
+
+                cpu.af = u16::from(to_bcd(x)) << 8;
+                cpu.execute_sub(AddressingModeByte::Literal(to_bcd(y)));
+                cpu.execute_daa();
+                assert_eq!(cpu.af >> 8, u16::from(to_bcd((100 + x - y) % 100)));
+                assert_eq!(cpu.get_carry_flag(), x < y);
+            }
+        }
+    }
 
-        self.set_zero_flag(result_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_value & 0b1000_0000) != 0);
+    #[test]
+    fn test_run_to_frame_with_lcd_off() {
+        // The LCD starts out off, and nothing turns it on.
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &[0; 0x100]).unwrap();
+        assert!(!cpu.run_to_frame(1, 90));
+        assert_eq!(cpu.frame_count(), 0);
+        let t_cycles = cpu.metrics().t_cycles;
+        assert!((90 * DOTS_PER_FRAME..91 * DOTS_PER_FRAME).contains(&t_cycles));
+        assert!(cpu.run_to_frame(0, 0));
+
+        cpu.bus.write_byte_address(0x91, 0xFF40);
+        assert!(cpu.run_to_frame(2, 1));
+        assert_eq!(cpu.frame_count(), 2);
     }
 
-    fn execute_sra(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        // Signed right shift performs sign extension.
-        let result_value = ((old_value as i8) >> 1) as u8;
-        self.write_byte(result_value, target);
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_load_state_marks_ram_dirty() {
+        // MBC1+RAM+BATTERY with 8KiB of RAM.
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let mut cpu = Cpu::new(Cartridge::new(&rom).unwrap());
+        let state = cpu.save_state();
+
+        cpu.bus.cartridge.write(0x0A, 0x0000);
+        cpu.bus.cartridge.write(0x42, 0xA000);
+        cpu.bus.cartridge.read_save_data();
+        cpu.load_state(&state).unwrap();
+        assert!(cpu.bus.cartridge.is_ram_dirty());
+
+        // Loading RAM identical to what's already saved leaves nothing to save.
+        cpu.bus.cartridge.read_save_data();
+        cpu.load_state(&state).unwrap();
+        assert!(!cpu.bus.cartridge.is_ram_dirty());
+
+        // Unsaved changes are still saved, even if the state matches them.
+        cpu.bus.cartridge.write(0x0A, 0x0000);
+        cpu.bus.cartridge.write(0x42, 0xA000);
+        cpu.bus.cartridge.write(0x00, 0xA000);
+        cpu.load_state(&state).unwrap();
+        assert!(cpu.bus.cartridge.is_ram_dirty());
+    }
 
-        self.set_zero_flag(result_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_value & 0b0000_0001) != 0);
+    #[test]
+    fn test_pc_wraps_mid_instruction() {
+        // ld bc, $3e00, with the opcode in the last byte of HRAM, the low byte
+        // in IE, and the high byte back at the start of the boot ROM.
+        let mut boot_rom = [0; 0x100];
+        boot_rom[0] = 0x3E;
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        cpu.bus.write_byte_address(0x01, 0xFFFE);
+        cpu.bus.write_byte_address(0x00, 0xFFFF);
+        cpu.pc = 0xFFFE;
+
+        assert_eq!(cpu.fetch_decode_execute(), 12);
+        assert_eq!(cpu.bc, 0x3E00);
+        assert_eq!(cpu.pc, 0x0001);
     }
 
-    fn execute_srl(&mut self, target: AddressingModeByte) {
-        let old_value = self.read_byte(target);
-        // Signed right shift performs sign extension.
-        let result_value = old_value >> 1;
-        self.write_byte(result_value, target);
+    #[test]
+    fn test_illegal_opcode_hangs() {
+        // ld a, $01; ldh ($ff), a; ei; (illegal)
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..6].copy_from_slice(&[0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0xD3]);
 
-        self.set_zero_flag(result_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag((old_value & 0b0000_0001) != 0);
-    }
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        for _ in 0..4 {
+            cpu.fetch_decode_execute();
+        }
+        assert!(cpu.is_hung());
+        assert_eq!(cpu.pc, 0x0006);
 
-    fn execute_stop(&mut self) {
-        // If the bus does not handle this stop (by performing a speed switch),
-        // we need to stop until the next user input is received.
-        self.stopped = !self.bus.maybe_handle_stop();
+        // Time still passes, but even a pending, enabled interrupt isn't taken.
+        cpu.bus.write_byte_address(0x01, 0xFF0F);
+        for _ in 0..100 {
+            assert_eq!(cpu.fetch_decode_execute(), 4);
+        }
+        assert_eq!(cpu.pc, 0x0006);
+        assert_eq!(cpu.run_until_break(1_000), RunStop::Halted);
+
+        cpu.reset(true);
+        assert!(!cpu.is_hung());
     }
 
-    // Some gameboy documentation has carry/half-carry documentation backwards for this op.
-    // Carry and half-carry flags are set when there is a borrow-in to bit 7 for carry flag,
-    // or borrow-in to bit 3 for half-carry flag, respectively.
-    fn execute_sub(&mut self, source: AddressingModeByte) {
-        let source_value = self.read_byte(source);
-        let destination_value = self.read_byte(AddressingModeByte::Accumulator);
-        let (result_value, carry_in) = destination_value.overflowing_sub(source_value);
-        let half_carry_in = (destination_value & 0b0000_1111) < (source_value & 0b0000_1111);
+    #[test]
+    fn test_step_debug() {
+        // nop; ld a, $01; ld ($ffff), a; ei; nop; halt
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..9].copy_from_slice(&[0x00, 0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x00, 0x76, 0x00]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        let step = cpu.step_debug();
+        assert_eq!(step.pc, 0x0000);
+        assert_eq!(
+            step.instruction.unwrap().instruction_type,
+            InstructionType::Nop
+        );
+        assert_eq!(step.interrupt, None);
+        assert_eq!(step.t_cycles, 4);
 
-        self.write_byte(result_value, AddressingModeByte::Accumulator);
+        for _ in 0..3 {
+            assert!(cpu.step_debug().instruction.is_some());
+        }
 
-        self.set_zero_flag(result_value == 0);
-        self.set_subtract_flag(true);
-        self.set_half_carry_flag(half_carry_in);
-        self.set_carry_flag(carry_in);
+        // A pending vblank is dispatched once the nop after ei has run.
+        cpu.bus.write_byte_address(0x01, 0xFF0F);
+        let step = cpu.step_debug();
+        assert_eq!(step.pc, 0x0006);
+        assert_eq!(
+            step.instruction.unwrap().instruction_type,
+            InstructionType::Nop
+        );
+        let step = cpu.step_debug();
+        assert_eq!(step.pc, 0x0007);
+        assert_eq!(step.instruction, None);
+        assert_eq!(step.interrupt, Some(InterruptType::VBlank));
+        assert_eq!(step.t_cycles, 20);
+        assert_eq!(cpu.pc, 0x0040);
+
+        // Dispatch is reported even when the pushes wrap sp around onto IE.
+        cpu.pc = 0x0007;
+        cpu.sp = 0x0001;
+        cpu.bus.set_interrupt_master_enable(true);
+        cpu.bus.write_byte_address(0x01, 0xFF0F);
+        cpu.bus.write_byte_address(0x01, 0xFFFF);
+        let step = cpu.step_debug();
+        assert_eq!(step.instruction, None);
+        assert_eq!(step.interrupt, Some(InterruptType::VBlank));
     }
-    fn execute_swap(&mut self, target: AddressingModeByte) {
-        let source_value = self.read_byte(target);
-        // Original low nibble will be shifted out when shifting right, and likewise,
-        // original high nibble will be shifted out when shifting left.
-        let result_value = (source_value >> 4) | (source_value << 4);
-        self.write_byte(result_value, target);
 
-        self.set_zero_flag(result_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag(false);
+    #[test]
+    fn test_step_debug_reports_fetched_instruction() {
+        // ld a, $c0; ldh ($46), a; nop
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..5].copy_from_slice(&[0x3E, 0xC0, 0xE0, 0x46, 0x00]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        cpu.step_debug();
+        cpu.step_debug();
+
+        // OAM DMA is running, so the nop is fetched as open bus.
+        let step = cpu.step_debug();
+        assert_eq!(step.pc, 0x0004);
+        assert_eq!(
+            cpu.disassemble_at(0x0004).0.instruction_type,
+            InstructionType::Nop
+        );
+        assert_eq!(
+            step.instruction.unwrap().instruction_type,
+            InstructionType::Rst { offset: 0x38 }
+        );
     }
 
-    fn execute_xor(&mut self, source: AddressingModeByte) {
-        let source_value = self.read_byte(source);
-        let result_value = self.read_byte(AddressingModeByte::Accumulator) ^ source_value;
-        self.write_byte(result_value, AddressingModeByte::Accumulator);
+    #[test]
+    fn test_disassemble_at() {
+        // ld hl, $c000; ld [hl], $42; bit 7, h; jr nz, -5; (illegal)
+        let mut boot_rom = [0; 0x100];
+        boot_rom[..11].copy_from_slice(&[
+            0x21, 0x00, 0xC0, 0x36, 0x42, 0xCB, 0x7C, 0x20, 0xFB, 0xD3, 0x00,
+        ]);
+
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+
+        let mut address = 0x0000;
+        let mut listing = Vec::new();
+        for _ in 0..5 {
+            let (instruction, length) = cpu.disassemble_at(address);
+            listing.push(instruction.to_string());
+            address += length;
+        }
 
-        self.set_zero_flag(result_value == 0);
-        self.set_subtract_flag(false);
-        self.set_half_carry_flag(false);
-        self.set_carry_flag(false);
+        assert_eq!(
+            listing,
+            [
+                "ld hl, $c000",
+                "ld [hl], $42",
+                "bit 7, h",
+                "jr nz, $fb",
+                "illegal $d3"
+            ]
+        );
+        assert_eq!(address, 0x000A);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.emulated_time(), Duration::ZERO);
     }
 
-    fn should_branch(&self, condition: BranchConditionType) -> bool {
-        match condition {
-            BranchConditionType::NotZero => !self.get_zero_flag(),
-            BranchConditionType::NotCarry => !self.get_carry_flag(),
-            BranchConditionType::Zero => self.get_zero_flag(),
-            BranchConditionType::Carry => self.get_carry_flag(),
-            BranchConditionType::Unconditional => true,
+    #[test]
+    fn test_display_starts_with_mnemonic() {
+        const MNEMONICS: &[&str] = &[
+            "adc", "add", "and", "bit", "call", "ccf", "cp", "cpl", "daa", "dec", "di", "ei",
+            "halt", "illegal", "inc", "jp", "jr", "ld", "nop", "or", "pop", "push", "res", "ret",
+            "reti", "rl", "rla", "rlc", "rlca", "rr", "rra", "rrc", "rrca", "rst", "sbc", "scf",
+            "set", "sla", "sra", "srl", "stop", "sub", "swap", "xor",
+        ];
+
+        let mut cpu = test_cpu();
+        let opcodes = (0x00..=0xFF)
+            .map(|opcode| [opcode, 0x00])
+            .chain((0x00..=0xFF).map(|postfix| [0xCB, postfix]));
+        for bytes in opcodes {
+            cpu.bus.write_byte_address(bytes[0], 0xC000);
+            cpu.bus.write_byte_address(bytes[1], 0xC001);
+
+            let text = cpu.disassemble_at(0xC000).0.to_string();
+            let mnemonic = text.split_whitespace().next().unwrap_or_default();
+            assert!(
+                MNEMONICS.contains(&mnemonic),
+                "{:02x?} formatted as {:?}",
+                bytes,
+                text
+            );
         }
     }
-}
 
-impl Cpu {
-    const ZERO_FLAG_MASK: u16 = 0b00000000_1000_0000;
-    const SUBTRACT_FLAG_MASK: u16 = 0b00000000_0100_0000;
-    const HALF_CARRY_FLAG_MASK: u16 = 0b00000000_0010_0000;
-    const CARRY_FLAG_MASK: u16 = 0b00000000_0001_0000;
+    #[test]
+    fn test_push_wraps_sp() {
+        let mut cpu = test_cpu();
+        cpu.sp = 0x0000;
+        cpu.bc = 0x1234;
 
-    fn get_zero_flag(&self) -> bool {
-        (self.af & Self::ZERO_FLAG_MASK) != 0
-    }
+        cpu.execute(Instruction {
+            instruction_type: InstructionType::Push {
+                source: AddressingModeWord::Bc,
+            },
+        });
 
-    fn get_subtract_flag(&self) -> bool {
-        (self.af & Self::SUBTRACT_FLAG_MASK) != 0
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.bus.read_byte_address(0xFFFE), 0x34);
+        // 0xFFFF is the interrupt enable register, which only keeps the low 5 bits.
+        assert_eq!(cpu.bus.read_byte_address(0xFFFF), 0x12 & 0b0001_1111);
     }
 
-    fn get_half_carry_flag(&self) -> bool {
-        (self.af & Self::HALF_CARRY_FLAG_MASK) != 0
-    }
+    #[test]
+    fn test_pop_wraps_sp() {
+        let mut cpu = test_cpu();
+        cpu.sp = 0xFFFF;
+        cpu.bus.write_byte_address(0x0F, 0xFFFF);
 
-    fn get_carry_flag(&self) -> bool {
-        (self.af & Self::CARRY_FLAG_MASK) != 0
+        cpu.execute(Instruction {
+            instruction_type: InstructionType::Pop {
+                target: AddressingModeWord::De,
+            },
+        });
+
+        assert_eq!(cpu.sp, 0x0001);
+        assert_eq!(cpu.de & 0x00FF, 0x000F);
     }
 
-    fn set_zero_flag(&mut self, set: bool) {
-        if set {
-            self.af |= Self::ZERO_FLAG_MASK;
-        } else {
-            self.af &= !Self::ZERO_FLAG_MASK;
-        }
+    #[test]
+    fn test_stack_guard_violation() {
+        let mut cpu = test_cpu();
+        cpu.set_stack_guard(0xC000, 0xDFFF);
+
+        // The boot ROM starts with `ld sp, $fffe`, which is outside the guard.
+        cpu.fetch_decode_execute();
+
+        assert_eq!(
+            cpu.poll_stack_guard_violation(),
+            Some(StackGuardViolation {
+                pc: 0x0000,
+                sp: 0xFFFE
+            })
+        );
+        assert_eq!(cpu.poll_stack_guard_violation(), None);
     }
 
-    fn set_subtract_flag(&mut self, set: bool) {
-        if set {
-            self.af |= Self::SUBTRACT_FLAG_MASK;
-        } else {
-            self.af &= !Self::SUBTRACT_FLAG_MASK;
-        }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_load_state_keeps_stack_guard() {
+        let mut cpu = test_cpu();
+        cpu.set_stack_guard(0xC000, 0xDFFF);
+        let state = cpu.save_state();
+
+        cpu.clear_stack_guard();
+        cpu.load_state(&state).unwrap();
+        cpu.fetch_decode_execute();
+        assert_eq!(cpu.poll_stack_guard_violation(), None);
+
+        cpu.set_stack_guard(0xC000, 0xDFFF);
+        cpu.load_state(&state).unwrap();
+        cpu.fetch_decode_execute();
+        assert!(cpu.poll_stack_guard_violation().is_some());
     }
 
-    fn set_half_carry_flag(&mut self, set: bool) {
-        if set {
-            self.af |= Self::HALF_CARRY_FLAG_MASK;
-        } else {
-            self.af &= !Self::HALF_CARRY_FLAG_MASK;
+    #[test]
+    fn test_emulated_time() {
+        let mut cpu = test_cpu();
+        assert_eq!(cpu.emulated_time(), Duration::ZERO);
+
+        let mut t_cycles = 0;
+        while t_cycles < u64::from(CLOCK_FREQUENCY) {
+            t_cycles += u64::from(cpu.fetch_decode_execute());
         }
+
+        let expected_nanos = u128::from(t_cycles) * 1_000_000_000 / u128::from(CLOCK_FREQUENCY);
+        assert_eq!(cpu.emulated_time().as_nanos(), expected_nanos);
+        assert!(cpu.emulated_time() >= Duration::from_secs(1));
     }
 
-    fn set_carry_flag(&mut self, set: bool) {
-        if set {
-            self.af |= Self::CARRY_FLAG_MASK;
-        } else {
-            self.af &= !Self::CARRY_FLAG_MASK;
+    #[test]
+    fn test_metrics() {
+        // nop x 4; halt, with no interrupts enabled so it never wakes.
+        let mut boot_rom = [0; 0x100];
+        boot_rom[4] = 0x76;
+        let cartridge = Cartridge::new(&[0; 0x8000]).unwrap();
+        let mut cpu = Cpu::new_with_boot_rom(cartridge, &boot_rom).unwrap();
+        assert_eq!(cpu.metrics(), Metrics::default());
+
+        for _ in 0..10 {
+            cpu.fetch_decode_execute();
         }
+        assert_eq!(
+            cpu.metrics(),
+            Metrics {
+                instructions: 5,
+                t_cycles: 40,
+                frames: 0,
+            }
+        );
+
+        cpu.reset(false);
+        assert_eq!(cpu.metrics(), Metrics::default());
     }
 }
@@ -0,0 +1,34 @@
+use crate::cpu::Registers;
+
+// Gameboy Doctor's reference logs are captured with LY stubbed to this value,
+// see `Ppu::set_lcd_y_override`.
+pub const DOCTOR_LCD_Y: u8 = 0x90;
+
+// Formats CPU state as a line of a Gameboy Doctor log, e.g.
+//
+//  A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// where PCMEM is the four bytes of memory starting at PC.
+pub fn format_line(registers: Registers, pc_memory: [u8; 4]) -> String {
+    let [a, f] = registers.af.to_be_bytes();
+    let [b, c] = registers.bc.to_be_bytes();
+    let [d, e] = registers.de.to_be_bytes();
+    let [h, l] = registers.hl.to_be_bytes();
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        a,
+        f,
+        b,
+        c,
+        d,
+        e,
+        h,
+        l,
+        registers.sp,
+        registers.pc,
+        pc_memory[0],
+        pc_memory[1],
+        pc_memory[2],
+        pc_memory[3]
+    )
+}
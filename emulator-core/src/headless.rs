@@ -0,0 +1,62 @@
+use std::error::Error;
+
+use crate::calculate_ppu_buffer_checksum;
+use crate::cartridge::Cartridge;
+use crate::cpu::Cpu;
+
+// Runs a ROM without any frontend attached, for test ROM harnesses and other
+// tooling. Runs are deterministic: the same ROM and the same sequence of calls
+// always produce the same serial output and PPU checksum.
+pub struct HeadlessRunner {
+    cpu: Cpu,
+    cycles: u64,
+}
+
+impl HeadlessRunner {
+    pub fn new(rom: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_cpu(Cpu::new(Cartridge::new(rom)?)))
+    }
+
+    pub fn from_cpu(cpu: Cpu) -> Self {
+        Self { cpu, cycles: 0 }
+    }
+
+    // Runs until at least `cycles` more t-cycles have elapsed. Whole
+    // instructions are always executed, so this may overshoot slightly; the
+    // overshoot counts towards the next call.
+    pub fn run_cycles(&mut self, cycles: u64) {
+        let target = self.cycles + cycles;
+        while self.cycles < target {
+            self.cycles += u64::from(self.cpu.fetch_decode_execute());
+        }
+    }
+
+    // Runs `frames` frames, see `Cpu::step_frame`.
+    pub fn run_frames(&mut self, frames: u64) {
+        for _ in 0..frames {
+            self.cycles += self.cpu.step_frame();
+        }
+    }
+
+    // Total t-cycles run so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Everything the ROM has written to the serial port so far.
+    pub fn serial_output(&self) -> &str {
+        self.cpu.bus.serial.get_data_written()
+    }
+
+    pub fn ppu_checksum(&self) -> u32 {
+        calculate_ppu_buffer_checksum(&self.cpu)
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
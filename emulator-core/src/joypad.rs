@@ -1,3 +1,5 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Button {
     Up,
     Down,
@@ -9,7 +11,75 @@ pub enum Button {
     A,
 }
 
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::Start,
+        Button::Select,
+        Button::B,
+        Button::A,
+    ];
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// The pressed state of all eight buttons, one bit each in the order of
+// `Button::ALL`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JoypadState(u8);
+
+impl JoypadState {
+    pub const NONE: JoypadState = JoypadState(0);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_pressed(self, button: Button) -> bool {
+        (self.0 & button.mask()) != 0
+    }
+
+    pub fn with(self, button: Button, pressed: bool) -> Self {
+        if pressed {
+            Self(self.0 | button.mask())
+        } else {
+            Self(self.0 & !button.mask())
+        }
+    }
+
+    // Releases both buttons of any opposing pair of directions, which the
+    // D-pad can't press together. Some games misbehave if they see it.
+    pub fn without_opposing_directions(self) -> Self {
+        let mut state = self;
+        for (first, second) in [(Button::Up, Button::Down), (Button::Left, Button::Right)] {
+            if self.is_pressed(first) && self.is_pressed(second) {
+                state = state.with(first, false).with(second, false);
+            }
+        }
+        state
+    }
+}
+
+impl FromIterator<Button> for JoypadState {
+    fn from_iter<I: IntoIterator<Item = Button>>(buttons: I) -> Self {
+        buttons
+            .into_iter()
+            .fold(Self::NONE, |state, button| state.with(button, true))
+    }
+}
+
 #[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Joypad {
     action_buttons_selected: bool,
     direction_buttons_selected: bool,
@@ -32,68 +102,103 @@ impl Joypad {
     const INPUT_LEFT_B_MASK: u8 = 1 << 1;
     const INPUT_RIGHT_A_MASK: u8 = 1 << 0;
 
-    pub fn set_up_pressed(&mut self, val: bool) {
-        if !self.up_pressed && val && self.direction_buttons_selected {
-            self.interrupt_waiting = true;
+    pub fn set_button_pressed(&mut self, button: Button, pressed: bool) {
+        self.set_state(self.state().with(button, pressed));
+    }
+
+    // Sets every button at once, so that the game never sees a mix of the old
+    // and new states.
+    pub fn set_state(&mut self, state: JoypadState) {
+        let old_input = self.read();
+        for button in Button::ALL {
+            *self.pressed_mut(button) = state.is_pressed(button);
         }
+        self.update_interrupt(old_input);
+    }
 
-        self.up_pressed = val;
+    pub fn state(&self) -> JoypadState {
+        Button::ALL
+            .into_iter()
+            .filter(|&button| self.pressed(button))
+            .collect()
     }
 
-    pub fn set_down_pressed(&mut self, val: bool) {
-        if !self.down_pressed && val && self.direction_buttons_selected {
-            self.interrupt_waiting = true;
+    fn pressed(&self, button: Button) -> bool {
+        match button {
+            Button::Up => self.up_pressed,
+            Button::Down => self.down_pressed,
+            Button::Left => self.left_pressed,
+            Button::Right => self.right_pressed,
+            Button::Start => self.start_pressed,
+            Button::Select => self.select_pressed,
+            Button::B => self.b_pressed,
+            Button::A => self.a_pressed,
         }
-
-        self.down_pressed = val;
     }
 
-    pub fn set_left_pressed(&mut self, val: bool) {
-        if !self.left_pressed && val && self.direction_buttons_selected {
-            self.interrupt_waiting = true;
+    fn pressed_mut(&mut self, button: Button) -> &mut bool {
+        match button {
+            Button::Up => &mut self.up_pressed,
+            Button::Down => &mut self.down_pressed,
+            Button::Left => &mut self.left_pressed,
+            Button::Right => &mut self.right_pressed,
+            Button::Start => &mut self.start_pressed,
+            Button::Select => &mut self.select_pressed,
+            Button::B => &mut self.b_pressed,
+            Button::A => &mut self.a_pressed,
         }
+    }
 
-        self.left_pressed = val;
+    pub fn set_up_pressed(&mut self, val: bool) {
+        self.set_button_pressed(Button::Up, val);
     }
 
-    pub fn set_right_pressed(&mut self, val: bool) {
-        if !self.right_pressed && val && self.direction_buttons_selected {
-            self.interrupt_waiting = true;
-        }
+    pub fn set_down_pressed(&mut self, val: bool) {
+        self.set_button_pressed(Button::Down, val);
+    }
 
-        self.right_pressed = val;
+    pub fn set_left_pressed(&mut self, val: bool) {
+        self.set_button_pressed(Button::Left, val);
     }
 
-    pub fn set_start_pressed(&mut self, val: bool) {
-        if !self.start_pressed && val && self.action_buttons_selected {
-            self.interrupt_waiting = true;
-        }
+    pub fn set_right_pressed(&mut self, val: bool) {
+        self.set_button_pressed(Button::Right, val);
+    }
 
-        self.start_pressed = val;
+    pub fn set_start_pressed(&mut self, val: bool) {
+        self.set_button_pressed(Button::Start, val);
     }
 
     pub fn set_select_pressed(&mut self, val: bool) {
-        if !self.select_pressed && val && self.action_buttons_selected {
-            self.interrupt_waiting = true;
-        }
-
-        self.select_pressed = val;
+        self.set_button_pressed(Button::Select, val);
     }
 
     pub fn set_b_pressed(&mut self, val: bool) {
-        if !self.b_pressed && val && self.action_buttons_selected {
-            self.interrupt_waiting = true;
-        }
-
-        self.b_pressed = val;
+        self.set_button_pressed(Button::B, val);
     }
 
     pub fn set_a_pressed(&mut self, val: bool) {
-        if !self.a_pressed && val && self.action_buttons_selected {
+        self.set_button_pressed(Button::A, val);
+    }
+
+    // The joypad interrupt is requested whenever one of the four input lines
+    // goes from high to low. That happens when a button is pressed in a
+    // selected group, but also when a group is selected while one of its
+    // buttons is already held.
+    fn update_interrupt(&mut self, old_input: u8) {
+        let input_mask = Self::INPUT_DOWN_START_MASK
+            | Self::INPUT_UP_SELECT_MASK
+            | Self::INPUT_LEFT_B_MASK
+            | Self::INPUT_RIGHT_A_MASK;
+        if old_input & !self.read() & input_mask != 0 {
             self.interrupt_waiting = true;
         }
+    }
 
-        self.a_pressed = val;
+    // Whether a joypad interrupt has been requested but not yet passed on to
+    // the interrupt flag register.
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt_waiting
     }
 
     pub fn poll_interrupt(&mut self) -> bool {
@@ -106,8 +211,10 @@ impl Joypad {
     }
 
     pub fn write(&mut self, data: u8) {
+        let old_input = self.read();
         self.action_buttons_selected = (data & Self::SELECT_ACTION_BUTTONS_MASK) == 0;
         self.direction_buttons_selected = (data & Self::SELECT_DIRECTION_BUTTONS_MASK) == 0;
+        self.update_interrupt(old_input);
     }
 
     pub fn read(&self) -> u8 {
@@ -156,3 +263,50 @@ impl Joypad {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt_on_press() {
+        let mut joypad = Joypad::default();
+        // Select the direction buttons only.
+        joypad.write(0x20);
+
+        // Unselected buttons don't reach the input lines.
+        joypad.set_button_pressed(Button::A, true);
+        assert!(!joypad.interrupt_pending());
+
+        joypad.set_button_pressed(Button::Up, true);
+        assert!(joypad.interrupt_pending());
+        assert!(joypad.poll_interrupt());
+        assert!(!joypad.interrupt_pending());
+
+        // Releasing doesn't request an interrupt, but selecting a group with a
+        // button already held does.
+        joypad.set_button_pressed(Button::Up, false);
+        assert!(!joypad.interrupt_pending());
+        joypad.write(0x10);
+        assert!(joypad.interrupt_pending());
+    }
+
+    #[test]
+    fn test_state() {
+        let mut joypad = Joypad::default();
+        let state: JoypadState = [Button::Up, Button::Start, Button::A].into_iter().collect();
+        joypad.set_state(state);
+        assert_eq!(joypad.state(), state);
+        assert_eq!(state.bits(), 0b1001_0001);
+
+        // Select the action buttons: Start and A read low.
+        joypad.write(0x10);
+        assert_eq!(joypad.read() & 0x0F, 0b0110);
+
+        let state = state.with(Button::Down, true);
+        assert_eq!(
+            state.without_opposing_directions(),
+            state.with(Button::Up, false).with(Button::Down, false)
+        );
+    }
+}
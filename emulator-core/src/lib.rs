@@ -1,10 +1,24 @@
 pub mod apu;
 pub mod bus;
 pub mod cartridge;
+pub mod cheats;
 pub mod cpu;
+pub mod doctor;
+pub mod headless;
 pub mod joypad;
+#[cfg(feature = "serde")]
+pub mod movie;
 pub mod ppu;
+pub mod printer;
+pub mod recording;
+#[cfg(feature = "serde")]
+pub mod rewind;
+#[cfg(feature = "screenshot")]
+pub mod screenshot;
 pub mod serial;
+pub mod sgb;
+#[cfg(feature = "serde")]
+pub mod state;
 pub mod timer;
 
 use cpu::Cpu;
@@ -28,20 +42,30 @@ pub fn calculate_ppu_buffer_checksum(cpu: &Cpu) -> u32 {
 mod tests {
     use super::*;
 
+    use owo_colors::OwoColorize;
+
     use super::cartridge::Cartridge;
     use super::cpu::Cpu;
+    use super::headless::HeadlessRunner;
 
-    fn test_blaarg_rom_serial_passed(rom: &[u8]) {
-        let cartridge = Cartridge::new(rom).unwrap();
-        let mut cpu = Cpu::new(cartridge);
-
-        let mut steps = 0;
-        while steps < 150_000_000 {
-            steps += u64::from(cpu.fetch_decode_execute());
+    // Highlights "Passed" in green and "Failed" in red to make test rom results
+    // easier to spot. Coloring is skipped if the NO_COLOR environment variable is set.
+    fn colorize_serial_output(serial_out: &str) -> String {
+        if std::env::var_os("NO_COLOR").is_some() {
+            return serial_out.to_string();
         }
 
-        let serial_out = cpu.bus.serial.get_data_written();
-        println!("result: {}", serial_out);
+        serial_out
+            .replace("Passed", &"Passed".green().to_string())
+            .replace("Failed", &"Failed".red().to_string())
+    }
+
+    fn test_blaarg_rom_serial_passed(rom: &[u8]) {
+        let mut runner = HeadlessRunner::new(rom).unwrap();
+        runner.run_cycles(150_000_000);
+
+        let serial_out = runner.serial_output();
+        println!("result: {}", colorize_serial_output(serial_out));
         assert!(serial_out.contains("Passed"));
     }
 
@@ -54,7 +78,19 @@ mod tests {
             steps += u64::from(cpu.fetch_decode_execute());
         }
 
-        assert_eq!(calculate_ppu_buffer_checksum(&cpu), checksum);
+        let actual = calculate_ppu_buffer_checksum(&cpu);
+
+        // Set GB_DUMP_FRAMES to save mismatched frames for a closer look.
+        #[cfg(feature = "test-artifacts")]
+        if actual != checksum && std::env::var_os("GB_DUMP_FRAMES").is_some() {
+            let path = std::env::temp_dir().join(format!("gb-frame-{:08X}.png", actual));
+            match screenshot::dump_frame_png(&cpu, &path) {
+                Ok(()) => println!("dumped mismatched frame to {}", path.display()),
+                Err(e) => println!("failed to dump mismatched frame: {}", e),
+            }
+        }
+
+        assert_eq!(actual, checksum);
     }
 
     fn test_mooneye_rom_passed(rom: &[u8]) {
@@ -66,12 +102,328 @@ mod tests {
             steps += u64::from(cpu.fetch_decode_execute());
         }
 
-        assert_eq!(cpu.read_register(cpu::RegisterByte::B), 03);
-        assert_eq!(cpu.read_register(cpu::RegisterByte::C), 05);
-        assert_eq!(cpu.read_register(cpu::RegisterByte::D), 08);
-        assert_eq!(cpu.read_register(cpu::RegisterByte::E), 13);
-        assert_eq!(cpu.read_register(cpu::RegisterByte::H), 21);
-        assert_eq!(cpu.read_register(cpu::RegisterByte::L), 34);
+        assert_eq!(cpu.register(cpu::RegisterByte::B), 03);
+        assert_eq!(cpu.register(cpu::RegisterByte::C), 05);
+        assert_eq!(cpu.register(cpu::RegisterByte::D), 08);
+        assert_eq!(cpu.register(cpu::RegisterByte::E), 13);
+        assert_eq!(cpu.register(cpu::RegisterByte::H), 21);
+        assert_eq!(cpu.register(cpu::RegisterByte::L), 34);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_input_replay_deterministic() {
+        use super::joypad::Button;
+
+        let rom = include_bytes!("../tests/pocket.gb");
+        let input_log = [
+            (30, Button::Start, true),
+            (36, Button::Start, false),
+            (60, Button::A, true),
+            (62, Button::Right, true),
+            (75, Button::A, false),
+            (90, Button::Right, false),
+            (105, Button::Down, true),
+            (112, Button::Down, false),
+        ];
+
+        // Play with live input from power on, recording it as we go.
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        cpu.start_recording();
+        let mut input_log = input_log.iter().peekable();
+        let mut recorded_checksums = Vec::new();
+        for frame in 1..=150 {
+            while let Some((_, button, pressed)) =
+                input_log.next_if(|(input_frame, _, _)| *input_frame < frame)
+            {
+                cpu.set_button_pressed(*button, *pressed);
+            }
+            assert!(cpu.run_to_frame(frame, 60));
+            recorded_checksums.push(calculate_ppu_buffer_checksum(&cpu));
+        }
+        let movie = cpu.stop_recording().unwrap();
+
+        // Replaying the recording on a fresh Cpu reproduces every frame.
+        let mut replay_cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        replay_cpu.play_movie(&movie).unwrap();
+        let replayed_checksums: Vec<_> = (1..=150)
+            .map(|frame| {
+                assert!(replay_cpu.run_to_frame(frame, 60));
+                calculate_ppu_buffer_checksum(&replay_cpu)
+            })
+            .collect();
+
+        assert_eq!(replayed_checksums, recorded_checksums);
+        assert_ne!(recorded_checksums.first(), recorded_checksums.last());
+    }
+
+    #[test]
+    fn test_run_to_frame() {
+        let rom = include_bytes!("../tests/pocket.gb");
+        let run = || {
+            let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+            assert!(cpu.run_to_frame(60, 60));
+            assert_eq!(cpu.frame_count(), 60);
+            calculate_ppu_buffer_checksum(&cpu)
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn test_headless_runner() {
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut runner = HeadlessRunner::new(rom).unwrap();
+        runner.run_frames(60);
+
+        // Frames where the LCD was off don't count towards frame_count.
+        let frame_count = runner.cpu().frame_count();
+        assert!(frame_count > 0 && frame_count <= 60);
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        cpu.run_to_frame(frame_count, 60);
+        assert_eq!(runner.ppu_checksum(), calculate_ppu_buffer_checksum(&cpu));
+
+        let cycles = runner.cycles();
+        runner.run_cycles(1000);
+        assert!(runner.cycles() >= cycles + 1000);
+    }
+
+    #[test]
+    fn test_step_frame() {
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        cpu.run_to_frame(300, 60);
+
+        for _ in 0..10 {
+            let frame = cpu.frame_count();
+            let cycles = cpu.step_frame();
+            assert_eq!(cpu.frame_count(), frame + 1);
+            // Each step can overshoot the frame boundary by up to one instruction.
+            assert!(cycles.abs_diff(ppu::DOTS_PER_FRAME) <= 24, "{}", cycles);
+        }
+    }
+
+    #[test]
+    fn test_take_frame_ready() {
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        cpu.run_to_frame(300, 60);
+        cpu.take_frame_ready();
+        while !cpu.take_frame_ready() {
+            cpu.fetch_decode_execute();
+        }
+
+        for _ in 0..10 {
+            let mut cycles = 0;
+            while !cpu.take_frame_ready() {
+                cycles += u64::from(cpu.fetch_decode_execute());
+            }
+            assert!(cycles.abs_diff(ppu::DOTS_PER_FRAME) <= 24, "{}", cycles);
+            assert!(!cpu.take_frame_ready());
+        }
+    }
+
+    #[test]
+    fn test_ppu_mode_from_cgb_flag() {
+        let run_boot_rom = |cpu: &mut Cpu| {
+            while cpu.bus.boot_rom_enabled {
+                cpu.fetch_decode_execute();
+            }
+        };
+
+        let dmg_rom = include_bytes!("../tests/dmg_acid2.gb");
+        let mut cpu = Cpu::new(Cartridge::new(dmg_rom).unwrap());
+        run_boot_rom(&mut cpu);
+        assert_eq!(cpu.bus.ppu.get_ppu_mode(), ppu::PpuMode::Dmg);
+
+        let mut cpu =
+            Cpu::new_with_mode(Cartridge::new(dmg_rom).unwrap(), ppu::PpuMode::Cgb).unwrap();
+        run_boot_rom(&mut cpu);
+        assert_eq!(cpu.bus.ppu.get_ppu_mode(), ppu::PpuMode::Cgb);
+        assert!(Cpu::new_with_mode(Cartridge::new(dmg_rom).unwrap(), ppu::PpuMode::Pgb).is_err());
+
+        let cgb_rom = include_bytes!("../tests/cgb_acid2.gb");
+        let mut cpu = Cpu::new(Cartridge::new(cgb_rom).unwrap());
+        run_boot_rom(&mut cpu);
+        assert_eq!(cpu.bus.ppu.get_ppu_mode(), ppu::PpuMode::Cgb);
+    }
+
+    #[test]
+    fn test_dmg_palette() {
+        let green_palette =
+            [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F].map(ppu::PaletteColorRgb555::from_rgb888);
+
+        let rom = include_bytes!("../tests/dmg_acid2.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        cpu.bus.ppu.set_dmg_palette(green_palette);
+        cpu.run_to_frame(300, 60);
+
+        let buffer = cpu.bus.ppu.get_buffer();
+        assert!(buffer
+            .iter()
+            .flatten()
+            .all(|color| green_palette.contains(color)));
+        assert!(buffer
+            .iter()
+            .flatten()
+            .any(|color| *color == green_palette[3]));
+    }
+
+    #[test]
+    fn test_reset() {
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        let blank_checksum = calculate_ppu_buffer_checksum(&cpu);
+        cpu.run_to_frame(300, 60);
+        let expected_checksum = calculate_ppu_buffer_checksum(&cpu);
+        assert_ne!(expected_checksum, blank_checksum);
+
+        cpu.reset(true);
+        assert_eq!(cpu.frame_count(), 0);
+        assert_eq!(calculate_ppu_buffer_checksum(&cpu), blank_checksum);
+
+        // The boot ROM should hand control to the cartridge just as it does
+        // after power on.
+        while cpu.bus.boot_rom_enabled {
+            cpu.fetch_decode_execute();
+        }
+        assert_eq!(cpu.pc(), 0x100);
+
+        cpu.run_to_frame(300, 60);
+        assert_eq!(calculate_ppu_buffer_checksum(&cpu), expected_checksum);
+
+        // Settings made by the frontend survive a reset.
+        let colors = [ppu::PaletteColorRgb555::from_rgb555(0x001F); 4];
+        cpu.bus.ppu.set_dmg_palette(colors);
+        cpu.bus.ppu.set_lcd_y_override(Some(0x90));
+        cpu.bus.ppu.set_color_correction(ppu::ColorCorrection::Cgb);
+        cpu.bus.ppu.set_render_enabled(false);
+        cpu.reset(true);
+        assert_eq!(cpu.bus.ppu.dmg_palette_override(), Some([colors; 3]));
+        assert_eq!(cpu.bus.ppu.lcd_y_override(), Some(0x90));
+        assert_eq!(cpu.bus.ppu.color_correction(), ppu::ColorCorrection::Cgb);
+        assert!(!cpu.bus.ppu.render_enabled());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_state_round_trip() {
+        fn step_cycles(cpu: &mut Cpu, cycles: u64) {
+            let mut steps = 0;
+            while steps < cycles {
+                steps += u64::from(cpu.fetch_decode_execute());
+            }
+        }
+
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+
+        step_cycles(&mut cpu, 1_000_000);
+        let state = cpu.save_state();
+
+        step_cycles(&mut cpu, 1_000_000);
+        let expected_checksum = calculate_ppu_buffer_checksum(&cpu);
+
+        let mut restored_cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        restored_cpu.load_state(&state).unwrap();
+        step_cycles(&mut restored_cpu, 1_000_000);
+
+        assert_eq!(
+            calculate_ppu_buffer_checksum(&restored_cpu),
+            expected_checksum
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_state_rejects_other_rom() {
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        let state = cpu.save_state();
+
+        // Same title and mapper, but a different revision.
+        let mut other_rom = rom.to_vec();
+        *other_rom.last_mut().unwrap() ^= 0xFF;
+        let mut other_cpu = Cpu::new(Cartridge::new(&other_rom).unwrap());
+        assert!(matches!(
+            other_cpu.load_state(&state),
+            Err(state::StateError::CartridgeMismatch)
+        ));
+
+        cpu.load_state(&state).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_state_rejects_unknown_version() {
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+
+        let mut state = cpu.save_state();
+        state[0] = state[0].wrapping_add(1);
+
+        assert!(matches!(
+            cpu.load_state(&state),
+            Err(state::StateError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_movie_playback() {
+        use super::joypad::{Button, JoypadState};
+
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        cpu.run_to_frame(60, 60);
+
+        cpu.start_recording();
+        for frame in 61..=160 {
+            let buttons = ((frame / 10) * 37) as u8;
+            cpu.set_joypad_state(JoypadState::from_bits(buttons));
+            cpu.run_to_frame(frame, 60);
+        }
+        let movie = cpu.stop_recording().unwrap();
+        assert_eq!(movie.frames(), 101);
+        let expected_checksum = calculate_ppu_buffer_checksum(&cpu);
+
+        // Host input doesn't reach the game during playback.
+        let mut replay_cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        replay_cpu.play_movie(&movie).unwrap();
+        replay_cpu.set_button_pressed(Button::Start, true);
+        replay_cpu.run_to_frame(160, 60);
+        assert!(replay_cpu.is_playing_movie());
+        assert_eq!(
+            calculate_ppu_buffer_checksum(&replay_cpu),
+            expected_checksum
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_rewind() {
+        use super::rewind::Rewind;
+
+        let rom = include_bytes!("../tests/pocket.gb");
+        let mut cpu = Cpu::new(Cartridge::new(rom).unwrap());
+        let mut rewind = Rewind::new(2, u64::from(CLOCK_FREQUENCY));
+
+        let mut checksums = Vec::new();
+        for frame in [300, 310, 320] {
+            cpu.run_to_frame(frame, 60);
+            rewind.push(&cpu);
+            checksums.push(calculate_ppu_buffer_checksum(&cpu));
+        }
+        cpu.run_to_frame(330, 60);
+
+        // The snapshot from frame 300 was dropped to make room.
+        assert_eq!(rewind.len(), 2);
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(calculate_ppu_buffer_checksum(&cpu), checksums[2]);
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(calculate_ppu_buffer_checksum(&cpu), checksums[1]);
+        assert_eq!(cpu.frame_count(), 310);
+        assert!(!rewind.step_back(&mut cpu).unwrap());
     }
 
     #[test]
@@ -0,0 +1,102 @@
+use crate::joypad::JoypadState;
+use crate::state::{self, StateError};
+
+// A recording of the buttons held on each frame, along with the save state it
+// started from. Replaying it on the same cartridge reproduces the session
+// exactly, as emulation only depends on the initial state and the inputs.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Movie {
+    initial_state: Vec<u8>,
+    inputs: Vec<JoypadState>,
+}
+
+impl Movie {
+    pub(crate) fn new(initial_state: Vec<u8>) -> Self {
+        Self {
+            initial_state,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn initial_state(&self) -> &[u8] {
+        &self.initial_state
+    }
+
+    // The input for each frame, starting with the one recording began on.
+    pub fn inputs(&self) -> &[JoypadState] {
+        &self.inputs
+    }
+
+    pub fn frames(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("failed to serialize movie")
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        state::decode(data)
+    }
+}
+
+// What the Cpu is doing with a movie. Either way, inputs only change at frame
+// boundaries.
+#[derive(Clone, Debug)]
+pub(crate) enum MovieMode {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+impl MovieMode {
+    // Returns the input to apply for the next frame. While recording, that's
+    // `held` and it's added to the movie. While playing, it's the movie's
+    // next input, or None once the movie has run out.
+    pub(crate) fn next_input(&mut self, held: JoypadState) -> Option<JoypadState> {
+        match self {
+            MovieMode::Recording(movie) => {
+                movie.inputs.push(held);
+                Some(held)
+            }
+            MovieMode::Playing { movie, frame } => {
+                let input = movie.inputs.get(*frame).copied();
+                *frame += 1;
+                input
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::Button;
+
+    #[test]
+    fn test_to_bytes() {
+        let mut mode = MovieMode::Recording(Movie::new(vec![1, 2, 3]));
+        let state = JoypadState::NONE.with(Button::Left, true);
+        mode.next_input(JoypadState::NONE);
+        mode.next_input(state);
+        let MovieMode::Recording(movie) = mode else {
+            unreachable!()
+        };
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+
+        let mut mode = MovieMode::Playing { movie, frame: 0 };
+        assert_eq!(mode.next_input(state), Some(JoypadState::NONE));
+        assert_eq!(mode.next_input(JoypadState::NONE), Some(state));
+        assert_eq!(mode.next_input(state), None);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_bad_lengths() {
+        let mut bytes = Movie::new(vec![1, 2, 3]).to_bytes();
+        // Claim a huge initial state.
+        bytes[..8].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        assert!(matches!(
+            Movie::from_bytes(&bytes),
+            Err(StateError::Decode(_))
+        ));
+    }
+}
@@ -1,12 +1,13 @@
-use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::default::Default;
 use std::fmt::Debug;
 
 pub const PPU_WIDTH: usize = 160;
 pub const PPU_HEIGHT: usize = 144;
+// 154 lines (144 visible plus 10 of vblank) of 456 dots each.
+pub const DOTS_PER_FRAME: u64 = 456 * 154;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PpuRenderStatus {
     HBlank,
     VBlank,
@@ -14,13 +15,116 @@ pub enum PpuRenderStatus {
     PixelTransfer,
 }
 
-#[derive(Clone, Copy, Debug)]
+// The kinds of CPU access that corrupt OAM on the DMG, see `Ppu::corrupt_oam`.
+// Incrementing or decrementing a 16-bit register corrupts it like a write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OamCorruption {
+    Write,
+    Read,
+    ReadDuringIncDec,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PpuMode {
     Cgb,
     Dmg,
     Pgb,
 }
 
+// The 12 palettes selectable by holding a button combination while the CGB
+// boot ROM runs a DMG title, stored as [BG, OBJ0, OBJ1] sets of 24-bit colors.
+// Ordered as: Up, Up+A, Up+B, Left, Left+A, Left+B, Down, Down+A, Down+B,
+// Right, Right+A, Right+B.
+const COMPATIBILITY_PALETTES: [[[u32; 4]; 3]; 12] = [
+    [
+        [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000],
+        [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000],
+        [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000],
+    ],
+    [
+        [0xFFFFFF, 0xFF8584, 0x943A3A, 0x000000],
+        [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000],
+        [0xFFFFFF, 0xFF8584, 0x943A3A, 0x000000],
+    ],
+    [
+        [0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108],
+        [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000],
+        [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000],
+    ],
+    [
+        [0xFFFFFF, 0x65A49B, 0x0000FE, 0x000000],
+        [0xFFFFFF, 0xFF8584, 0x943A3A, 0x000000],
+        [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000],
+    ],
+    [
+        [0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000],
+        [0xFFFFFF, 0xFF8584, 0x943A3A, 0x000000],
+        [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000],
+    ],
+    [
+        [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000],
+        [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000],
+        [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000],
+    ],
+    [
+        [0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000],
+        [0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000],
+        [0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000],
+    ],
+    [
+        [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000],
+        [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000],
+        [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000],
+    ],
+    [
+        [0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000],
+        [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000],
+        [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000],
+    ],
+    [
+        [0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000],
+        [0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000],
+        [0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000],
+    ],
+    [
+        [0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000],
+        [0xFFFFFF, 0xFF8584, 0x943A3A, 0x000000],
+        [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000],
+    ],
+    [
+        [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF],
+        [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF],
+        [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF],
+    ],
+];
+
+pub const COMPATIBILITY_PALETTE_COUNT: u8 = COMPATIBILITY_PALETTES.len() as u8;
+
+// Plain white to black shades, for use with `Ppu::set_dmg_palette`.
+pub const DMG_GRAYSCALE_PALETTE: [PaletteColorRgb555; 4] = [
+    PaletteColorRgb555::from_rgb888(0xFFFFFF),
+    PaletteColorRgb555::from_rgb888(0xAAAAAA),
+    PaletteColorRgb555::from_rgb888(0x555555),
+    PaletteColorRgb555::from_rgb888(0x000000),
+];
+
+const LCD_OFF_COLOR: PaletteColorRgb555 = PaletteColorRgb555::from_rgb888(0xFFFFFF);
+
+// Decodes a 16 byte 2bpp tile into palette indices, accessed as tile[y][x].
+fn decode_tile(tile: &[u8]) -> [[usize; 8]; 8] {
+    let mut result = [[0; 8]; 8];
+    for (row, row_data) in tile.chunks_exact(2).enumerate() {
+        let (lsb_row_color, msb_row_color) = (row_data[0], row_data[1]);
+        for (col, palette_idx) in result[row].iter_mut().enumerate() {
+            let lsb_pixel_color = (lsb_row_color & (1 << (7 - col))) != 0;
+            let msb_pixel_color = (msb_row_color & (1 << (7 - col))) != 0;
+            *palette_idx = (usize::from(msb_pixel_color) << 1) | usize::from(lsb_pixel_color);
+        }
+    }
+    result
+}
+
 #[derive(Clone, Copy, Debug)]
 enum ObjSize {
     EightByEight,
@@ -35,14 +139,73 @@ enum StatInterruptSource {
     VBlank,
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PaletteColorRgb555 {
     pub red: u8,
     pub green: u8,
     pub blue: u8,
 }
 
+impl PaletteColorRgb555 {
+    pub const fn from_rgb888(color: u32) -> Self {
+        Self {
+            red: ((color >> 16) as u8) >> 3,
+            green: ((color >> 8) as u8) >> 3,
+            blue: (color as u8) >> 3,
+        }
+    }
+
+    // Decodes a color in the CGB/SGB palette format, with red in the low five
+    // bits and blue in the high ones.
+    pub const fn from_rgb555(color: u16) -> Self {
+        Self {
+            red: (color & 0x1F) as u8,
+            green: ((color >> 5) & 0x1F) as u8,
+            blue: ((color >> 10) & 0x1F) as u8,
+        }
+    }
+
+    // Expands each 5 bit channel to 8 bits, repeating the top bits in the
+    // bottom so that 0x1F maps to 0xFF.
+    pub const fn to_rgb888(self) -> [u8; 3] {
+        [
+            (self.red << 3) | (self.red >> 2),
+            (self.green << 3) | (self.green >> 2),
+            (self.blue << 3) | (self.blue >> 2),
+        ]
+    }
+
+    pub fn to_rgb888_corrected(self, correction: ColorCorrection) -> [u8; 3] {
+        match correction {
+            ColorCorrection::None => self.to_rgb888(),
+            ColorCorrection::Cgb => {
+                // Mixes in some of the other channels and scales down to 0-240,
+                // which is roughly how the CGB's LCD washes colors out.
+                let [red, green, blue] = [self.red, self.green, self.blue].map(u16::from);
+                [
+                    red * 26 + green * 4 + blue * 2,
+                    green * 24 + blue * 8,
+                    red * 6 + green * 4 + blue * 22,
+                ]
+                .map(|channel| (channel.min(960) >> 2) as u8)
+            }
+        }
+    }
+}
+
+// How RGB555 colors are converted for display by `Ppu::fill_rgba8`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorCorrection {
+    // Expand each channel as is. Looks oversaturated next to a real CGB.
+    #[default]
+    None,
+    // The color matrix BGB and SameBoy use to mimic the CGB's LCD.
+    Cgb,
+}
+
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct SpriteAttributeInfo {
     pub y_position: u8,
     pub x_position: u8,
@@ -50,6 +213,22 @@ struct SpriteAttributeInfo {
     pub flags: u8,
 }
 
+// A decoded OAM entry, as reported by `Ppu::sprites`. Positions are the raw
+// OAM values, so the sprite's top left corner is at (x - 8, y - 16) on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpriteEntry {
+    pub x: u8,
+    pub y: u8,
+    pub tile_index: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    // Set when the background and window are drawn over the sprite.
+    pub behind_background: bool,
+    pub dmg_palette: u8,
+    pub cgb_palette: u8,
+    pub vram_bank: u8,
+}
+
 #[derive(Clone, Copy, Debug)]
 struct BackgroundPixelInfo {
     pub color: PaletteColorRgb555,
@@ -100,6 +279,7 @@ impl SpriteAttributeInfo {
 }
 
 #[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct TileMapAttributeInfo {
     pub tile_idx: u8,
     flags: u8,
@@ -141,15 +321,20 @@ impl TileMapAttributeInfo {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ppu {
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array_2d"))]
     tile_data: Box<[[u8; 0x1800]; 2]>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array"))]
     bg_map_0: Box<[TileMapAttributeInfo; 0x400]>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array"))]
     bg_map_1: Box<[TileMapAttributeInfo; 0x400]>,
     vram_bank_index: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array"))]
     object_attributes: Box<[SpriteAttributeInfo; 40]>,
     vblank_interrupt_waiting: bool,
     stat_interrupt_waiting: bool,
-    pub dot: u16,
+    dot: u16,
     pub lcd_y: u8,
     window_lcd_y: u8,
     window_y_condition_triggered: bool,
@@ -161,17 +346,32 @@ pub struct Ppu {
     scroll_y: u8,
     window_x: u8,
     window_y: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array_2d"))]
     back_buffer: Box<[[PaletteColorRgb555; PPU_WIDTH]; PPU_HEIGHT]>, // access as buffer[y][x]
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array_2d"))]
     front_buffer: Box<[[PaletteColorRgb555; PPU_WIDTH]; PPU_HEIGHT]>, // access as buffer[y][x]
     bg_palette: u8,
     obj_palette_0: u8,
     obj_palette_1: u8,
-    scanline_seen_sprites: HashSet<usize>,
+    // OAM indices of the sprites selected during OAM search, in ascending order.
+    scanline_seen_sprites: Vec<usize>,
     bg_color_palette_index: u8,
     bg_color_palette_data: Box<[[PaletteColorRgb555; 4]; 8]>,
     obj_color_palette_index: u8,
     obj_color_palette_data: Box<[[PaletteColorRgb555; 4]; 8]>,
     dmg_mode: bool,
+    // Background, OBP0 and OBP1 colors used in DMG mode in place of the ones the
+    // boot ROM picks.
+    dmg_palette_override: Option<[[PaletteColorRgb555; 4]; 3]>,
+    frame_count: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    frame_ready: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    lcd_y_override: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    color_correction: ColorCorrection,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    skip_rendering: bool,
 }
 
 impl Default for Ppu {
@@ -201,12 +401,18 @@ impl Default for Ppu {
             bg_palette: Default::default(),
             obj_palette_0: Default::default(),
             obj_palette_1: Default::default(),
-            scanline_seen_sprites: HashSet::default(),
+            scanline_seen_sprites: Vec::with_capacity(10),
             bg_color_palette_index: Default::default(),
             bg_color_palette_data: Box::new([[PaletteColorRgb555::default(); 4]; 8]),
             obj_color_palette_index: Default::default(),
             obj_color_palette_data: Box::new([[PaletteColorRgb555::default(); 4]; 8]),
             dmg_mode: false,
+            dmg_palette_override: None,
+            frame_count: 0,
+            frame_ready: false,
+            lcd_y_override: None,
+            color_correction: ColorCorrection::None,
+            skip_rendering: false,
         }
     }
 }
@@ -251,6 +457,15 @@ impl Ppu {
                         .map(|(i, _)| i)
                         .take(10),
                 );
+
+                // On DMG the sprite with the smaller X coordinate is drawn on
+                // top, with ties going to the sprite earlier in OAM. CGB only
+                // uses OAM order.
+                if self.dmg_mode {
+                    let object_attributes = &self.object_attributes;
+                    self.scanline_seen_sprites
+                        .sort_by_key(|&i| object_attributes[i].x_position);
+                }
             } else if self.dot == 252 {
                 self.set_stat_mode(PpuRenderStatus::HBlank);
 
@@ -275,52 +490,19 @@ impl Ppu {
             let buffer_y = self.lcd_y;
 
             if buffer_x < 160 {
-                let background_pixel_info = self.get_background_pixel(buffer_x, buffer_y);
-
-                self.back_buffer[usize::from(buffer_y)][usize::from(buffer_x)] =
-                    background_pixel_info.color;
-
-                // window_x is "actual_window_x + 7". Values less than 7 result in
-                // buggy behavior. For now, when window_x < 7, trigger window x
-                // condition iff render_x == 0.
+                // window_x is "actual_window_x + 7". With values below 7 the
+                // window starts at the left edge of the screen, with its first
+                // 7 - window_x columns cut off (see `get_window_pixel`). The
+                // horizontal stutter hardware shows with window_x == 0 and a
+                // fine scroll_x isn't emulated.
                 if self.window_x >= 7 {
                     self.window_x_condition_triggered |= buffer_x + 7 == self.window_x;
                 } else {
                     self.window_x_condition_triggered |= buffer_x == 0;
                 };
 
-                let window_pixel_info = self.get_window_pixel(buffer_x);
-                if let Some(BackgroundPixelInfo { color, .. }) = window_pixel_info {
-                    self.back_buffer[usize::from(buffer_y)][usize::from(buffer_x)] = color;
-                }
-
-                let sprite_pixel_info = self.get_sprite_pixel(buffer_x, buffer_y);
-                if let Some(SpritePixelInfo {
-                    color,
-                    priority_under_bg,
-                    ..
-                }) = sprite_pixel_info
-                {
-                    let window_drawn =
-                        window_pixel_info.map_or(false, |info| info.palette_idx != 0);
-                    let background_drawn = background_pixel_info.palette_idx != 0;
-
-                    let window_over_sprite = window_pixel_info
-                        .map_or(false, |info| info.priority_over_sprite && window_drawn);
-                    let background_over_sprite =
-                        background_pixel_info.priority_over_sprite && background_drawn;
-                    let sprite_under_bg_window =
-                        priority_under_bg && (background_drawn || window_drawn);
-
-                    let sprite_drawn = if self.get_bg_window_enable_priority() {
-                        !(background_over_sprite || window_over_sprite || sprite_under_bg_window)
-                    } else {
-                        true
-                    };
-
-                    if sprite_drawn {
-                        self.back_buffer[usize::from(buffer_y)][usize::from(buffer_x)] = color;
-                    }
+                if !self.skip_rendering {
+                    self.render_pixel(buffer_x, buffer_y);
                 }
             }
         }
@@ -333,7 +515,53 @@ impl Ppu {
             if self.lcd_y > 153 {
                 self.lcd_y = 0;
                 self.window_lcd_y = 0;
-                self.front_buffer = self.back_buffer.clone();
+                // Every pixel of the back buffer is redrawn before the next
+                // swap, so the stale frame it now holds is never shown. A
+                // skipped frame isn't swapped in, leaving the last rendered one.
+                if !self.skip_rendering {
+                    std::mem::swap(&mut self.front_buffer, &mut self.back_buffer);
+                }
+                self.frame_count += 1;
+                self.frame_ready = true;
+            }
+        }
+    }
+
+    fn render_pixel(&mut self, buffer_x: u8, buffer_y: u8) {
+        let background_pixel_info = self.get_background_pixel(buffer_x, buffer_y);
+
+        self.back_buffer[usize::from(buffer_y)][usize::from(buffer_x)] =
+            background_pixel_info.color;
+
+        let window_pixel_info = self.get_window_pixel(buffer_x);
+        if let Some(BackgroundPixelInfo { color, .. }) = window_pixel_info {
+            self.back_buffer[usize::from(buffer_y)][usize::from(buffer_x)] = color;
+        }
+
+        let sprite_pixel_info = self.get_sprite_pixel(buffer_x, buffer_y);
+        if let Some(SpritePixelInfo {
+            color,
+            priority_under_bg,
+            ..
+        }) = sprite_pixel_info
+        {
+            let window_drawn = window_pixel_info.map_or(false, |info| info.palette_idx != 0);
+            let background_drawn = background_pixel_info.palette_idx != 0;
+
+            let window_over_sprite =
+                window_pixel_info.map_or(false, |info| info.priority_over_sprite && window_drawn);
+            let background_over_sprite =
+                background_pixel_info.priority_over_sprite && background_drawn;
+            let sprite_under_bg_window = priority_under_bg && (background_drawn || window_drawn);
+
+            let sprite_drawn = if self.get_bg_window_enable_priority() {
+                !(background_over_sprite || window_over_sprite || sprite_under_bg_window)
+            } else {
+                true
+            };
+
+            if sprite_drawn {
+                self.back_buffer[usize::from(buffer_y)][usize::from(buffer_x)] = color;
             }
         }
     }
@@ -381,7 +609,9 @@ impl Ppu {
 
     fn get_window_pixel(&self, pixel_x: u8) -> Option<BackgroundPixelInfo> {
         if self.get_window_displayed() {
-            let window_render_x = u16::from(pixel_x + 7 - self.window_x);
+            // The condition only triggers once pixel_x + 7 has reached
+            // window_x, but saturate anyway rather than risk wrapping around.
+            let window_render_x = (u16::from(pixel_x) + 7).saturating_sub(u16::from(self.window_x));
             let window_render_y = u16::from(self.window_lcd_y);
 
             let window_tile_x = window_render_x / 8;
@@ -425,7 +655,11 @@ impl Ppu {
 
     fn get_sprite_pixel(&self, pixel_x: u8, pixel_y: u8) -> Option<SpritePixelInfo> {
         if self.get_obj_enable() {
-            for sprite_attribute_info in self.object_attributes.into_iter() {
+            for sprite_attribute_info in self
+                .scanline_seen_sprites
+                .iter()
+                .map(|&i| self.object_attributes[i])
+            {
                 match self.get_obj_size() {
                     ObjSize::EightByEight => {
                         if pixel_y + 16 >= sprite_attribute_info.y_position
@@ -527,10 +761,165 @@ impl Ppu {
         None
     }
 
+    // Whether `bank` is a VRAM bank in the current mode. DMG mode only has
+    // bank 0.
+    fn has_vram_bank(&self, bank: u8) -> bool {
+        bank == 0 || (bank == 1 && !self.dmg_mode)
+    }
+
+    // Renders all 384 tiles in the given VRAM bank (0 or 1) to a 16x24 tile
+    // (128x192 pixel) RGBA image, row-major, using a fixed grayscale palette.
+    // Returns None if there's no such bank.
+    pub fn render_tile_bank(&self, bank: u8) -> Option<Vec<u8>> {
+        const TILES_PER_ROW: usize = 16;
+        const IMAGE_WIDTH: usize = TILES_PER_ROW * 8;
+        const GRAYSCALE: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+        if !self.has_vram_bank(bank) {
+            return None;
+        }
+
+        let tile_bank = &self.tile_data[usize::from(bank)];
+        let tile_count = tile_bank.len() / 16;
+        let image_height = (tile_count / TILES_PER_ROW) * 8;
+
+        let mut result = vec![0; IMAGE_WIDTH * image_height * 4];
+        for (tile_idx, tile) in tile_bank.chunks_exact(16).enumerate() {
+            let tile_x = (tile_idx % TILES_PER_ROW) * 8;
+            let tile_y = (tile_idx / TILES_PER_ROW) * 8;
+
+            for (row, row_palette_idxs) in decode_tile(tile).iter().enumerate() {
+                for (col, &palette_idx) in row_palette_idxs.iter().enumerate() {
+                    let shade = GRAYSCALE[palette_idx];
+
+                    let pixel_idx = ((tile_y + row) * IMAGE_WIDTH) + tile_x + col;
+                    result[pixel_idx * 4..][..4].copy_from_slice(&[shade, shade, shade, 0xFF]);
+                }
+            }
+        }
+
+        Some(result)
+    }
+
+    // Returns all 40 OAM entries in OAM order.
+    pub fn sprites(&self) -> Vec<SpriteEntry> {
+        self.object_attributes
+            .iter()
+            .map(|attribute_info| SpriteEntry {
+                x: attribute_info.x_position,
+                y: attribute_info.y_position,
+                tile_index: attribute_info.tile_index,
+                x_flip: attribute_info.get_x_flip(),
+                y_flip: attribute_info.get_y_flip(),
+                behind_background: attribute_info.get_bg_window_over_obj(),
+                dmg_palette: u8::from(!attribute_info.use_low_grayscale_palette()),
+                cgb_palette: attribute_info.get_rgb_palette_number(),
+                vram_bank: attribute_info.get_tile_vram_bank(),
+            })
+            .collect()
+    }
+
+    // Returns the OAM indices of the (up to 10) sprites selected for the most
+    // recently drawn scanline, highest drawing priority first.
+    pub fn last_scanline_sprites(&self) -> Vec<usize> {
+        self.scanline_seen_sprites.clone()
+    }
+
+    // Decodes one of the 384 tiles in the given VRAM bank (0 or 1) with the
+    // given background palette. In DMG mode the palette number is ignored and
+    // BGP is used instead. Access the result as tile[y][x]. Returns None if
+    // there's no such bank or tile.
+    pub fn tile_rgba(
+        &self,
+        bank: u8,
+        tile_index: u16,
+        palette: u8,
+    ) -> Option<[[PaletteColorRgb555; 8]; 8]> {
+        if !self.has_vram_bank(bank) || tile_index >= 384 {
+            return None;
+        }
+
+        let tile = &self.tile_data[usize::from(bank)][usize::from(tile_index) * 16..][..16];
+        let attributes = TileMapAttributeInfo {
+            tile_idx: 0,
+            flags: palette & 0b111,
+        };
+
+        Some(decode_tile(tile).map(|row| {
+            row.map(|palette_idx| self.get_background_palette_color(attributes, palette_idx))
+        }))
+    }
+
+    // Renders every tile in both VRAM banks with the given background palette.
+    // Each bank is laid out as a 16x24 tile grid, with bank 1 to the right of
+    // bank 0, giving a 256x192 pixel image in row-major order. In DMG mode the
+    // bank 1 half is left blank.
+    pub fn all_tiles_rgba(&self, palette: u8) -> Vec<PaletteColorRgb555> {
+        const TILES_PER_ROW: usize = 16;
+        const IMAGE_WIDTH: usize = TILES_PER_ROW * 8 * 2;
+        const IMAGE_HEIGHT: usize = (384 / TILES_PER_ROW) * 8;
+
+        let mut result = vec![PaletteColorRgb555::default(); IMAGE_WIDTH * IMAGE_HEIGHT];
+        for bank in 0..2 {
+            for tile_index in 0..384 {
+                let tile_x = (usize::from(bank) * TILES_PER_ROW * 8)
+                    + (usize::from(tile_index) % TILES_PER_ROW) * 8;
+                let tile_y = (usize::from(tile_index) / TILES_PER_ROW) * 8;
+
+                let Some(tile) = self.tile_rgba(bank, tile_index, palette) else {
+                    continue;
+                };
+                for (row, colors) in tile.iter().enumerate() {
+                    let pixel_idx = ((tile_y + row) * IMAGE_WIDTH) + tile_x;
+                    result[pixel_idx..][..8].copy_from_slice(colors);
+                }
+            }
+        }
+
+        result
+    }
+
     pub fn get_buffer(&self) -> &[[PaletteColorRgb555; PPU_WIDTH]; PPU_HEIGHT] {
         &self.front_buffer
     }
 
+    // Returns the buffer as PPU_WIDTH * PPU_HEIGHT RGBA8 pixels, row by row.
+    pub fn buffer_rgba8(&self) -> Vec<u8> {
+        let mut rgba = vec![0; PPU_WIDTH * PPU_HEIGHT * 4];
+        self.fill_rgba8(&mut rgba);
+        rgba
+    }
+
+    // Like `buffer_rgba8`, but writes into `rgba`, which must be exactly
+    // PPU_WIDTH * PPU_HEIGHT * 4 bytes long.
+    pub fn fill_rgba8(&self, rgba: &mut [u8]) {
+        assert_eq!(rgba.len(), PPU_WIDTH * PPU_HEIGHT * 4);
+        // DMG palettes are already picked to look right on a modern screen.
+        let correction = if self.dmg_mode {
+            ColorCorrection::None
+        } else {
+            self.color_correction
+        };
+        for (pixel, color) in rgba
+            .chunks_exact_mut(4)
+            .zip(self.front_buffer.iter().flatten())
+        {
+            let [red, green, blue] = color.to_rgb888_corrected(correction);
+            pixel.copy_from_slice(&[red, green, blue, 0xFF]);
+        }
+    }
+
+    // Returns the number of frames completed since power on. Frames are only
+    // completed while the LCD is enabled.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // Returns whether a new frame has been completed since the last call.
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
     pub fn poll_vblank_interrupt(&mut self) -> bool {
         if self.vblank_interrupt_waiting {
             self.vblank_interrupt_waiting = false;
@@ -640,6 +1029,18 @@ impl Ppu {
         };
     }
 
+    // The mode the PPU is in, as reported in STAT. Together with `dot` and
+    // `lcd_y` this gives the current position in the frame, for debugging
+    // raster effects.
+    pub fn render_status(&self) -> PpuRenderStatus {
+        self.get_stat_mode()
+    }
+
+    // The dot the next step will run on within the current scanline, 0-455.
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
     pub fn get_stat_mode(&self) -> PpuRenderStatus {
         match self.stat & Self::STAT_MODE_MASK {
             Self::STAT_HBLANK_MODE_MASK => PpuRenderStatus::HBlank,
@@ -678,6 +1079,7 @@ impl Ppu {
 
     pub fn write_lcd_control(&mut self, data: u8) {
         let old_window_displayed = self.get_window_displayed();
+        let old_lcd_ppu_enable = self.get_lcd_ppu_enable();
         self.lcd_control = data;
         let new_window_displayed = self.get_window_displayed();
 
@@ -690,6 +1092,26 @@ impl Ppu {
             self.dot = 0;
             self.lcd_y = 0;
             self.set_stat_mode(PpuRenderStatus::OAMSearch);
+
+            // A disabled LCD shows a blank white screen rather than the last
+            // frame drawn.
+            if old_lcd_ppu_enable {
+                self.blank_screen();
+            }
+        }
+    }
+
+    // Shows the blank white screen of an LCD that isn't being driven, until
+    // the next frame completes.
+    pub fn blank_screen(&mut self) {
+        self.front_buffer.fill([LCD_OFF_COLOR; PPU_WIDTH]);
+    }
+
+    pub fn get_ppu_mode(&self) -> PpuMode {
+        if self.dmg_mode {
+            PpuMode::Dmg
+        } else {
+            PpuMode::Cgb
         }
     }
 
@@ -897,8 +1319,56 @@ impl Ppu {
         self.scroll_x = value;
     }
 
+    // Returns the PPU to its power-on state, keeping the settings made by the
+    // frontend.
+    pub(crate) fn reset(&mut self) {
+        let mut ppu = Ppu::default();
+        ppu.restore_settings_from(self);
+        ppu.dmg_palette_override = self.dmg_palette_override;
+        *self = ppu;
+    }
+
+    // Copies the frontend settings that aren't part of save states.
+    pub(crate) fn restore_settings_from(&mut self, other: &Ppu) {
+        self.lcd_y_override = other.lcd_y_override;
+        self.color_correction = other.color_correction;
+        self.skip_rendering = other.skip_rendering;
+    }
+
     pub fn read_lcd_y(&self) -> u8 {
-        self.lcd_y
+        self.lcd_y_override.unwrap_or(self.lcd_y)
+    }
+
+    // Makes reads of LY return `lcd_y` instead of the current scanline, for
+    // test harnesses that expect a fixed value. Rendering is unaffected.
+    pub fn set_lcd_y_override(&mut self, lcd_y: Option<u8>) {
+        self.lcd_y_override = lcd_y;
+    }
+
+    pub fn lcd_y_override(&self) -> Option<u8> {
+        self.lcd_y_override
+    }
+
+    // Sets how CGB colors are converted by `buffer_rgba8` and `fill_rgba8`.
+    // This only changes the output, not what gets emulated.
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+    }
+
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.color_correction
+    }
+
+    // With rendering disabled the PPU keeps its timing, registers and
+    // interrupts but draws no pixels, and the last rendered frame stays in the
+    // buffer. Frontends on slow hosts can skip frames by only enabling it for
+    // one frame in every few, toggling it between frames.
+    pub fn set_render_enabled(&mut self, enabled: bool) {
+        self.skip_rendering = !enabled;
+    }
+
+    pub fn render_enabled(&self) -> bool {
+        !self.skip_rendering
     }
 
     pub fn read_lcd_y_compare(&self) -> u8 {
@@ -1025,6 +1495,56 @@ impl Ppu {
         };
     }
 
+    // Applies the DMG OAM bug to the row the PPU is reading, see
+    // https://gbdev.io/pandocs/OAM_Corruption_Bug.html. Only happens during OAM
+    // search, and never to the first row, which has no preceding row to mix
+    // in. The corruption patterns are bitwise, so they're applied to each byte
+    // of a word separately.
+    pub(crate) fn corrupt_oam(&mut self, kind: OamCorruption) {
+        const ROW_SIZE: usize = 8;
+
+        if !self.get_lcd_ppu_enable() || self.get_stat_mode() != PpuRenderStatus::OAMSearch {
+            return;
+        }
+        let row = usize::from(self.dot / 4);
+        if row == 0 || row >= 20 {
+            return;
+        }
+
+        let mut oam: [u8; 0xA0] =
+            std::array::from_fn(|i| self.read_object_attribute_memory(i as u16));
+        let current = row * ROW_SIZE;
+        let preceding = current - ROW_SIZE;
+
+        if kind == OamCorruption::ReadDuringIncDec && (4..19).contains(&row) {
+            let two_before = preceding - ROW_SIZE;
+            for i in 0..2 {
+                let a = oam[two_before + i];
+                let b = oam[preceding + i];
+                let c = oam[current + i];
+                let d = oam[preceding + 4 + i];
+                oam[preceding + i] = (b & (a | c | d)) | (a & c & d);
+            }
+            oam.copy_within(preceding..current, current);
+            oam.copy_within(preceding..current, two_before);
+        }
+
+        for i in 0..2 {
+            let a = oam[current + i];
+            let b = oam[preceding + i];
+            let c = oam[preceding + 4 + i];
+            oam[current + i] = match kind {
+                OamCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+                OamCorruption::Read | OamCorruption::ReadDuringIncDec => b | (a & c),
+            };
+        }
+        oam.copy_within(preceding + 2..current, current + 2);
+
+        for (offset, data) in oam.into_iter().enumerate() {
+            self.write_object_attribute_memory(data, offset as u16);
+        }
+    }
+
     pub fn read_background_palette_index(&self) -> u8 {
         self.bg_color_palette_index
     }
@@ -1131,6 +1651,450 @@ impl Ppu {
             PpuMode::Dmg => self.dmg_mode = true,
             PpuMode::Pgb => unimplemented!(),
         };
-        println!("mode: {:?}", mode);
+
+        if self.dmg_mode {
+            self.apply_dmg_palette_override();
+        }
+    }
+
+    // Selects one of the boot ROM's button-combination palettes (indexed
+    // 0..COMPATIBILITY_PALETTE_COUNT) to colorize DMG titles, overriding the
+    // palette picked by the boot ROM from the cartridge title hash. The
+    // palette is applied immediately if already running in DMG mode, and
+    // otherwise once the boot ROM switches the PPU into DMG mode. Returns false,
+    // changing nothing, if `index` is out of range.
+    pub fn set_compatibility_palette(&mut self, index: u8) -> bool {
+        let Some(palettes) = COMPATIBILITY_PALETTES.get(usize::from(index)) else {
+            return false;
+        };
+
+        self.set_dmg_palette_override(
+            palettes.map(|palette| palette.map(PaletteColorRgb555::from_rgb888)),
+        );
+        true
+    }
+
+    // Uses `colors` for DMG shades 0 through 3 of the background and both
+    // object palettes. Like `set_compatibility_palette`, this replaces the
+    // palette picked by the boot ROM.
+    pub fn set_dmg_palette(&mut self, colors: [PaletteColorRgb555; 4]) {
+        self.set_dmg_palette_override([colors; 3]);
+    }
+
+    // The background and two object palettes used in DMG mode, if they've been
+    // replaced.
+    pub fn dmg_palette_override(&self) -> Option<[[PaletteColorRgb555; 4]; 3]> {
+        self.dmg_palette_override
+    }
+
+    fn set_dmg_palette_override(&mut self, palettes: [[PaletteColorRgb555; 4]; 3]) {
+        self.dmg_palette_override = Some(palettes);
+        if self.dmg_mode {
+            self.apply_dmg_palette_override();
+        }
+    }
+
+    fn apply_dmg_palette_override(&mut self) {
+        if let Some([bg, obj_0, obj_1]) = self.dmg_palette_override {
+            self.bg_color_palette_data[0] = bg;
+            self.obj_color_palette_data[0] = obj_0;
+            self.obj_color_palette_data[1] = obj_1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lcd_y_override() {
+        let mut ppu = Ppu::default();
+        ppu.write_lcd_control(0x80);
+        ppu.set_lcd_y_override(Some(0x90));
+
+        for _ in 0..3 {
+            for _ in 0..1000 {
+                ppu.step();
+            }
+            assert_eq!(ppu.read_lcd_y(), 0x90);
+        }
+        // The real scanline keeps advancing underneath.
+        assert_eq!(ppu.lcd_y, 6);
+
+        ppu.set_lcd_y_override(None);
+        assert_eq!(ppu.read_lcd_y(), 6);
+    }
+
+    #[test]
+    fn test_render_disabled() {
+        let mut ppu = Ppu::default();
+        ppu.write_lcd_control(0x80);
+        ppu.set_render_enabled(false);
+        let sentinel = PaletteColorRgb555::from_rgb555(0x001F);
+        ppu.front_buffer[0][0] = sentinel;
+
+        for _ in 0..456 * 144 {
+            ppu.step();
+        }
+        assert_eq!(ppu.lcd_y, 144);
+        ppu.step();
+        assert!(ppu.vblank_interrupt_waiting);
+
+        // The frame still completes, but the buffer keeps the last one drawn.
+        for _ in 1..456 * 10 {
+            ppu.step();
+        }
+        assert_eq!(ppu.lcd_y, 0);
+        assert_eq!(ppu.frame_count(), 1);
+        assert_eq!(ppu.front_buffer[0][0], sentinel);
+
+        ppu.set_render_enabled(true);
+        for _ in 0..456 * 154 {
+            ppu.step();
+        }
+        assert_eq!(ppu.frame_count(), 2);
+        assert_ne!(ppu.front_buffer[0][0], sentinel);
+    }
+
+    #[test]
+    fn test_compatibility_palette() {
+        let mut ppu = Ppu::default();
+        assert!(ppu.set_compatibility_palette(COMPATIBILITY_PALETTE_COUNT - 1));
+        let palettes = ppu.dmg_palette_override();
+        assert!(palettes.is_some());
+
+        assert!(!ppu.set_compatibility_palette(COMPATIBILITY_PALETTE_COUNT));
+        assert!(!ppu.set_compatibility_palette(0xFF));
+        assert_eq!(ppu.dmg_palette_override(), palettes);
+    }
+
+    #[test]
+    fn test_render_status() {
+        let mut ppu = Ppu::default();
+        // The PPU reports HBlank until the first dot after the LCD turns on.
+        ppu.write_lcd_control(0x80);
+        assert_eq!(ppu.render_status(), PpuRenderStatus::HBlank);
+        assert_eq!(ppu.dot(), 0);
+
+        let mut transitions = Vec::new();
+        let mut status = ppu.render_status();
+        for _ in 0..=456 {
+            let dot = ppu.dot();
+            ppu.step();
+            if ppu.render_status() != status {
+                status = ppu.render_status();
+                transitions.push((ppu.lcd_y, dot, status));
+            }
+        }
+
+        assert_eq!(
+            transitions,
+            [
+                (0, 0, PpuRenderStatus::OAMSearch),
+                (0, 80, PpuRenderStatus::PixelTransfer),
+                (0, 252, PpuRenderStatus::HBlank),
+                (1, 0, PpuRenderStatus::OAMSearch),
+            ]
+        );
+        assert_eq!(ppu.dot(), 1);
+    }
+
+    #[test]
+    fn test_oam_bug() {
+        // OAM holds 0x00-0x9F, with the words used by the write and read
+        // patterns in rows 1 and 2 set apart.
+        let corrupted = |dots: u16, kind: OamCorruption| {
+            let mut ppu = Ppu::default();
+            for offset in 0..0xA0 {
+                ppu.write_object_attribute_memory(offset as u8, offset);
+            }
+            for (offset, data) in [(16, 0xF0), (17, 0xF0), (8, 0xCC), (9, 0xCC)] {
+                ppu.write_object_attribute_memory(data, offset);
+            }
+            ppu.write_object_attribute_memory(0xAA, 12);
+            ppu.write_object_attribute_memory(0xAA, 13);
+
+            ppu.write_lcd_control(0x80);
+            for _ in 0..dots {
+                ppu.step();
+            }
+            ppu.corrupt_oam(kind);
+            std::array::from_fn::<u8, 0xA0, _>(|i| ppu.read_object_attribute_memory(i as u16))
+        };
+        let untouched = corrupted(0, OamCorruption::Write);
+        assert_eq!(untouched[16..18], [0xF0, 0xF0]);
+
+        // Row 2 is being read after 9 dots. The first word mixes in row 1,
+        // and the rest is copied from it.
+        let oam = corrupted(9, OamCorruption::Write);
+        assert_eq!(oam[16..24], [0xE8, 0xE8, 10, 11, 0xAA, 0xAA, 14, 15]);
+        assert_eq!(oam[..16], untouched[..16]);
+        assert_eq!(oam[24..], untouched[24..]);
+        let oam = corrupted(9, OamCorruption::Read);
+        assert_eq!(oam[16..18], [0xEC, 0xEC]);
+
+        // The first row is never corrupted.
+        assert_eq!(corrupted(1, OamCorruption::Write), untouched);
+
+        // Reading with an increment at row 4 leaves rows 2-4 as copies of
+        // row 3, whose first word works out unchanged here.
+        let oam = corrupted(17, OamCorruption::ReadDuringIncDec);
+        for row in 2..=4 {
+            assert_eq!(oam[row * 8..row * 8 + 8], untouched[24..32]);
+        }
+        assert_eq!(oam[40..], untouched[40..]);
+    }
+
+    #[test]
+    fn test_buffer_rgba8() {
+        let mut ppu = Ppu::default();
+        let color = PaletteColorRgb555 {
+            red: 0x1F,
+            green: 0x10,
+            blue: 0x01,
+        };
+        ppu.front_buffer[0][0] = color;
+
+        let rgba = ppu.buffer_rgba8();
+        assert_eq!(rgba.len(), PPU_WIDTH * PPU_HEIGHT * 4);
+        assert_eq!(
+            rgba[..4],
+            [
+                (color.red << 3) | (color.red >> 2),
+                (color.green << 3) | (color.green >> 2),
+                (color.blue << 3) | (color.blue >> 2),
+                0xFF,
+            ]
+        );
+        assert_eq!(rgba[..4], [0xFF, 0x84, 0x08, 0xFF]);
+    }
+
+    #[test]
+    fn test_color_correction() {
+        let mut ppu = Ppu::default();
+        ppu.set_ppu_mode(PpuMode::Cgb);
+        ppu.front_buffer[0][0] = PaletteColorRgb555::from_rgb555(0x001F);
+        assert_eq!(ppu.buffer_rgba8()[..4], [0xFF, 0x00, 0x00, 0xFF]);
+
+        ppu.set_color_correction(ColorCorrection::Cgb);
+        let rgba = ppu.buffer_rgba8();
+        assert!(rgba[0] < 0xD0);
+        assert_eq!(rgba[1], 0x00);
+        assert!(rgba[2] > 0x00);
+
+        // DMG palettes are shown as they are.
+        ppu.set_ppu_mode(PpuMode::Dmg);
+        assert_eq!(ppu.buffer_rgba8()[..4], [0xFF, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_decode_tile() {
+        // Row 0 has every color index from left to right, row 1 is all color 3
+        // and the rest are all color 0.
+        let mut tile = [0; 16];
+        tile[0] = 0b0101_0101;
+        tile[1] = 0b0011_0011;
+        tile[2] = 0xFF;
+        tile[3] = 0xFF;
+
+        let pixels = decode_tile(&tile);
+        assert_eq!(pixels[0], [0, 1, 2, 3, 0, 1, 2, 3]);
+        assert_eq!(pixels[1], [3; 8]);
+        assert!(pixels[2..].iter().all(|row| *row == [0; 8]));
+    }
+
+    #[test]
+    fn test_render_tile_bank() {
+        let mut ppu = Ppu::default();
+        // Bank 0, tile 17 (second row, second column): the top row is color 1.
+        ppu.write_vram(0xFF, 17 * 16);
+        ppu.write_vram(0x00, 17 * 16 + 1);
+        // Bank 1, tile 0: the leftmost pixel of row 2 is color 3.
+        ppu.write_vram_bank(1);
+        ppu.write_vram(0x80, 4);
+        ppu.write_vram(0x80, 5);
+
+        let pixel = |image: &[u8], x: usize, y: usize| -> [u8; 4] {
+            image[(y * 128 + x) * 4..][..4].try_into().unwrap()
+        };
+        const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+        let bank_0 = ppu.render_tile_bank(0).unwrap();
+        assert_eq!(bank_0.len(), 128 * 192 * 4);
+        for x in 8..16 {
+            assert_eq!(pixel(&bank_0, x, 8), [0xAA, 0xAA, 0xAA, 0xFF]);
+        }
+        assert_eq!(pixel(&bank_0, 7, 8), WHITE);
+        assert_eq!(pixel(&bank_0, 8, 9), WHITE);
+        assert_eq!(pixel(&bank_0, 0, 2), WHITE);
+
+        let bank_1 = ppu.render_tile_bank(1).unwrap();
+        assert_eq!(pixel(&bank_1, 0, 2), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&bank_1, 1, 2), WHITE);
+        assert_eq!(pixel(&bank_1, 8, 8), WHITE);
+
+        assert_eq!(ppu.render_tile_bank(2), None);
+        ppu.set_ppu_mode(PpuMode::Dmg);
+        assert!(ppu.render_tile_bank(0).is_some());
+        assert_eq!(ppu.render_tile_bank(1), None);
+    }
+
+    #[test]
+    fn test_tile_rgba() {
+        let mut ppu = Ppu::default();
+        ppu.set_ppu_mode(PpuMode::Dmg);
+        ppu.set_dmg_palette(DMG_GRAYSCALE_PALETTE);
+        ppu.write_bg_palette(0b1110_0100);
+        // Tile 383's top row is color 3.
+        ppu.write_vram(0xFF, 383 * 16);
+        ppu.write_vram(0xFF, 383 * 16 + 1);
+
+        let tile = ppu.tile_rgba(0, 383, 0).unwrap();
+        assert_eq!(tile[0], [DMG_GRAYSCALE_PALETTE[3]; 8]);
+        assert_eq!(tile[1], [DMG_GRAYSCALE_PALETTE[0]; 8]);
+
+        assert_eq!(ppu.tile_rgba(0, 384, 0), None);
+        assert_eq!(ppu.tile_rgba(1, 0, 0), None);
+        assert_eq!(ppu.tile_rgba(2, 0, 0), None);
+        ppu.set_ppu_mode(PpuMode::Cgb);
+        assert!(ppu.tile_rgba(1, 0, 0).is_some());
+    }
+
+    #[test]
+    fn test_sprites() {
+        let mut ppu = Ppu::default();
+        for (offset, value) in [0x14u8, 0x20, 0x42, 0b0111_1011].into_iter().enumerate() {
+            ppu.write_object_attribute_memory(value, (5 * 4) + offset as u16);
+        }
+
+        let sprites = ppu.sprites();
+        assert_eq!(sprites.len(), 40);
+        assert_eq!(
+            sprites[5],
+            SpriteEntry {
+                x: 0x20,
+                y: 0x14,
+                tile_index: 0x42,
+                x_flip: true,
+                y_flip: true,
+                behind_background: false,
+                dmg_palette: 1,
+                cgb_palette: 3,
+                vram_bank: 1,
+            }
+        );
+        assert_eq!(sprites[4].tile_index, 0);
+    }
+
+    #[test]
+    fn test_dmg_sprite_priority_by_x() {
+        let mut ppu = Ppu::default();
+        ppu.set_ppu_mode(PpuMode::Dmg);
+        ppu.set_dmg_palette(DMG_GRAYSCALE_PALETTE);
+        ppu.write_obj_palette_0(0b1110_0100);
+
+        // Tile 1 is all color 1, tile 2 is all color 3.
+        for row in 0..8 {
+            ppu.write_vram(0xFF, 0x10 + row * 2);
+            ppu.write_vram(0xFF, 0x20 + row * 2);
+            ppu.write_vram(0xFF, 0x20 + row * 2 + 1);
+        }
+
+        // The first sprite in OAM covers x 4..12, the second covers x 0..8.
+        for (offset, value) in [16, 12, 1, 0, 16, 8, 2, 0].into_iter().enumerate() {
+            ppu.write_object_attribute_memory(value, offset as u16);
+        }
+
+        ppu.write_lcd_control(0x83);
+        for _ in 0..DOTS_PER_FRAME {
+            ppu.step();
+        }
+
+        let row = &ppu.get_buffer()[0];
+        assert_eq!(row[2], DMG_GRAYSCALE_PALETTE[3]);
+        assert_eq!(row[6], DMG_GRAYSCALE_PALETTE[3]);
+        assert_eq!(row[10], DMG_GRAYSCALE_PALETTE[1]);
+    }
+
+    #[test]
+    fn test_buffer_swap() {
+        let mut ppu = Ppu::default();
+        ppu.set_ppu_mode(PpuMode::Dmg);
+        ppu.set_dmg_palette(DMG_GRAYSCALE_PALETTE);
+        ppu.write_lcd_control(0x81);
+
+        // Tile 0 is all color 0 and fills the background, so each frame is a
+        // single shade picked by the background palette.
+        for shade in [3, 1, 3] {
+            ppu.write_bg_palette(shade);
+            for _ in 0..DOTS_PER_FRAME {
+                ppu.step();
+            }
+            assert!(ppu
+                .get_buffer()
+                .iter()
+                .flatten()
+                .all(|&c| c == DMG_GRAYSCALE_PALETTE[usize::from(shade)]));
+        }
+    }
+
+    #[test]
+    fn test_window_x_below_7() {
+        // Every row of tile 0 has color 1 in its last column only.
+        let render_row = |window_x| {
+            let mut ppu = Ppu::default();
+            ppu.set_ppu_mode(PpuMode::Dmg);
+            ppu.set_dmg_palette(DMG_GRAYSCALE_PALETTE);
+            ppu.write_bg_palette(0b1110_0100);
+            for row in 0..8 {
+                ppu.write_vram(0x01, row * 2);
+            }
+            ppu.write_window_x(window_x);
+            ppu.write_window_y(0);
+            ppu.write_lcd_control(0xB1);
+            for _ in 0..DOTS_PER_FRAME * 2 {
+                ppu.step();
+            }
+            ppu.get_buffer()[0].map(|color| color == DMG_GRAYSCALE_PALETTE[1])
+        };
+
+        // With the window's first 7 - window_x columns cut off, its column 7
+        // lands on screen x window_x, then every 8 pixels after.
+        for window_x in 0..7 {
+            let row = render_row(window_x);
+            for (x, &drawn) in row.iter().enumerate() {
+                assert_eq!(
+                    drawn,
+                    x % 8 == usize::from(window_x),
+                    "window_x {}",
+                    window_x
+                );
+            }
+        }
+
+        // The background alone puts it on screen x 7.
+        assert!(render_row(0xFF)[7]);
+    }
+
+    #[test]
+    fn test_lcd_off_buffer_is_white() {
+        let mut ppu = Ppu::default();
+        ppu.write_lcd_control(0x80);
+        for _ in 0..DOTS_PER_FRAME {
+            ppu.step();
+        }
+        assert!(ppu
+            .get_buffer()
+            .iter()
+            .flatten()
+            .all(|&c| c != LCD_OFF_COLOR));
+
+        ppu.write_lcd_control(0x00);
+        assert!(ppu
+            .get_buffer()
+            .iter()
+            .flatten()
+            .all(|&c| c == LCD_OFF_COLOR));
     }
 }
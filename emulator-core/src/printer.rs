@@ -0,0 +1,287 @@
+use crate::ppu::{PaletteColorRgb555, DMG_GRAYSCALE_PALETTE};
+use crate::serial::SerialLink;
+
+#[derive(Clone, Copy, Debug)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// A Game Boy Printer on the other end of the link cable. Games send it packets
+// of the form
+//
+//  0x88 0x33 <command> <compression> <length lo> <length hi> <data...>
+//  <checksum lo> <checksum hi> 0x00 0x00
+//
+// and the printer answers the last two bytes with 0x81 and its status. Tile
+// data sent with DATA packets is buffered until a PRINT packet, at which point
+// it's rendered onto the printed image.
+pub struct GbPrinter {
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    tile_data: Vec<u8>,
+    image: Vec<PaletteColorRgb555>,
+}
+
+impl Default for GbPrinter {
+    fn default() -> Self {
+        Self {
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            tile_data: Vec::new(),
+            image: Vec::new(),
+        }
+    }
+}
+
+impl GbPrinter {
+    pub const IMAGE_WIDTH: usize = 160;
+
+    const TILES_PER_ROW: usize = Self::IMAGE_WIDTH / 8;
+    const BYTES_PER_TILE: usize = 16;
+    // The printer has room for 9 DATA packets of 2 tile rows each.
+    const TILE_DATA_CAPACITY: usize = 9 * 2 * Self::TILES_PER_ROW * Self::BYTES_PER_TILE;
+
+    const COMMAND_INIT: u8 = 0x01;
+    const COMMAND_PRINT: u8 = 0x02;
+    const COMMAND_DATA: u8 = 0x04;
+    const COMMAND_STATUS: u8 = 0x0F;
+
+    const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+    const STATUS_IMAGE_DATA_FULL: u8 = 1 << 2;
+    const STATUS_UNPROCESSED_DATA: u8 = 1 << 3;
+
+    const ALIVE: u8 = 0x81;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns everything printed since the last call, IMAGE_WIDTH pixels per
+    // row.
+    pub fn take_image(&mut self) -> Vec<PaletteColorRgb555> {
+        std::mem::take(&mut self.image)
+    }
+
+    fn run_command(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= Self::STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !Self::STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            Self::COMMAND_INIT => {
+                self.tile_data.clear();
+                self.status = 0;
+            }
+            Self::COMMAND_PRINT => {
+                // The third byte of a PRINT packet is the palette, in the same
+                // format as BGP. Zero is treated as the default palette.
+                let palette = match self.data.get(2) {
+                    Some(0) | None => 0b1110_0100,
+                    Some(&palette) => palette,
+                };
+                self.print(palette);
+            }
+            Self::COMMAND_DATA => {
+                if self.compressed {
+                    Self::decompress_into(&self.data, &mut self.tile_data);
+                } else {
+                    self.tile_data.extend_from_slice(&self.data);
+                }
+
+                if self.tile_data.len() >= Self::TILE_DATA_CAPACITY {
+                    self.tile_data.truncate(Self::TILE_DATA_CAPACITY);
+                    self.status |= Self::STATUS_IMAGE_DATA_FULL;
+                }
+                if !self.tile_data.is_empty() {
+                    self.status |= Self::STATUS_UNPROCESSED_DATA;
+                }
+            }
+            Self::COMMAND_STATUS => {}
+            // Unknown commands are ignored.
+            _ => {}
+        }
+    }
+
+    // Runs are encoded as a control byte followed by either (n & 0x7F) + 1
+    // literal bytes, or a single byte repeated (n & 0x7F) + 2 times when the
+    // top bit of n is set.
+    fn decompress_into(data: &[u8], output: &mut Vec<u8>) {
+        let mut bytes = data.iter().copied();
+        while let Some(control) = bytes.next() {
+            if control & 0x80 != 0 {
+                let count = usize::from(control & 0x7F) + 2;
+                if let Some(value) = bytes.next() {
+                    output.resize(output.len() + count, value);
+                }
+            } else {
+                let count = usize::from(control) + 1;
+                output.extend(bytes.by_ref().take(count));
+            }
+        }
+    }
+
+    fn print(&mut self, palette: u8) {
+        let row_bytes = Self::TILES_PER_ROW * Self::BYTES_PER_TILE;
+        for tile_row in self.tile_data.chunks_exact(row_bytes) {
+            for y in 0..8 {
+                for x in 0..Self::IMAGE_WIDTH {
+                    let tile = &tile_row[(x / 8) * Self::BYTES_PER_TILE..];
+                    let low = tile[y * 2];
+                    let high = tile[y * 2 + 1];
+                    let bit = 7 - (x % 8);
+                    let color_index = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+                    let shade = (palette >> (color_index * 2)) & 0b11;
+                    self.image.push(DMG_GRAYSCALE_PALETTE[usize::from(shade)]);
+                }
+            }
+        }
+
+        self.tile_data.clear();
+        self.status &= !(Self::STATUS_UNPROCESSED_DATA | Self::STATUS_IMAGE_DATA_FULL);
+    }
+}
+
+impl SerialLink for GbPrinter {
+    fn exchange(&mut self, out: u8) -> u8 {
+        let mut response = 0x00;
+
+        self.state = match self.state {
+            PacketState::Magic1 if out == 0x88 => PacketState::Magic2,
+            PacketState::Magic1 => PacketState::Magic1,
+            PacketState::Magic2 if out == 0x33 => PacketState::Command,
+            PacketState::Magic2 if out == 0x88 => PacketState::Magic2,
+            PacketState::Magic2 => PacketState::Magic1,
+            PacketState::Command => {
+                self.command = out;
+                self.checksum = u16::from(out);
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = (out & 1) != 0;
+                self.checksum = self.checksum.wrapping_add(u16::from(out));
+                PacketState::LengthLow
+            }
+            PacketState::LengthLow => {
+                self.length = u16::from(out);
+                self.checksum = self.checksum.wrapping_add(u16::from(out));
+                PacketState::LengthHigh
+            }
+            PacketState::LengthHigh => {
+                self.length |= u16::from(out) << 8;
+                self.checksum = self.checksum.wrapping_add(u16::from(out));
+                self.data.clear();
+                if self.length == 0 {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::Data => {
+                self.data.push(out);
+                self.checksum = self.checksum.wrapping_add(u16::from(out));
+                if self.data.len() == usize::from(self.length) {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = u16::from(out);
+                PacketState::ChecksumHigh
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= u16::from(out) << 8;
+                PacketState::Alive
+            }
+            PacketState::Alive => {
+                self.run_command();
+                response = Self::ALIVE;
+                PacketState::Status
+            }
+            PacketState::Status => {
+                response = self.status;
+                PacketState::Magic1
+            }
+        };
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(printer: &mut GbPrinter, command: u8, data: &[u8]) -> [u8; 2] {
+        let length = data.len() as u16;
+        let mut packet = vec![0x88, 0x33, command, 0x00];
+        packet.extend_from_slice(&length.to_le_bytes());
+        packet.extend_from_slice(data);
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(u16::from(byte)));
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(&[0x00, 0x00]);
+
+        let responses: Vec<u8> = packet.iter().map(|&byte| printer.exchange(byte)).collect();
+        [
+            responses[responses.len() - 2],
+            responses[responses.len() - 1],
+        ]
+    }
+
+    #[test]
+    fn test_print_image() {
+        let mut printer = GbPrinter::new();
+
+        assert_eq!(send_packet(&mut printer, 0x01, &[]), [0x81, 0x00]);
+
+        // Two rows of tiles, all set to color index 3.
+        let tile_data = [0xFF; 2 * 20 * 16];
+        assert_eq!(send_packet(&mut printer, 0x04, &tile_data), [0x81, 0x08]);
+        assert_eq!(send_packet(&mut printer, 0x04, &[]), [0x81, 0x08]);
+        assert_eq!(
+            send_packet(&mut printer, 0x02, &[0x01, 0x13, 0xE4, 0x40]),
+            [0x81, 0x00]
+        );
+
+        let image = printer.take_image();
+        assert_eq!(image.len(), GbPrinter::IMAGE_WIDTH * 16);
+        assert!(image.iter().all(|&color| color == DMG_GRAYSCALE_PALETTE[3]));
+        assert!(printer.take_image().is_empty());
+    }
+
+    #[test]
+    fn test_checksum_error() {
+        let mut printer = GbPrinter::new();
+        let responses: Vec<u8> = [0x88, 0x33, 0x01, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00]
+            .iter()
+            .map(|&byte| printer.exchange(byte))
+            .collect();
+        assert_eq!(responses[8..], [0x81, 0x01]);
+    }
+}
@@ -0,0 +1,267 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::apu::{Apu, OutputFilter};
+use crate::CLOCK_FREQUENCY;
+
+// Records the APU's output at a fixed sample rate as a 16 bit stereo PCM WAV
+// file, and optionally each channel as its own mono WAV file. Samples are
+// written out as they're taken, so the writers should be buffered (e.g. a
+// BufWriter around a File).
+pub struct AudioRecorder<W: Write + Seek> {
+    sample_rate: u32,
+    emulation_steps: u64,
+    audio_steps: u64,
+    filter: OutputFilter,
+    output: WavWriter<W>,
+    // Pulse 1, pulse 2, wave and noise.
+    stems: Option<[WavWriter<W>; 4]>,
+}
+
+impl<W: Write + Seek> AudioRecorder<W> {
+    // Writes a placeholder header to `writer`, which `finish` fills in.
+    pub fn new(sample_rate: u32, writer: W) -> io::Result<Self> {
+        if sample_rate == 0 || sample_rate > CLOCK_FREQUENCY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid sample rate",
+            ));
+        }
+
+        Ok(Self {
+            sample_rate,
+            emulation_steps: 0,
+            audio_steps: 0,
+            filter: OutputFilter::default(),
+            output: WavWriter::new(writer, 2, sample_rate)?,
+            stems: None,
+        })
+    }
+
+    // As `new`, but also records each channel before mixing to the matching
+    // writer in `stems`, in the order pulse 1, pulse 2, wave, noise.
+    pub fn new_with_stems(sample_rate: u32, writer: W, stems: [W; 4]) -> io::Result<Self> {
+        let mut recorder = Self::new(sample_rate, writer)?;
+        let [pulse_1, pulse_2, wave, noise] = stems;
+        recorder.stems = Some([
+            WavWriter::new(pulse_1, 1, sample_rate)?,
+            WavWriter::new(pulse_2, 1, sample_rate)?,
+            WavWriter::new(wave, 1, sample_rate)?,
+            WavWriter::new(noise, 1, sample_rate)?,
+        ]);
+        Ok(recorder)
+    }
+
+    // Call after every instruction with the number of t-cycles it took. Takes
+    // samples from the APU so that the recording keeps pace with the CPU
+    // clock, the same way the native frontend's audio output does.
+    pub fn record(&mut self, apu: &mut Apu, steps_executed: u32) -> io::Result<()> {
+        while (self.audio_steps * u64::from(CLOCK_FREQUENCY) / u64::from(self.sample_rate))
+            < self.emulation_steps
+        {
+            let [left, right] = apu.sample_filtered(&mut self.filter);
+            self.output.write_sample(&[left, right])?;
+            if let Some(stems) = &mut self.stems {
+                for (stem, sample) in stems.iter_mut().zip(apu.sample_channels()) {
+                    stem.write_sample(&[sample])?;
+                }
+            }
+            self.audio_steps += 1;
+        }
+
+        self.emulation_steps += u64::from(steps_executed);
+        Ok(())
+    }
+
+    pub fn sample_count(&self) -> u64 {
+        self.audio_steps
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.output.finish()?;
+        for stem in self.stems.into_iter().flatten() {
+            stem.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// A PCM WAV file being written, whose sizes are only known once it's
+// finished.
+struct WavWriter<W: Write + Seek> {
+    writer: W,
+    channels: u16,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    const BITS_PER_SAMPLE: u16 = 16;
+    const RIFF_SIZE_OFFSET: u64 = 4;
+    const DATA_SIZE_OFFSET: u64 = 40;
+
+    fn new(mut writer: W, channels: u16, sample_rate: u32) -> io::Result<Self> {
+        let block_align = channels * (Self::BITS_PER_SAMPLE / 8);
+        let byte_rate = sample_rate * u32::from(block_align);
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&36u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&byte_rate.to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&Self::BITS_PER_SAMPLE.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            channels,
+            data_size: 0,
+        })
+    }
+
+    // Writes one sample for each channel.
+    fn write_sample(&mut self, sample: &[f32]) -> io::Result<()> {
+        debug_assert_eq!(sample.len(), usize::from(self.channels));
+
+        let size = u32::from(self.channels * (Self::BITS_PER_SAMPLE / 8));
+        self.data_size = self
+            .data_size
+            .checked_add(size)
+            .filter(|data_size| data_size.checked_add(36).is_some())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "recording too long"))?;
+
+        for &value in sample {
+            self.writer.write_all(&Self::to_pcm(value).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(Self::RIFF_SIZE_OFFSET))?;
+        self.writer
+            .write_all(&(36 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(Self::DATA_SIZE_OFFSET))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()
+    }
+
+    fn to_pcm(sample: f32) -> i16 {
+        (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_header() {
+        let mut apu = Apu::default();
+        let mut wav = Cursor::new(Vec::new());
+        let mut recorder = AudioRecorder::new(44100, &mut wav).unwrap();
+        for _ in 0..100_000 {
+            apu.step();
+            recorder.record(&mut apu, 4).unwrap();
+        }
+
+        // 400000 t-cycles at 44100Hz.
+        let sample_count = recorder.sample_count() as usize;
+        assert_eq!(sample_count, 4206);
+        recorder.finish().unwrap();
+        let wav = wav.into_inner();
+
+        let u16_at = |offset: usize| u16::from_le_bytes([wav[offset], wav[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(20), 1);
+        assert_eq!(u16_at(22), 2);
+        assert_eq!(u32_at(24), 44100);
+        assert_eq!(u32_at(28), 44100 * 4);
+        assert_eq!(u16_at(32), 4);
+        assert_eq!(u16_at(34), 16);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(40) as usize, sample_count * 4);
+        assert_eq!(wav.len(), 44 + sample_count * 4);
+
+        assert!(AudioRecorder::new(0, Cursor::new(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn test_recording_unaffected_by_buffered_output() {
+        let record = |sample_rate| {
+            let mut apu = Apu::default();
+            apu.set_sample_rate(sample_rate).unwrap();
+            apu.write_nr52(0x80);
+            apu.write_nr50(0x77);
+            apu.write_nr51(0xFF);
+            apu.write_nr21(0x80, false);
+            apu.write_nr22(0xF0);
+            apu.write_nr24(0x87);
+
+            let mut wav = Cursor::new(Vec::new());
+            let mut recorder = AudioRecorder::new(44100, &mut wav).unwrap();
+            for _ in 0..10_000 {
+                for _ in 0..4 {
+                    apu.step();
+                }
+                recorder.record(&mut apu, 4).unwrap();
+            }
+            recorder.finish().unwrap();
+            wav.into_inner()
+        };
+
+        assert_eq!(record(48000), record(0));
+    }
+
+    #[test]
+    fn test_stems() {
+        let mut apu = Apu::default();
+        apu.write_nr52(0x80);
+
+        // Only channel 2 plays.
+        apu.write_nr21(0x80, false);
+        apu.write_nr22(0xF0);
+        apu.write_nr23(0x00);
+        apu.write_nr24(0x87);
+
+        let mut mixed = Cursor::new(Vec::new());
+        let mut stems: [Cursor<Vec<u8>>; 4] = Default::default();
+        let [pulse_1, pulse_2, wave, noise] = &mut stems;
+        let mut recorder =
+            AudioRecorder::new_with_stems(44100, &mut mixed, [pulse_1, pulse_2, wave, noise])
+                .unwrap();
+        for _ in 0..10_000 {
+            apu.step();
+            recorder.record(&mut apu, 4).unwrap();
+        }
+        let sample_count = recorder.sample_count() as usize;
+        recorder.finish().unwrap();
+
+        assert_eq!(mixed.get_ref().len(), 44 + sample_count * 4);
+        for (index, stem) in stems.iter().enumerate() {
+            let stem = stem.get_ref();
+            assert_eq!(u16::from_le_bytes([stem[22], stem[23]]), 1);
+            assert_eq!(
+                u32::from_le_bytes(stem[40..44].try_into().unwrap()) as usize,
+                sample_count * 2
+            );
+            assert_eq!(stem.len(), 44 + sample_count * 2);
+
+            let silent = stem[44..].iter().all(|&byte| byte == 0);
+            assert_eq!(silent, index != 1, "stem {}", index);
+        }
+    }
+}
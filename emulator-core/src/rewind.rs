@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+
+use crate::cpu::Cpu;
+use crate::state::StateError;
+use crate::CLOCK_FREQUENCY;
+
+// Keeps a bounded history of save states, taken every `interval` t-cycles of
+// emulated time, that a Cpu can be rewound through. Snapshots are stored
+// deflate compressed, since most of a save state is frame buffers and memory
+// that compress well.
+pub struct Rewind {
+    snapshots: VecDeque<Vec<u8>>,
+    depth: usize,
+    interval: u64,
+    cycles_since_snapshot: u64,
+}
+
+impl Default for Rewind {
+    // One snapshot per second of emulated time, going back up to a minute.
+    fn default() -> Self {
+        Self::new(60, u64::from(CLOCK_FREQUENCY))
+    }
+}
+
+impl Rewind {
+    // Fast compression, as snapshots are taken while the emulator is running.
+    const COMPRESSION_LEVEL: u8 = 1;
+
+    pub fn new(depth: usize, interval: u64) -> Self {
+        assert!(depth > 0, "rewind depth must be nonzero");
+        Self {
+            snapshots: VecDeque::with_capacity(depth),
+            depth,
+            interval,
+            cycles_since_snapshot: 0,
+        }
+    }
+
+    // Call with the t-cycles returned from each `fetch_decode_execute`. Takes a
+    // snapshot whenever another interval has elapsed.
+    pub fn record(&mut self, cpu: &Cpu, cycles: u32) {
+        self.cycles_since_snapshot += u64::from(cycles);
+        if self.cycles_since_snapshot >= self.interval {
+            self.push(cpu);
+        }
+    }
+
+    // Takes a snapshot right away, dropping the oldest one if the buffer is full.
+    pub fn push(&mut self, cpu: &Cpu) {
+        if self.snapshots.len() == self.depth {
+            self.snapshots.pop_front();
+        }
+
+        let state = cpu.save_state();
+        self.snapshots
+            .push_back(compress_to_vec(&state, Self::COMPRESSION_LEVEL));
+        self.cycles_since_snapshot = 0;
+    }
+
+    // Restores the most recent snapshot into `cpu` and removes it from the
+    // buffer, so that repeated calls step further back. Returns false if there
+    // is nothing left to rewind to. A snapshot that fails to load is kept.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Result<bool, StateError> {
+        let Some(snapshot) = self.snapshots.back() else {
+            return Ok(false);
+        };
+
+        let state = decompress_to_vec(snapshot).map_err(|_| StateError::Corrupt)?;
+        cpu.load_state(&state)?;
+        self.snapshots.pop_back();
+        self.cycles_since_snapshot = 0;
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.cycles_since_snapshot = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn test_step_back_keeps_failed_snapshot() {
+        let mut cpu = Cpu::new(Cartridge::new(&[0; 0x8000]).unwrap());
+        let mut rewind = Rewind::new(2, 1);
+        rewind.push(&cpu);
+        rewind.push(&cpu);
+
+        rewind.snapshots.back_mut().unwrap().truncate(4);
+        assert!(matches!(
+            rewind.step_back(&mut cpu),
+            Err(StateError::Corrupt)
+        ));
+        assert_eq!(rewind.len(), 2);
+
+        rewind.snapshots.pop_back();
+        assert!(matches!(rewind.step_back(&mut cpu), Ok(true)));
+        assert!(rewind.is_empty());
+        assert!(matches!(rewind.step_back(&mut cpu), Ok(false)));
+    }
+}
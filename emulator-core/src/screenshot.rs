@@ -0,0 +1,61 @@
+use std::io::Write;
+#[cfg(feature = "test-artifacts")]
+use std::path::Path;
+
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder, ImageError};
+
+#[cfg(feature = "test-artifacts")]
+use crate::cpu::Cpu;
+use crate::ppu::{PaletteColorRgb555, PPU_HEIGHT, PPU_WIDTH};
+
+// Encodes a PPU buffer as a 160x144 RGB PNG.
+pub fn buffer_to_png<W: Write>(
+    buffer: &[[PaletteColorRgb555; PPU_WIDTH]; PPU_HEIGHT],
+    writer: W,
+) -> Result<(), ImageError> {
+    let mut rgb = Vec::with_capacity(PPU_WIDTH * PPU_HEIGHT * 3);
+    for pixel in buffer.iter().flatten() {
+        rgb.extend_from_slice(&pixel.to_rgb888());
+    }
+
+    PngEncoder::new(writer).write_image(&rgb, PPU_WIDTH as u32, PPU_HEIGHT as u32, ColorType::Rgb8)
+}
+
+// Writes the current frame to `path` as a PNG, for inspecting frames from
+// failing regression tests.
+#[cfg(feature = "test-artifacts")]
+pub fn dump_frame_png(cpu: &Cpu, path: &Path) -> Result<(), ImageError> {
+    let file = std::fs::File::create(path).map_err(ImageError::IoError)?;
+    buffer_to_png(cpu.bus.ppu.get_buffer(), std::io::BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut buffer = [[PaletteColorRgb555::default(); PPU_WIDTH]; PPU_HEIGHT];
+        for (y, row) in buffer.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = PaletteColorRgb555 {
+                    red: (x % 32) as u8,
+                    green: (y % 32) as u8,
+                    blue: ((x + y) % 32) as u8,
+                };
+            }
+        }
+
+        let mut png = Vec::new();
+        buffer_to_png(&buffer, &mut png).unwrap();
+
+        let decoded = image::load_from_memory_with_format(&png, image::ImageFormat::Png)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(decoded.dimensions(), (160, 144));
+        assert_eq!(decoded.get_pixel(0, 0).0, [0x00, 0x00, 0x00]);
+        assert_eq!(decoded.get_pixel(31, 0).0, [0xFF, 0x00, 0xFF]);
+        assert_eq!(decoded.get_pixel(1, 2).0, [0x08, 0x10, 0x18]);
+    }
+}
@@ -1,20 +1,254 @@
-#[derive(Default, Clone)]
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// The other end of the link cable. `exchange` is called once per completed
+// transfer driven by our internal clock, with the byte shifted out, and
+// returns the byte shifted in from the peer.
+pub trait SerialLink {
+    fn exchange(&mut self, out: u8) -> u8;
+}
+
+// Lets the frontend keep a handle on a link device (e.g. to read back printed
+// images) after plugging it in.
+impl<T: SerialLink> SerialLink for Rc<RefCell<T>> {
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.borrow_mut().exchange(out)
+    }
+}
+
+// A link cable with nothing plugged in. The input line floats high, so every
+// transfer reads back 0xFF.
+pub struct NoPeer;
+
+impl SerialLink for NoPeer {
+    fn exchange(&mut self, _out: u8) -> u8 {
+        0xFF
+    }
+}
+
+fn default_link() -> Box<dyn SerialLink> {
+    Box::new(NoPeer)
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Serial {
     data_written: String,
+    data: u8,
+    control: u8,
+    transfer_ticks_left: u16,
+    interrupt_waiting: bool,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_link"))]
+    pub(crate) link: Box<dyn SerialLink>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) on_write: Option<Box<dyn FnMut(u8)>>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self {
+            data_written: String::new(),
+            data: 0,
+            control: 0,
+            transfer_ticks_left: 0,
+            interrupt_waiting: false,
+            link: default_link(),
+            on_write: None,
+        }
+    }
+}
+
+// The link and callback can't be cloned, so a cloned Serial starts out
+// unplugged and without a callback.
+impl Clone for Serial {
+    fn clone(&self) -> Self {
+        Self {
+            data_written: self.data_written.clone(),
+            data: self.data,
+            control: self.control,
+            transfer_ticks_left: self.transfer_ticks_left,
+            interrupt_waiting: self.interrupt_waiting,
+            link: default_link(),
+            on_write: None,
+        }
+    }
 }
 
 impl Serial {
+    const CONTROL_TRANSFER_ENABLE_MASK: u8 = 1 << 7;
+    const CONTROL_FAST_CLOCK_MASK: u8 = 1 << 1;
+    const CONTROL_INTERNAL_CLOCK_MASK: u8 = 1 << 0;
+
+    // 8 bits at 8192Hz, or 262144Hz with the CGB fast clock.
+    const TRANSFER_TICKS: u16 = 4096;
+    const FAST_TRANSFER_TICKS: u16 = 128;
+
+    pub fn set_link(&mut self, link: Box<dyn SerialLink>) {
+        self.link = link;
+    }
+
+    // Calls `on_write` with every byte the game writes to the serial data
+    // register, e.g. to echo test ROM output to stdout.
+    pub fn set_write_callback(&mut self, on_write: Box<dyn FnMut(u8)>) {
+        self.on_write = Some(on_write);
+    }
+
+    pub fn clear_write_callback(&mut self) {
+        self.on_write = None;
+    }
+
+    pub fn step(&mut self) {
+        if self.transfer_ticks_left > 0 {
+            self.transfer_ticks_left -= 1;
+            if self.transfer_ticks_left == 0 {
+                let received = self.link.exchange(self.data);
+                self.finish_transfer(received);
+            }
+        }
+    }
+
+    // Called by the peer when it clocks a transfer. If we're waiting on an
+    // external clock the transfer completes, otherwise the byte is shifted in
+    // without raising an interrupt. Returns the byte shifted out to the peer.
+    pub fn exchange_external(&mut self, received: u8) -> u8 {
+        let sent = self.data;
+        if self.transfer_pending() && !self.internal_clock() {
+            self.finish_transfer(received);
+        } else {
+            self.data = received;
+        }
+        sent
+    }
+
+    pub fn poll_interrupt(&mut self) -> bool {
+        if self.interrupt_waiting {
+            self.interrupt_waiting = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn finish_transfer(&mut self, received: u8) {
+        self.data = received;
+        self.control &= !Self::CONTROL_TRANSFER_ENABLE_MASK;
+        self.interrupt_waiting = true;
+    }
+
+    fn transfer_pending(&self) -> bool {
+        (self.control & Self::CONTROL_TRANSFER_ENABLE_MASK) != 0
+    }
+
+    fn internal_clock(&self) -> bool {
+        (self.control & Self::CONTROL_INTERNAL_CLOCK_MASK) != 0
+    }
+
     pub fn write_byte(&mut self, byte_written: u8) {
-        let char_written = char::from(byte_written);
-        self.data_written.push(char_written);
+        self.data_written.push(char::from(byte_written));
+        self.data = byte_written;
 
-        #[cfg(test)]
-        {
-            print!("{}", char_written);
+        if let Some(on_write) = &mut self.on_write {
+            on_write(byte_written);
         }
     }
 
+    pub fn read_byte(&self) -> u8 {
+        self.data
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.control = value
+            & (Self::CONTROL_TRANSFER_ENABLE_MASK
+                | Self::CONTROL_FAST_CLOCK_MASK
+                | Self::CONTROL_INTERNAL_CLOCK_MASK);
+
+        // Only the internal clock drives a transfer by itself. With an external
+        // clock we wait for the peer to call `exchange_external`.
+        self.transfer_ticks_left = if self.transfer_pending() && self.internal_clock() {
+            if (self.control & Self::CONTROL_FAST_CLOCK_MASK) != 0 {
+                Self::FAST_TRANSFER_TICKS
+            } else {
+                Self::TRANSFER_TICKS
+            }
+        } else {
+            0
+        };
+    }
+
+    pub fn read_control(&self) -> u8 {
+        self.control | 0b0111_1100
+    }
+
     pub fn get_data_written(&self) -> &str {
         self.data_written.as_str()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Cable(Rc<RefCell<Serial>>);
+
+    impl SerialLink for Cable {
+        fn exchange(&mut self, out: u8) -> u8 {
+            self.0.borrow_mut().exchange_external(out)
+        }
+    }
+
+    #[test]
+    fn test_linked_transfer() {
+        let slave = Rc::new(RefCell::new(Serial::default()));
+        let mut master = Serial::default();
+        master.set_link(Box::new(Cable(Rc::clone(&slave))));
+
+        slave.borrow_mut().write_byte(0x42);
+        slave.borrow_mut().write_control(0x80);
+        master.write_byte(0x99);
+        master.write_control(0x81);
+
+        for _ in 0..Serial::TRANSFER_TICKS {
+            assert_eq!(master.read_control() & 0x80, 0x80);
+            master.step();
+        }
+
+        assert_eq!(master.read_byte(), 0x42);
+        assert_eq!(master.read_control() & 0x80, 0);
+        assert!(master.poll_interrupt());
+
+        let mut slave = slave.borrow_mut();
+        assert_eq!(slave.read_byte(), 0x99);
+        assert_eq!(slave.read_control() & 0x80, 0);
+        assert!(slave.poll_interrupt());
+    }
+
+    #[test]
+    fn test_unlinked_transfer_reads_ff() {
+        let mut serial = Serial::default();
+        serial.write_byte(0x12);
+        serial.write_control(0x81);
+        for _ in 0..Serial::TRANSFER_TICKS {
+            serial.step();
+        }
+        assert_eq!(serial.read_byte(), 0xFF);
+        assert!(serial.poll_interrupt());
+    }
+
+    #[test]
+    fn test_write_callback() {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut bus = crate::bus::Bus::new(crate::cartridge::Cartridge::new(&[0; 0x8000]).unwrap());
+        let callback_written = Rc::clone(&written);
+        bus.serial.set_write_callback(Box::new(move |byte| {
+            callback_written.borrow_mut().push(byte)
+        }));
+
+        bus.write_byte_address(b'o', 0xFF01);
+        bus.reset(false);
+        bus.write_byte_address(b'k', 0xFF01);
+        assert_eq!(*written.borrow(), b"ok");
+
+        bus.serial.clear_write_callback();
+        bus.write_byte_address(b'!', 0xFF01);
+        assert_eq!(written.borrow().len(), 2);
+    }
+}
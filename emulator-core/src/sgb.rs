@@ -0,0 +1,189 @@
+use crate::ppu::PaletteColorRgb555;
+
+// Super Game Boy commands, which DMG games with the SGB flag set send by
+// pulsing the P14/P15 select lines of the joypad register. Only the palette
+// commands are decoded. Everything else, including MLT_REQ (which many games
+// use to detect an SGB before sending anything else) and the border transfers,
+// is received and ignored.
+//
+// Every packet starts with a reset pulse, where P14 and P15 are both low. Then
+// come 128 data bits, least significant bit of each byte first, where P14 low
+// sends a 0 and P15 low sends a 1, each followed by both lines going high.
+// A final 0 bit ends the packet. The low three bits of a command's first byte
+// give the number of packets it spans.
+const PACKET_LENGTH: usize = 16;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+
+const P14_MASK: u8 = 1 << 4;
+const P15_MASK: u8 = 1 << 5;
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sgb {
+    // Packets received so far for the current command.
+    packets: Vec<[u8; PACKET_LENGTH]>,
+    packet: [u8; PACKET_LENGTH],
+    // Number of bits received in the current packet, or None when waiting for
+    // a reset pulse.
+    bits_received: Option<usize>,
+    last_select: u8,
+    palettes: [[PaletteColorRgb555; 4]; 4],
+}
+
+impl Sgb {
+    // Handles a write to the joypad register. When a command changes the
+    // palette used for the screen, returns the new palette.
+    pub fn write(&mut self, value: u8) -> Option<[PaletteColorRgb555; 4]> {
+        let select = value & (P14_MASK | P15_MASK);
+        let last_select = std::mem::replace(&mut self.last_select, select);
+
+        let bit = match select {
+            0 => {
+                self.bits_received = Some(0);
+                self.packet = [0; PACKET_LENGTH];
+                return None;
+            }
+            // Bits only count when sent from the idle state, with both lines
+            // high.
+            _ if last_select != P14_MASK | P15_MASK => return None,
+            P15_MASK => false,
+            P14_MASK => true,
+            _ => return None,
+        };
+
+        let bits_received = self.bits_received?;
+        if bits_received < PACKET_LENGTH * 8 {
+            if bit {
+                self.packet[bits_received / 8] |= 1 << (bits_received % 8);
+            }
+            self.bits_received = Some(bits_received + 1);
+            None
+        } else {
+            self.bits_received = None;
+            // The stop bit must be a 0.
+            if bit {
+                return None;
+            }
+            self.receive_packet()
+        }
+    }
+
+    fn receive_packet(&mut self) -> Option<[PaletteColorRgb555; 4]> {
+        self.packets.push(self.packet);
+        let packet_count = usize::from(self.packets[0][0] & 0b111).max(1);
+        if self.packets.len() < packet_count {
+            return None;
+        }
+
+        let packets = std::mem::take(&mut self.packets);
+        let data = &packets[0];
+        let (first, second) = match data[0] >> 3 {
+            PAL01 => (0, 1),
+            PAL23 => (2, 3),
+            PAL03 => (0, 3),
+            PAL12 => (1, 2),
+            _ => return None,
+        };
+
+        let color = |index: usize| {
+            PaletteColorRgb555::from_rgb555(u16::from_le_bytes([
+                data[1 + index * 2],
+                data[2 + index * 2],
+            ]))
+        };
+
+        // Color 0 is shared by every palette.
+        let color_0 = color(0);
+        for palette in &mut self.palettes {
+            palette[0] = color_0;
+        }
+        for color_idx in 1..4 {
+            self.palettes[first][color_idx] = color(color_idx);
+            self.palettes[second][color_idx] = color(color_idx + 3);
+        }
+
+        // Without the attribute commands the whole screen uses palette 0.
+        Some(self.palettes[0])
+    }
+
+    pub fn palettes(&self) -> &[[PaletteColorRgb555; 4]; 4] {
+        &self.palettes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::Cartridge;
+    use crate::ppu::PpuMode;
+
+    // Returns the joypad register writes that send `packet`.
+    fn packet_writes(packet: [u8; PACKET_LENGTH]) -> Vec<u8> {
+        let mut writes = vec![0x00, 0x30];
+        for bit_idx in 0..=PACKET_LENGTH * 8 {
+            let bit =
+                bit_idx < PACKET_LENGTH * 8 && packet[bit_idx / 8] & (1 << (bit_idx % 8)) != 0;
+            writes.push(if bit { 0x10 } else { 0x20 });
+            writes.push(0x30);
+        }
+        writes
+    }
+
+    // PAL01, with color 0 white, palette 0 shades of red and palette 1 shades
+    // of blue.
+    const PAL01_PACKET: [u8; PACKET_LENGTH] = [
+        0x01, 0xFF, 0x7F, 0x1F, 0x00, 0x10, 0x00, 0x08, 0x00, 0x00, 0x7C, 0x00, 0x40, 0x00, 0x20,
+        0x00,
+    ];
+
+    #[test]
+    fn test_palette_command() {
+        let mut sgb = Sgb::default();
+        let mut palette = None;
+        for value in packet_writes(PAL01_PACKET) {
+            palette = palette.or(sgb.write(value));
+        }
+
+        let red = |red| PaletteColorRgb555 {
+            red,
+            green: 0,
+            blue: 0,
+        };
+        let white = PaletteColorRgb555::from_rgb555(0x7FFF);
+        assert_eq!(palette, Some([white, red(0x1F), red(0x10), red(0x08)]));
+        assert_eq!(sgb.palettes()[1][1].blue, 0x1F);
+        assert_eq!(sgb.palettes()[3][0], white);
+
+        // A packet without its stop bit is dropped.
+        let mut writes = packet_writes(PAL01_PACKET);
+        writes.truncate(writes.len() - 2);
+        let mut sgb = Sgb::default();
+        assert!(writes.into_iter().all(|value| sgb.write(value).is_none()));
+    }
+
+    #[test]
+    fn test_palette_command_on_bus() {
+        let mut rom = vec![0; 0x8000];
+        let send = |rom: &[u8]| {
+            let mut bus = Bus::new(Cartridge::new(rom).unwrap());
+            bus.ppu.set_ppu_mode(PpuMode::Dmg);
+            for value in packet_writes(PAL01_PACKET) {
+                bus.write_byte_address(value, 0xFF00);
+            }
+            bus.ppu.dmg_palette_override()
+        };
+
+        // Only cartridges with the SGB flag get SGB features.
+        assert_eq!(send(&rom), None);
+
+        rom[0x146] = 0x03;
+        let palettes = send(&rom).unwrap();
+        assert_eq!(palettes[0][1].red, 0x1F);
+        assert_eq!(palettes[0], palettes[2]);
+    }
+}
@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::fmt::Display;
+
+// Version of the save state format. Bump this whenever a change to any
+// serialized struct would make older snapshots decode incorrectly.
+pub(crate) const STATE_VERSION: u8 = 13;
+
+#[derive(Debug)]
+pub enum StateError {
+    Empty,
+    UnsupportedVersion(u8),
+    CartridgeMismatch,
+    // The compressed snapshot couldn't be inflated.
+    Corrupt,
+    Decode(bincode::Error),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::Empty => f.write_str("save state is empty"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported (expected {})",
+                version, STATE_VERSION
+            ),
+            StateError::CartridgeMismatch => {
+                f.write_str("save state was created with a different cartridge")
+            }
+            StateError::Corrupt => f.write_str("save state is corrupt"),
+            StateError::Decode(err) => write!(f, "failed to decode save state: {}", err),
+        }
+    }
+}
+
+impl Error for StateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StateError::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// Decodes data written by `bincode::serialize`. The data may come from a
+// corrupt or malicious file, so decoding is limited to the bytes actually
+// given, and a bad length can't make it allocate more than that.
+pub(crate) fn decode<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T, StateError> {
+    use bincode::Options;
+
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(data.len() as u64)
+        .deserialize(data)
+        .map_err(StateError::Decode)
+}
+
+// serde only implements Serialize/Deserialize for arrays of up to 32 elements,
+// so the larger arrays used for memory and frame buffers are (de)serialized as
+// flat sequences of their elements instead.
+
+pub(crate) mod boxed_array {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use std::ops::Deref;
+
+    pub fn serialize<S: Serializer, A: Deref<Target = [T; N]>, T: Serialize, const N: usize>(
+        value: &A,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(value.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<Box<[T; N]>, D::Error> {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let len = values.len();
+        values
+            .into_boxed_slice()
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &N.to_string().as_str()))
+    }
+}
+
+pub(crate) mod boxed_array_2d {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use std::ops::Deref;
+
+    pub fn serialize<
+        S: Serializer,
+        A: Deref<Target = [[T; N]; M]>,
+        T: Serialize,
+        const N: usize,
+        const M: usize,
+    >(
+        value: &A,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(value.iter().flatten())
+    }
+
+    pub fn deserialize<
+        'de,
+        D: Deserializer<'de>,
+        T: Deserialize<'de> + Copy,
+        const N: usize,
+        const M: usize,
+    >(
+        deserializer: D,
+    ) -> Result<Box<[[T; N]; M]>, D::Error> {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let len = values.len();
+        let invalid_length = || D::Error::invalid_length(len, &(N * M).to_string().as_str());
+
+        if len != N * M {
+            return Err(invalid_length());
+        }
+
+        values
+            .chunks_exact(N)
+            .map(|chunk| <[T; N]>::try_from(chunk).unwrap())
+            .collect::<Vec<_>>()
+            .into_boxed_slice()
+            .try_into()
+            .map_err(|_| invalid_length())
+    }
+}
+
+pub(crate) mod vec_of_arrays {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(
+        value: &[[T; N]],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(value.iter().flatten())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de> + Copy, const N: usize>(
+        deserializer: D,
+    ) -> Result<Vec<[T; N]>, D::Error> {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        if values.len() % N != 0 {
+            return Err(D::Error::invalid_length(
+                values.len(),
+                &format!("a multiple of {}", N).as_str(),
+            ));
+        }
+
+        Ok(values
+            .chunks_exact(N)
+            .map(|chunk| <[T; N]>::try_from(chunk).unwrap())
+            .collect())
+    }
+}
@@ -6,7 +6,17 @@ enum InputClockSelect {
     Bit9,
 }
 
+// The timer's registers as the CPU reads them, for debugger views.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerRegisters {
+    pub div: u8,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+}
+
 #[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Timer {
     pub timer_counter: u8,
     timer_counter_reload_delay: u8,
@@ -176,19 +186,89 @@ impl Timer {
         let new_timer_increment_bit = (self.tick_counter & new_input_clock_select_mask) != 0;
 
         if old_timer_increment_bit && !new_timer_increment_bit {
-            println!("increment from timer control change");
             let (new_timer_counter, overflow) = self.timer_counter.overflowing_add(1);
             if overflow {
                 self.timer_counter = self.timer_modulo;
                 self.interrupt_waiting = true;
-                println!("timer interrupt waiting");
             } else {
                 self.timer_counter = new_timer_counter;
             }
         }
     }
 
+    // Only the low three bits exist, the rest read back as 1.
     pub fn get_timer_control(&self) -> u8 {
-        self.timer_control
+        self.timer_control | 0b1111_1000
+    }
+
+    // DIV, 0xFF04. This is the top byte of the internal counter, so it
+    // increments every 256 t-cycles.
+    pub fn div(&self) -> u8 {
+        self.get_divider_register()
+    }
+
+    // TIMA, 0xFF05.
+    pub fn tima(&self) -> u8 {
+        self.get_timer_counter()
+    }
+
+    // TMA, 0xFF06.
+    pub fn tma(&self) -> u8 {
+        self.get_timer_modulo()
+    }
+
+    // TAC, 0xFF07.
+    pub fn tac(&self) -> u8 {
+        self.get_timer_control()
+    }
+
+    pub fn registers(&self) -> TimerRegisters {
+        TimerRegisters {
+            div: self.div(),
+            tima: self.tima(),
+            tma: self.tma(),
+            tac: self.tac(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div() {
+        let mut timer = Timer::default();
+        for _ in 0..(256 * 5 + 255) {
+            timer.step();
+        }
+        assert_eq!(timer.div(), 5);
+        timer.step();
+        assert_eq!(timer.div(), 6);
+
+        timer.set_divider_register(0x12);
+        assert_eq!(timer.div(), 0);
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut timer = Timer::default();
+        timer.set_timer_modulo(0xAB);
+        timer.set_timer_counter(0xFE);
+        // Enabled, incrementing every 16 t-cycles.
+        timer.set_timer_control(0b101);
+        for _ in 0..16 {
+            timer.step();
+        }
+
+        assert_eq!(
+            timer.registers(),
+            TimerRegisters {
+                div: 0,
+                tima: 0xFF,
+                tma: 0xAB,
+                tac: 0b1111_1101,
+            }
+        );
     }
 }
@@ -0,0 +1,128 @@
+use emulator_core::joypad::Button;
+
+use winit::event::VirtualKeyCode;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct KeyBindingConflict {
+    pub key: VirtualKeyCode,
+    pub buttons: [Button; 2],
+}
+
+impl fmt::Display for KeyBindingConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "key {:?} is bound to both {:?} and {:?}",
+            self.key, self.buttons[0], self.buttons[1]
+        )
+    }
+}
+
+impl Error for KeyBindingConflict {}
+
+// Maps keyboard keys to joypad buttons. Configs are written per button, e.g.
+//
+//  { A: [X], B: [Z, LShift], Start: [Return], ... }
+//
+// Buttons left out of a config keep their default keys. Any number of keys may
+// press the same button, but a key can only press one button.
+#[derive(Debug)]
+pub struct KeyBindings {
+    buttons: HashMap<VirtualKeyCode, Button>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::from_config(Self::default_config()).unwrap()
+    }
+}
+
+impl KeyBindings {
+    pub const CONFIG_FILENAME: &'static str = "keybindings.ron";
+
+    fn default_config() -> HashMap<Button, Vec<VirtualKeyCode>> {
+        HashMap::from([
+            (Button::B, vec![VirtualKeyCode::Z]),
+            (Button::A, vec![VirtualKeyCode::X]),
+            (Button::Select, vec![VirtualKeyCode::RShift]),
+            (Button::Start, vec![VirtualKeyCode::Return]),
+            (Button::Up, vec![VirtualKeyCode::Up]),
+            (Button::Right, vec![VirtualKeyCode::Right]),
+            (Button::Down, vec![VirtualKeyCode::Down]),
+            (Button::Left, vec![VirtualKeyCode::Left]),
+        ])
+    }
+
+    pub fn from_config(
+        config: HashMap<Button, Vec<VirtualKeyCode>>,
+    ) -> Result<Self, KeyBindingConflict> {
+        let mut buttons = HashMap::new();
+        for (button, keys) in config {
+            for key in keys {
+                if let Some(existing) = buttons.insert(key, button) {
+                    if existing != button {
+                        return Err(KeyBindingConflict {
+                            key,
+                            buttons: [existing, button],
+                        });
+                    }
+                }
+            }
+        }
+        Ok(Self { buttons })
+    }
+
+    pub fn parse(config: &str) -> Result<Self, Box<dyn Error>> {
+        let mut merged = Self::default_config();
+        merged.extend(ron::from_str::<HashMap<Button, Vec<VirtualKeyCode>>>(
+            config,
+        )?);
+        Ok(Self::from_config(merged)?)
+    }
+
+    // Loads the config next to the ROM, falling back to the defaults when
+    // there isn't one.
+    pub fn load_for_rom<P: AsRef<Path>>(rom_filename: P) -> Result<Self, Box<dyn Error>> {
+        let path = rom_filename.as_ref().with_file_name(Self::CONFIG_FILENAME);
+        match std::fs::read_to_string(&path) {
+            Ok(config) => Self::parse(&config)
+                .map_err(|e| format!("invalid key bindings in {}: {}", path.display(), e).into()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn button(&self, key: VirtualKeyCode) -> Option<Button> {
+        self.buttons.get(&key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_bindings() {
+        let bindings = KeyBindings::default();
+        assert_eq!(bindings.button(VirtualKeyCode::Z), Some(Button::B));
+        assert_eq!(bindings.button(VirtualKeyCode::X), Some(Button::A));
+        assert_eq!(bindings.button(VirtualKeyCode::Space), None);
+    }
+
+    #[test]
+    fn test_rebinding() {
+        let bindings = KeyBindings::parse("{ B: [Z, LShift], Start: [Space] }").unwrap();
+        assert_eq!(bindings.button(VirtualKeyCode::Z), Some(Button::B));
+        assert_eq!(bindings.button(VirtualKeyCode::LShift), Some(Button::B));
+        assert_eq!(bindings.button(VirtualKeyCode::Space), Some(Button::Start));
+        assert_eq!(bindings.button(VirtualKeyCode::Return), None);
+        assert_eq!(bindings.button(VirtualKeyCode::X), Some(Button::A));
+
+        assert!(KeyBindings::parse("{ A: [Z] }").is_err());
+    }
+}
@@ -1,54 +1,325 @@
+mod key_bindings;
+mod rom_loader;
 mod samples_queue;
+mod test_rom;
 
+use crate::key_bindings::KeyBindings;
 use crate::samples_queue::samples_queue;
 
+use emulator_core::apu::downmix_mono;
 use emulator_core::calculate_ppu_buffer_checksum;
 use emulator_core::cartridge::Cartridge;
 use emulator_core::cpu::Cpu;
-use emulator_core::joypad::Button;
+use emulator_core::joypad::{Button, JoypadState};
+use emulator_core::screenshot::buffer_to_png;
+use emulator_core::CLOCK_FREQUENCY;
 
+use gilrs::{Axis, EventType, Gilrs};
 use pixels::{wgpu::TextureFormat, PixelsBuilder, SurfaceTexture};
 use winit::dpi::LogicalSize;
-use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event::{
+    ElementState, Event, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent,
+};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::WindowBuilder;
+use winit::window::{Fullscreen, Window, WindowBuilder};
 
 use std::error::Error;
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::time::{Duration, Instant};
+use std::io::{BufWriter, Read, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const PPU_WIDTH: u16 = 160;
 const PPU_HEIGHT: u16 = 144;
 const DEFAULT_PIXEL_SCALE: u16 = 4;
 
-const CLOCK_FREQUENCY: u32 = 4_194_304;
+// Hold space to run at TURBO_SPEED times normal speed. Tab toggles uncapped
+// mode, which runs as fast as possible (also available with --uncapped, for
+// benchmarking). --speed sets a base multiplier that turbo stacks on top of.
+// Audio is muted whenever the effective speed isn't normal speed.
+const TURBO_SPEED: f64 = 4.0;
 const AUDIO_SAMPLE_FREQUENCY: u32 = 44_100;
+// Lower audio latency reduces the delay between input and sound, but leaves
+// less slack for the emulator to keep the queue filled, which causes audible
+// crackling on slower or heavily loaded machines. Higher values trade delay
+// for robustness against underruns.
+const DEFAULT_AUDIO_LATENCY_MS: u64 = 100;
+
+// The most emulated time the frontend will run in one go to catch up. Falling
+// further behind than this (e.g. while the window is being dragged, or the
+// machine was asleep) skips ahead instead of fast-forwarding through it.
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+// How long a status message (e.g. "saved state 1") stays in the title bar.
+const STATUS_MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+// Emulated time after `steps` t-cycles.
+fn emulated_time(steps: u64) -> Duration {
+    let clock_frequency = u64::from(CLOCK_FREQUENCY);
+    let seconds = steps / clock_frequency;
+    let nanos = (steps % clock_frequency) * 1_000_000_000 / clock_frequency;
+    Duration::new(seconds, nanos as u32)
+}
 
 fn get_save_filename<T: AsRef<str>>(rom_filename: T) -> String {
     format!("{}.save", rom_filename.as_ref())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = std::env::args().collect();
+fn get_state_filename<T: AsRef<str>>(rom_filename: T, slot: u8) -> String {
+    format!("{}.state{}", rom_filename.as_ref(), slot)
+}
+
+// Number keys 1 through 9 save to the matching slot, and load from it with
+// shift held.
+fn state_slot(keycode: VirtualKeyCode) -> Option<u8> {
+    match keycode {
+        VirtualKeyCode::Key1 => Some(1),
+        VirtualKeyCode::Key2 => Some(2),
+        VirtualKeyCode::Key3 => Some(3),
+        VirtualKeyCode::Key4 => Some(4),
+        VirtualKeyCode::Key5 => Some(5),
+        VirtualKeyCode::Key6 => Some(6),
+        VirtualKeyCode::Key7 => Some(7),
+        VirtualKeyCode::Key8 => Some(8),
+        VirtualKeyCode::Key9 => Some(9),
+        _ => None,
+    }
+}
+
+// Writes to a temporary file first and renames it into place, so a crash
+// mid-write never leaves a truncated file behind.
+fn write_file_atomically(filename: &str, data: &[u8]) -> std::io::Result<()> {
+    let temp_filename = format!("{}.tmp", filename);
+    let mut temp_file = File::create(&temp_filename)?;
+    temp_file.write_all(data)?;
+    temp_file.sync_all()?;
+    std::fs::rename(&temp_filename, filename)
+}
+
+fn load_state_file(cpu: &mut Cpu, filename: &str) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(filename)?;
+    cpu.load_state(&data)?;
+    Ok(())
+}
+
+// `--test-rom <rom> --frames <n> [--expect-hash <hash>]` runs the rom
+// headlessly and prints the hash of its last frame instead of opening a window,
+// for checking acceptance roms in CI.
+struct TestRomArgs {
+    rom_filename: String,
+    frames: String,
+    expect_hash: Option<String>,
+}
+
+enum Command {
+    Play(Args),
+    TestRom(TestRomArgs),
+}
+
+struct Args {
+    rom_filename: String,
+    scale_factor: Option<String>,
+    profile_out: Option<String>,
+    audio_latency_ms: Option<String>,
+    speed: Option<String>,
+    uncapped: bool,
+    mono: bool,
+}
+
+fn parse_args() -> Result<Command, Box<dyn Error>> {
+    let mut args = std::env::args();
+    let program_name = args.next().unwrap_or_default();
+    let usage = || {
+        format!(
+            "usage: ./{0} [--profile-out <path>] [--audio-latency-ms <ms>] [--speed <multiplier>] [--uncapped] [--mono] <rom_file> [scale_factor]\n       ./{0} --test-rom <rom_file> --frames <n> [--expect-hash <hash>]",
+            program_name
+        )
+    };
+
+    let mut positional = Vec::new();
+    let mut profile_out = None;
+    let mut audio_latency_ms = None;
+    let mut speed = None;
+    let mut uncapped = false;
+    let mut mono = false;
+    let mut test_rom = None;
+    let mut frames = None;
+    let mut expect_hash = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--test-rom" => test_rom = Some(args.next().ok_or_else(usage)?),
+            "--frames" => frames = Some(args.next().ok_or_else(usage)?),
+            "--expect-hash" => expect_hash = Some(args.next().ok_or_else(usage)?),
+            "--profile-out" => profile_out = Some(args.next().ok_or_else(usage)?),
+            "--audio-latency-ms" => audio_latency_ms = Some(args.next().ok_or_else(usage)?),
+            "--speed" => speed = Some(args.next().ok_or_else(usage)?),
+            "--uncapped" => uncapped = true,
+            "--mono" => mono = true,
+            _ if arg.starts_with("--") => return Err(usage().into()),
+            _ => positional.push(arg),
+        }
+    }
+
+    if let Some(rom_filename) = test_rom {
+        if !positional.is_empty() {
+            return Err(usage().into());
+        }
+        return Ok(Command::TestRom(TestRomArgs {
+            rom_filename,
+            frames: frames.ok_or_else(usage)?,
+            expect_hash,
+        }));
+    }
+
+    let headless_only = frames.is_some() || expect_hash.is_some();
+    if !(1..=2).contains(&positional.len()) || headless_only {
+        return Err(usage().into());
+    }
+    let mut positional = positional.into_iter();
+
+    Ok(Command::Play(Args {
+        rom_filename: positional.next().unwrap(),
+        scale_factor: positional.next(),
+        profile_out,
+        audio_latency_ms,
+        speed,
+        uncapped,
+        mono,
+    }))
+}
+
+// Stick deflection past which the left stick counts as a d-pad press.
+const STICK_THRESHOLD: f32 = 0.5;
+
+// The directions held on the gamepad d-pad and on the left stick, tracked
+// apart so that moving one doesn't release a direction held on the other.
+#[derive(Default)]
+struct GamepadDirections {
+    dpad: JoypadState,
+    stick: JoypadState,
+}
 
-    if args.len() != 2 && args.len() != 3 {
-        return Err(format!("usage: ./{} <rom_file> [scale_factor]", args[0]).into());
+impl GamepadDirections {
+    fn apply(&self, cpu: &mut Cpu, directions: &[Button]) {
+        for &direction in directions {
+            cpu.set_button_pressed(
+                direction,
+                self.dpad.is_pressed(direction) || self.stick.is_pressed(direction),
+            );
+        }
     }
-    let rom_filename = args[1].clone();
+}
+
+// Applies any gamepad input since the last call. gilrs reports pads connected
+// after launch through the same event queue, so hot-plugged pads just work.
+fn poll_gamepads(gilrs: &mut Gilrs, cpu: &mut Cpu, directions: &mut GamepadDirections) {
+    while let Some(event) = gilrs.next_event() {
+        match event.event {
+            EventType::ButtonPressed(button, _) | EventType::ButtonReleased(button, _) => {
+                let pressed = matches!(event.event, EventType::ButtonPressed(..));
+                let button = match button {
+                    gilrs::Button::East => Button::A,
+                    gilrs::Button::South => Button::B,
+                    gilrs::Button::Select => Button::Select,
+                    gilrs::Button::Start => Button::Start,
+                    gilrs::Button::DPadUp => Button::Up,
+                    gilrs::Button::DPadDown => Button::Down,
+                    gilrs::Button::DPadLeft => Button::Left,
+                    gilrs::Button::DPadRight => Button::Right,
+                    _ => continue,
+                };
+                if matches!(
+                    button,
+                    Button::Up | Button::Down | Button::Left | Button::Right
+                ) {
+                    directions.dpad = directions.dpad.with(button, pressed);
+                    directions.apply(cpu, &[button]);
+                } else {
+                    cpu.set_button_pressed(button, pressed);
+                }
+            }
+            EventType::AxisChanged(Axis::LeftStickX, value, _) => {
+                directions.stick = directions
+                    .stick
+                    .with(Button::Left, value < -STICK_THRESHOLD)
+                    .with(Button::Right, value > STICK_THRESHOLD);
+                directions.apply(cpu, &[Button::Left, Button::Right]);
+            }
+            EventType::AxisChanged(Axis::LeftStickY, value, _) => {
+                directions.stick = directions
+                    .stick
+                    .with(Button::Up, value > STICK_THRESHOLD)
+                    .with(Button::Down, value < -STICK_THRESHOLD);
+                directions.apply(cpu, &[Button::Up, Button::Down]);
+            }
+            EventType::Connected => println!("gamepad {} connected", event.id),
+            EventType::Disconnected => println!("gamepad {} disconnected", event.id),
+            _ => {}
+        }
+    }
+}
+
+// F11 switches between windowed and borderless fullscreen on the current
+// monitor.
+fn toggle_fullscreen(window: &Window) {
+    let fullscreen = match window.fullscreen() {
+        Some(_) => None,
+        None => Some(Fullscreen::Borderless(None)),
+    };
+    window.set_fullscreen(fullscreen);
+}
+
+fn write_profile_report(cpu: &Cpu, filename: &str) -> std::io::Result<()> {
+    let mut profile_file = File::create(filename)?;
+    for entry in cpu.profile_report() {
+        let example = entry.example.to_string();
+        let mnemonic = example.split_whitespace().next().unwrap_or_default();
+        writeln!(
+            profile_file,
+            "{:>12}  0x{:02X}  {:<6} (e.g. {})",
+            entry.count, entry.opcode, mnemonic, example
+        )?;
+    }
+    profile_file.flush()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = match parse_args()? {
+        Command::Play(args) => args,
+        Command::TestRom(args) => {
+            let expect_hash = args
+                .expect_hash
+                .as_deref()
+                .map(test_rom::parse_hash)
+                .transpose()?;
+            return test_rom::run(&args.rom_filename, args.frames.parse()?, expect_hash);
+        }
+    };
+
+    let rom_filename = args.rom_filename;
     println!("playing from rom: {}", rom_filename);
 
-    let mut rom_data = Vec::new();
-    File::open(&rom_filename)?.read_to_end(&mut rom_data)?;
+    let rom_data = rom_loader::load_rom(&rom_filename)?;
 
     println!("cpu size: {}", std::mem::size_of::<Cpu>());
     let cartridge = Cartridge::new(&rom_data)?;
+    if let Err(e) = cartridge.check_header_checksum() {
+        println!(
+            "warning: {}, real hardware would refuse to boot this rom",
+            e
+        );
+    }
     let mut cpu = Cpu::new(cartridge);
+    cpu.bus.apu.set_sample_rate(AUDIO_SAMPLE_FREQUENCY)?;
+    cpu.set_profiling_enabled(args.profile_out.is_some());
 
-    let save_filename = get_save_filename(rom_filename);
+    let key_bindings = KeyBindings::load_for_rom(&rom_filename)?;
+
+    let save_filename = get_save_filename(&rom_filename);
     println!("attempting to load save from: {}", save_filename);
 
-    if let Ok(mut save_file) = File::open(&save_filename) {
+    if !cpu.bus.cartridge.has_battery() {
+        println!("cartridge has no battery, so nothing will be saved");
+    } else if let Ok(mut save_file) = File::open(&save_filename) {
         let mut save_data = Vec::new();
         save_file.read_to_end(&mut save_data)?;
         if cpu.bus.cartridge.write_save_data(&save_data) {
@@ -61,11 +332,26 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     let scale_factor = args
-        .get(2)
+        .scale_factor
         .map(|scale_str| scale_str.parse())
         .transpose()?
         .unwrap_or(DEFAULT_PIXEL_SCALE);
 
+    let audio_latency_ms = args
+        .audio_latency_ms
+        .map(|latency_str| latency_str.parse())
+        .transpose()?
+        .unwrap_or(DEFAULT_AUDIO_LATENCY_MS);
+
+    let base_speed: f64 = args
+        .speed
+        .map(|speed_str| speed_str.parse())
+        .transpose()?
+        .unwrap_or(1.0);
+    if !base_speed.is_finite() || base_speed <= 0.0 {
+        return Err(format!("invalid speed multiplier: {}", base_speed).into());
+    }
+
     let event_loop = EventLoop::new();
     let window = {
         let size = LogicalSize::new(PPU_WIDTH * scale_factor, PPU_HEIGHT * scale_factor);
@@ -87,12 +373,33 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let (_stream, stream_handle) = rodio::OutputStream::try_default()?;
 
-    let (samples_input, samples_output) = samples_queue(2, AUDIO_SAMPLE_FREQUENCY);
+    // --mono opens a single channel stream, for output devices without stereo.
+    let mono = args.mono;
+    let (samples_input, samples_output) = samples_queue(
+        if mono { 1 } else { 2 },
+        AUDIO_SAMPLE_FREQUENCY,
+        Duration::from_millis(audio_latency_ms),
+    );
     stream_handle.play_raw(samples_output)?;
 
-    let emulation_start = Instant::now();
+    // Amount of emulated time that should have passed by now. This advances
+    // with wall-clock time, scaled by the current speed.
+    let mut target_emulation_time = Duration::ZERO;
+    let mut last_update = Instant::now();
     let mut emulation_steps = 0;
-    let mut audio_steps = 0;
+    let mut turbo = false;
+    let mut uncapped = args.uncapped;
+    // P pauses, and period then advances a single frame at a time. Audio is
+    // muted while paused.
+    let mut paused = false;
+
+    let mut gilrs = Gilrs::new()
+        .map_err(|e| println!("gamepad support unavailable: {}", e))
+        .ok();
+    let mut gamepad_directions = GamepadDirections::default();
+
+    let mut modifiers = ModifiersState::empty();
+    let mut status_message: Option<(String, Instant)> = None;
 
     let mut last_fps_calculation = Instant::now();
     let mut frames_since_fps_calculation = 0;
@@ -100,46 +407,53 @@ fn main() -> Result<(), Box<dyn Error>> {
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::MainEventsCleared => {
-                if cpu.bus.ppu.get_lcd_ppu_enable() {
-                    let ppu_buffer = cpu.bus.ppu.get_buffer();
-                    for (pixel_idx, pixel) in pixels.frame_mut().chunks_exact_mut(4).enumerate() {
-                        let ppu_pixel_x = pixel_idx % usize::from(PPU_WIDTH);
-                        let ppu_pixel_y = pixel_idx / usize::from(PPU_WIDTH);
-
-                        let pixel_color = ppu_buffer[ppu_pixel_y][ppu_pixel_x];
-                        let pixel_red = (pixel_color.red << 3) | (pixel_color.red >> 2);
-                        let pixel_green = (pixel_color.green << 3) | (pixel_color.green >> 2);
-                        let pixel_blue = (pixel_color.blue << 3) | (pixel_color.blue >> 2);
-
-                        let pixel_rgba = [pixel_red, pixel_green, pixel_blue, 255];
-                        pixel.copy_from_slice(&pixel_rgba);
-                    }
+                cpu.bus.ppu.fill_rgba8(pixels.frame_mut());
+                pixels.render().expect("failed to render frame");
 
-                    pixels.render().expect("failed to render frame");
+                if let Some(gilrs) = &mut gilrs {
+                    poll_gamepads(gilrs, &mut cpu, &mut gamepad_directions);
                 }
 
-                // Run the CPU until we have caught up to the proper step.
-                while emulation_start.elapsed()
-                    >= Duration::from_nanos(
-                        1_000_000_000 * emulation_steps / u64::from(CLOCK_FREQUENCY),
-                    )
-                {
-                    let steps_executed = cpu.fetch_decode_execute();
-
-                    // While number of cycles for which we have played audio is less than the
-                    // number of cpu cycles actually run, take another sound sample.
-                    //
-                    // This while loop should never add two samples inside of a single cpu cycle,
-                    // unless the audio sample rate is somehow higher than the cpu frequency.
-                    while (audio_steps * u64::from(CLOCK_FREQUENCY)
-                        / u64::from(AUDIO_SAMPLE_FREQUENCY))
-                        < emulation_steps
-                    {
-                        samples_input.append(cpu.bus.apu.sample());
-                        audio_steps += 1;
+                let now = Instant::now();
+                let speed = if turbo {
+                    base_speed * TURBO_SPEED
+                } else {
+                    base_speed
+                };
+                target_emulation_time += (now - last_update).mul_f64(speed);
+                last_update = now;
+
+                if paused {
+                    // Hold the clock still, so that resuming doesn't try to
+                    // catch up on the time spent paused.
+                    target_emulation_time = emulated_time(emulation_steps);
+                } else if uncapped {
+                    emulation_steps += cpu.step_frame();
+                    target_emulation_time = emulated_time(emulation_steps);
+                } else {
+                    let behind =
+                        target_emulation_time.saturating_sub(emulated_time(emulation_steps));
+                    if behind > MAX_CATCH_UP {
+                        target_emulation_time = emulated_time(emulation_steps);
                     }
 
-                    emulation_steps += u64::from(steps_executed);
+                    // Run the CPU until we have caught up to the proper step.
+                    while target_emulation_time >= emulated_time(emulation_steps) {
+                        let steps_executed = cpu.fetch_decode_execute();
+                        emulation_steps += u64::from(steps_executed);
+                    }
+                }
+
+                // Samples are produced faster (or slower) than they can be
+                // played back while running at any other speed, so drop them
+                // instead of letting the queue back up or underrun.
+                let samples = cpu.bus.apu.take_samples();
+                if speed == 1.0 && !uncapped && !paused {
+                    if mono {
+                        samples_input.append(samples.into_iter().map(downmix_mono));
+                    } else {
+                        samples_input.append(samples.into_iter().flatten());
+                    }
                 }
 
                 frames_since_fps_calculation += 1;
@@ -148,7 +462,20 @@ fn main() -> Result<(), Box<dyn Error>> {
                 if time_since_fps_calculation.as_secs() >= 1 {
                     let fps = 1_000_000_000 * frames_since_fps_calculation
                         / time_since_fps_calculation.as_nanos();
-                    window.set_title(format!("FPS: {:03}", fps).as_str());
+                    match &status_message {
+                        Some((message, shown_at))
+                            if shown_at.elapsed() < STATUS_MESSAGE_DURATION =>
+                        {
+                            window.set_title(format!("FPS: {:03} - {}", fps, message).as_str())
+                        }
+                        _ if paused => {
+                            window.set_title(format!("FPS: {:03} - paused", fps).as_str())
+                        }
+                        _ if cpu.is_hung() => window.set_title(
+                            format!("FPS: {:03} - cpu hung on an illegal opcode", fps).as_str(),
+                        ),
+                        _ => window.set_title(format!("FPS: {:03}", fps).as_str()),
+                    }
                     frames_since_fps_calculation = 0;
                     last_fps_calculation = Instant::now();
                 }
@@ -158,13 +485,21 @@ fn main() -> Result<(), Box<dyn Error>> {
                 window_id,
                 ..
             } if window_id == window.id() => *control_flow = ControlFlow::Exit,
+            // pixels scales the frame by the largest whole factor that fits and
+            // letterboxes the rest, so the image keeps its 160:144 aspect ratio
+            // at any window size. Minimized windows report a zero size, which
+            // the surface can't take.
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 window_id,
                 ..
-            } if window_id == window.id() => {
+            } if window_id == window.id() && size.width > 0 && size.height > 0 => {
                 pixels.resize_surface(size.width, size.height).unwrap()
             }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(new_modifiers),
+                window_id,
+            } if window_id == window.id() => modifiers = new_modifiers,
             Event::WindowEvent {
                 event:
                     WindowEvent::KeyboardInput {
@@ -182,41 +517,92 @@ fn main() -> Result<(), Box<dyn Error>> {
                     ElementState::Pressed => true,
                     ElementState::Released => false,
                 };
+                if let Some(button) = key_bindings.button(keycode) {
+                    cpu.set_button_pressed(button, pressed);
+                    return;
+                }
+                if let Some(slot) = state_slot(keycode).filter(|_| pressed) {
+                    let state_filename = get_state_filename(&rom_filename, slot);
+                    let message = if modifiers.shift() {
+                        match load_state_file(&mut cpu, &state_filename) {
+                            Ok(()) => format!("loaded state {}", slot),
+                            Err(e) => format!("failed to load state {}: {}", slot, e),
+                        }
+                    } else {
+                        match write_file_atomically(&state_filename, &cpu.save_state()) {
+                            Ok(()) => format!("saved state {}", slot),
+                            Err(e) => format!("failed to save state {}: {}", slot, e),
+                        }
+                    };
+                    println!("{}", message);
+                    window.set_title(&message);
+                    status_message = Some((message, Instant::now()));
+                    return;
+                }
                 match keycode {
-                    VirtualKeyCode::Z => cpu.set_button_pressed(Button::B, pressed),
-                    VirtualKeyCode::X => cpu.set_button_pressed(Button::A, pressed),
-                    VirtualKeyCode::RShift => cpu.set_button_pressed(Button::Select, pressed),
-                    VirtualKeyCode::Return => cpu.set_button_pressed(Button::Start, pressed),
-                    VirtualKeyCode::Up => cpu.set_button_pressed(Button::Up, pressed),
-                    VirtualKeyCode::Right => cpu.set_button_pressed(Button::Right, pressed),
-                    VirtualKeyCode::Down => cpu.set_button_pressed(Button::Down, pressed),
-                    VirtualKeyCode::Left => cpu.set_button_pressed(Button::Left, pressed),
+                    VirtualKeyCode::Space => turbo = pressed,
+                    VirtualKeyCode::Tab if pressed => uncapped = !uncapped,
+                    VirtualKeyCode::F11 if pressed => toggle_fullscreen(&window),
+                    VirtualKeyCode::P if pressed => {
+                        paused = !paused;
+                        let message = if paused { "paused" } else { "resumed" };
+                        window.set_title(message);
+                        status_message = Some((message.to_string(), Instant::now()));
+                    }
+                    VirtualKeyCode::Period if pressed && paused => {
+                        emulation_steps += cpu.step_frame();
+                    }
                     VirtualKeyCode::H if pressed => {
                         println!(
                             "current checksum: 0x{:08X}",
                             calculate_ppu_buffer_checksum(&cpu)
                         )
                     }
+                    VirtualKeyCode::F12 if pressed => {
+                        let timestamp = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs();
+                        let filename = format!("screenshot-{}.png", timestamp);
+                        let result = File::create(&filename)
+                            .map(BufWriter::new)
+                            .map_err(|e| e.into())
+                            .and_then(|file| buffer_to_png(cpu.bus.ppu.get_buffer(), file));
+                        match result {
+                            Ok(()) => println!("saved screenshot to {}", filename),
+                            Err(e) => println!("failed to save screenshot: {}", e),
+                        }
+                    }
                     _ => {}
                 };
             }
             Event::LoopDestroyed => {
-                let mut save_file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .truncate(true)
-                    .open(&save_filename)
-                    .unwrap_or_else(|_| panic!("failed to open save file: {}", save_filename));
-                let save_data = cpu.bus.cartridge.read_save_data();
-
-                save_file.write_all(&save_data).unwrap_or_else(|_| {
-                    panic!("failed to write save data to save file: {}", save_filename)
-                });
-                save_file.flush().unwrap_or_else(|_| {
-                    panic!("failed to flush save data to save file: {}", save_filename)
-                });
-
-                println!("wrote save file to {}", save_filename);
+                // Only battery backed RAM is saved, and only if it has changed.
+                if cpu.bus.cartridge.has_battery() && cpu.bus.cartridge.is_ram_dirty() {
+                    let mut save_file = OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .truncate(true)
+                        .open(&save_filename)
+                        .unwrap_or_else(|_| panic!("failed to open save file: {}", save_filename));
+                    let save_data = cpu.bus.cartridge.read_save_data();
+
+                    save_file.write_all(&save_data).unwrap_or_else(|_| {
+                        panic!("failed to write save data to save file: {}", save_filename)
+                    });
+                    save_file.flush().unwrap_or_else(|_| {
+                        panic!("failed to flush save data to save file: {}", save_filename)
+                    });
+
+                    println!("wrote save file to {}", save_filename);
+                }
+
+                if let Some(profile_filename) = &args.profile_out {
+                    write_profile_report(&cpu, profile_filename).unwrap_or_else(|_| {
+                        panic!("failed to write profile report to: {}", profile_filename)
+                    });
+                    println!("wrote profile report to {}", profile_filename);
+                }
             }
             _ => {}
         };
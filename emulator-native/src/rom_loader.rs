@@ -0,0 +1,99 @@
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use std::error::Error;
+use std::fmt::Display;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+#[derive(Debug)]
+pub struct NoRomInArchive;
+
+impl Display for NoRomInArchive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "zip archive has no .gb or .gbc file")
+    }
+}
+
+impl Error for NoRomInArchive {}
+
+// Reads the rom at `path`, which may be gzipped or inside a zip archive.
+pub fn load_rom<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Box<dyn Error>> {
+    decompress_rom(std::fs::read(path)?)
+}
+
+// Compression is detected from the data itself rather than the file
+// extension. Anything that isn't gzip or zip is returned as is.
+pub fn decompress_rom(data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    if data.starts_with(&GZIP_MAGIC) {
+        let mut rom = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut rom)?;
+        Ok(rom)
+    } else if data.starts_with(&ZIP_MAGIC) {
+        unzip_rom(data)
+    } else {
+        Ok(data)
+    }
+}
+
+// Takes the first .gb or .gbc entry, or the only entry if there's just one.
+fn unzip_rom(data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let is_rom = |name: &str| {
+        let name = name.to_ascii_lowercase();
+        name.ends_with(".gb") || name.ends_with(".gbc")
+    };
+    let index = match archive.file_names().position(is_rom) {
+        Some(index) => index,
+        None if archive.len() == 1 => 0,
+        None => return Err(Box::new(NoRomInArchive)),
+    };
+
+    let mut rom = Vec::new();
+    archive.by_index(index)?.read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator_core::cartridge::Cartridge;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    const ROM: &[u8] = include_bytes!("../../emulator-core/tests/01_special.gb");
+
+    #[test]
+    fn test_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(ROM).unwrap();
+        let rom = decompress_rom(encoder.finish().unwrap()).unwrap();
+        assert_eq!(rom, ROM);
+        assert!(Cartridge::new(&rom).is_ok());
+
+        assert_eq!(decompress_rom(ROM.to_vec()).unwrap(), ROM);
+    }
+
+    #[test]
+    fn test_zip() {
+        let zip = |entries: &[(&str, &[u8])]| {
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            for (name, data) in entries {
+                writer.start_file(*name, FileOptions::default()).unwrap();
+                writer.write_all(data).unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+
+        let rom = decompress_rom(zip(&[("readme.txt", b"hi"), ("Game.GB", ROM)])).unwrap();
+        assert_eq!(rom, ROM);
+        assert!(Cartridge::new(&rom).is_ok());
+
+        assert_eq!(decompress_rom(zip(&[("game.bin", ROM)])).unwrap(), ROM);
+        assert!(decompress_rom(zip(&[("a.txt", b"a"), ("b.txt", b"b")])).is_err());
+    }
+}
@@ -4,9 +4,13 @@ use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+// Creates a queue of samples to be played. At most `max_latency` worth of
+// samples are kept queued; when the producer gets further ahead than this, the
+// oldest samples are dropped so playback never lags too far behind emulation.
 pub fn samples_queue<S: Sample>(
     channels: u16,
     sample_rate: u32,
+    max_latency: Duration,
 ) -> (SamplesQueueInput<S>, SamplesQueueOutput<S>) {
     let samples = Arc::default();
 
@@ -19,7 +23,10 @@ pub fn samples_queue<S: Sample>(
         last_output: S::zero_value(),
         channels,
         sample_rate,
-        max_queued_samples: usize::try_from(sample_rate).unwrap() / 10,
+        max_queued_samples: usize::try_from(
+            u128::from(sample_rate) * max_latency.as_millis() / 1000,
+        )
+        .unwrap(),
     };
     (input, output)
 }
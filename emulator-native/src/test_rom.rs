@@ -0,0 +1,88 @@
+use emulator_core::headless::HeadlessRunner;
+
+use std::error::Error;
+use std::fmt::Display;
+use std::num::ParseIntError;
+
+use crate::rom_loader;
+
+#[derive(Debug)]
+pub struct HashMismatch {
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl Display for HashMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame hash 0x{:08X} does not match the expected 0x{:08X}",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl Error for HashMismatch {}
+
+// Runs the rom for `frames` frames without a window or audio device, and
+// returns the checksum of the last frame, see `calculate_ppu_buffer_checksum`.
+pub fn frame_hash(rom: &[u8], frames: u64) -> Result<u32, Box<dyn Error>> {
+    let mut runner = HeadlessRunner::new(rom)?;
+    runner.run_frames(frames);
+    Ok(runner.ppu_checksum())
+}
+
+// Hashes are given in hex, with or without a 0x prefix.
+pub fn parse_hash(hash: &str) -> Result<u32, ParseIntError> {
+    let digits = hash
+        .strip_prefix("0x")
+        .or_else(|| hash.strip_prefix("0X"))
+        .unwrap_or(hash);
+    u32::from_str_radix(digits, 16)
+}
+
+// Prints the frame hash, and fails if it isn't `expected_hash`, so that CI can
+// check acceptance roms through the exit status.
+pub fn run(
+    rom_filename: &str,
+    frames: u64,
+    expected_hash: Option<u32>,
+) -> Result<(), Box<dyn Error>> {
+    let hash = frame_hash(&rom_loader::load_rom(rom_filename)?, frames)?;
+    println!("0x{:08X}", hash);
+
+    match expected_hash {
+        Some(expected) if expected != hash => Err(Box::new(HashMismatch {
+            expected,
+            actual: hash,
+        })),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_hash() {
+        let rom_filename = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../emulator-core/tests/dmg_acid2.gb"
+        );
+        let rom = std::fs::read(rom_filename).unwrap();
+        assert_eq!(frame_hash(&rom, 300).unwrap(), 0x9B78469A);
+
+        assert!(run(rom_filename, 300, Some(0x9B78469A)).is_ok());
+        assert!(run(rom_filename, 300, None).is_ok());
+        let err = run(rom_filename, 300, Some(0x12345678)).unwrap_err();
+        assert!(err.is::<HashMismatch>());
+    }
+
+    #[test]
+    fn test_parse_hash() {
+        assert_eq!(parse_hash("0x1234ABCD"), Ok(0x1234ABCD));
+        assert_eq!(parse_hash("1234abcd"), Ok(0x1234ABCD));
+        assert!(parse_hash("0xG").is_err());
+    }
+}